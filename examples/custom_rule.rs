@@ -0,0 +1,42 @@
+//! Installs a house rule next to the engine's own checks: a withdrawal taking more than
+//! half of the client's available funds is held for review instead of being applied.
+//!
+//! ```sh
+//! cargo run --example custom_rule
+//! ```
+use anyhow::Result;
+use mini_payments_engine::{example, prelude::*};
+use std::io::stdout;
+
+#[derive(Debug)]
+struct LargeWithdrawals;
+
+impl Rule for LargeWithdrawals {
+    fn holds(&self, ledger: &Ledger, tx: &TransactionState) -> bool {
+        let (TransactionType::Withdrawal, Some(amount)) = (&tx.tx_type, tx.amount) else {
+            return false;
+        };
+        ledger
+            .account(tx.client)
+            .is_some_and(|account| amount + amount > account.available_funds)
+    }
+}
+
+fn main() -> Result<()> {
+    let mut ledger = Ledger::builder().rule(LargeWithdrawals).build();
+
+    for transaction in example::transactions() {
+        let tx = transaction.tx;
+        if let Err(err) = ledger.process_transaction(transaction) {
+            eprintln!("rejected {tx}: {err}");
+        }
+    }
+
+    for transaction in ledger.quarantine() {
+        eprintln!(
+            "held for review: {} {}",
+            transaction.tx_type, transaction.tx
+        );
+    }
+    ledger.write_report(stdout())
+}
@@ -0,0 +1,21 @@
+//! Runs the example dataset through a ledger held in memory and prints the account
+//! report, the way an application embedding the engine would.
+//!
+//! ```sh
+//! cargo run --example embed
+//! ```
+use anyhow::Result;
+use mini_payments_engine::{example, prelude::Ledger};
+use std::io::stdout;
+
+fn main() -> Result<()> {
+    let mut ledger = Ledger::new();
+    for transaction in example::transactions() {
+        let (tx_type, tx) = (transaction.tx_type.clone(), transaction.tx);
+        if let Err(err) = ledger.process_transaction(transaction) {
+            eprintln!("rejected {tx_type} {tx}: {err}");
+        }
+    }
+
+    ledger.write_report(stdout())
+}
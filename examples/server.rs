@@ -0,0 +1,31 @@
+//! Serves the ledger as a `tower::Service` and sends the example dataset through it.
+//! Every call is queued before any response is awaited, the way concurrent requests
+//! would reach a server, and the worker still applies them in the order they were made.
+//!
+//! ```sh
+//! cargo run --example server --features tower
+//! ```
+use anyhow::Result;
+use mini_payments_engine::{example, prelude::Ledger, service::LedgerService};
+use std::{future::poll_fn, io::stdout};
+use tower::Service;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let (mut service, worker) = LedgerService::new(Ledger::new(), 64);
+
+    let mut responses = Vec::new();
+    for transaction in example::transactions() {
+        poll_fn(|cx| service.poll_ready(cx)).await?;
+        let tx = transaction.tx;
+        responses.push((tx, tokio::spawn(service.call(transaction))));
+    }
+    for (tx, response) in responses {
+        if let Err(err) = response.await? {
+            eprintln!("rejected {tx}: {err}");
+        }
+    }
+
+    drop(service);
+    worker.await?.write_report(stdout())
+}
@@ -1,26 +1,144 @@
-use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
+use crate::{
+    amount::{self, Amount},
+    transaction::TransactionType,
+};
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+use std::{fmt, time::SystemTime};
 use thiserror::Error;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    ///The account accepts every transaction type
+    Active,
+
+    ///The account is locked after a chargeback and rejects every transaction
+    Frozen,
+
+    ///Temporary risk hold. The client cannot move money in or out, but disputes raised
+    ///against earlier transactions still run their course
+    Suspended,
+
+    ///The account has been closed for good and rejects every transaction
+    Closed,
+
+    ///The account is being looked at. Funds can come in and disputes run as usual,
+    ///but nothing can be withdrawn
+    UnderReview,
+}
+
+impl AccountStatus {
+    /// Whether an account in this status may move to `next`.
+    pub fn can_transition_to(self, next: AccountStatus) -> bool {
+        use AccountStatus::*;
+
+        matches!(
+            (self, next),
+            (Active, Frozen | Suspended | Closed | UnderReview)
+                | (Suspended, Active | Frozen | Closed | UnderReview)
+                | (UnderReview, Active | Frozen | Suspended | Closed)
+                | (Frozen, UnderReview | Closed)
+        )
+    }
+
+    /// Whether an account in this status accepts a transaction of the given type.
+    pub fn permits(self, tx_type: &TransactionType) -> bool {
+        match self {
+            AccountStatus::Active => true,
+            AccountStatus::Frozen | AccountStatus::Closed => false,
+            AccountStatus::Suspended => !matches!(
+                tx_type,
+                TransactionType::Deposit | TransactionType::Withdrawal
+            ),
+            AccountStatus::UnderReview => *tx_type != TransactionType::Withdrawal,
+        }
+    }
+}
+
+impl fmt::Display for AccountStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Frozen => "frozen",
+            AccountStatus::Suspended => "suspended",
+            AccountStatus::Closed => "closed",
+            AccountStatus::UnderReview => "under_review",
+        };
+        f.write_str(status)
+    }
+}
+
+/// What froze an account
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LockReason {
+    ///A chargeback, including one the held funds could not cover
+    Chargeback,
+
+    ///The handler of a custom transaction type, by type name
+    Custom(String),
+}
+
+impl fmt::Display for LockReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockReason::Chargeback => f.write_str("chargeback"),
+            LockReason::Custom(name) => f.write_str(name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Account {
     /// Client id
     pub client_id: u16,
 
     ///The total funds that are available for trading, staking, withdrawal, etc.
     ///This should be equal to the total - held amounts
-    pub available_funds: Decimal,
+    pub available_funds: Amount,
 
     ///The total funds that are held for dispute. This should be equal to
     ///total - available amounts
-    pub held_funds: Decimal,
+    pub held_funds: Amount,
 
     /// The total funds that are available or held. This should be equal to available
     /// + held
-    pub total_funds: Decimal,
+    pub total_funds: Amount,
+
+    ///Where the account is in its lifecycle. An account is frozen if a charge back occurs
+    pub status: AccountStatus,
+
+    ///When a suspension ends and the account goes back to active
+    #[serde(skip)]
+    pub suspended_until: Option<SystemTime>,
+
+    ///Bumped on every balance change, starting at 1 for the deposit that opened the
+    ///account, so consumers of successive reports can tell when they missed an update
+    #[serde(default)]
+    pub sequence: u64,
+
+    ///Why the account was frozen, kept through a review until it is unlocked or closed
+    #[serde(default)]
+    pub locked_reason: Option<LockReason>,
 
-    ///Whether the account is locked. An account is locked if a charge back occurs
-    pub locked: bool,
+    ///The transaction that froze the account, kept as long as the reason is
+    #[serde(default)]
+    pub locked_by_tx: Option<u32>,
+}
+
+impl Serialize for Account {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // `locked` is kept in the report for consumers that predate `status`
+        let mut state = serializer.serialize_struct("Account", 7)?;
+        state.serialize_field("client_id", &self.client_id)?;
+        state.serialize_field("available_funds", &self.available_funds)?;
+        state.serialize_field("held_funds", &self.held_funds)?;
+        state.serialize_field("total_funds", &self.total_funds)?;
+        state.serialize_field("locked", &self.is_locked())?;
+        state.serialize_field("status", &self.status)?;
+        state.serialize_field("sequence", &self.sequence)?;
+        state.end()
+    }
 }
 
 #[derive(Debug, Error)]
@@ -29,97 +147,217 @@ pub enum AccountError {
     AccountLocked(u16),
 
     #[error("Not Enough Funds in Account {0} to withdraw {1} units")]
-    NotEnoughFunds(u16, Decimal),
+    NotEnoughFunds(u16, Amount),
+
+    #[error("Account {0} is {1} and does not accept {2:?} transactions")]
+    StatusForbids(u16, AccountStatus, TransactionType),
+
+    #[error("Account {0} cannot move from {1} to {2}")]
+    InvalidTransition(u16, AccountStatus, AccountStatus),
+
+    #[error("{1} units would take a balance of account {0} out of range")]
+    Overflow(u16, Amount),
 }
 
 impl Account {
-    pub fn new(amount: &mut Decimal, client_id: u16) -> Self {
-        amount.rescale(4);
+    pub fn new(amount: &mut Amount, client_id: u16) -> Self {
+        amount::normalize(amount);
         Self {
             client_id,
             available_funds: *amount,
-            held_funds: Decimal::new(0, 4),
+            held_funds: amount::zero(),
             total_funds: *amount,
-            locked: false,
+            status: AccountStatus::Active,
+            suspended_until: None,
+            sequence: 1,
+            locked_reason: None,
+            locked_by_tx: None,
         }
     }
 
-    pub fn deposit(&mut self, amount: Decimal) -> Result<(), AccountError> {
-        if self.locked {
-            return Err(AccountError::AccountLocked(self.client_id));
+    /// Whether the account has been frozen by a chargeback.
+    pub fn is_locked(&self) -> bool {
+        self.status == AccountStatus::Frozen
+    }
+
+    /// Moves the account to `next`, enforcing [`AccountStatus::can_transition_to`]. An
+    /// account that was frozen can be put under review to settle its disputes, but only
+    /// [`crate::ledger::Ledger::unlock`] brings it back to active.
+    pub fn transition(&mut self, next: AccountStatus) -> Result<(), AccountError> {
+        let unlocking = self.locked_reason.is_some()
+            && matches!(next, AccountStatus::Active | AccountStatus::Suspended);
+        if unlocking || !self.status.can_transition_to(next) {
+            return Err(AccountError::InvalidTransition(
+                self.client_id,
+                self.status,
+                next,
+            ));
         }
 
-        self.available_funds += amount;
-        self.total_funds += amount;
+        if next == AccountStatus::Closed {
+            self.clear_lock();
+        }
+        self.status = next;
+        Ok(())
+    }
 
-        assert_eq!(self.total_funds, self.available_funds + self.held_funds);
+    /// Records `tx` as what froze the account, if the transaction that just ran on it
+    /// froze it. Transactions are refused by frozen accounts, so a frozen account after
+    /// one means that one froze it.
+    pub(crate) fn record_lock(&mut self, reason: LockReason, tx: u32) {
+        if self.is_locked() {
+            self.locked_reason = Some(reason);
+            self.locked_by_tx = Some(tx);
+        }
+    }
+
+    pub(crate) fn clear_lock(&mut self) {
+        self.locked_reason = None;
+        self.locked_by_tx = None;
+    }
+
+    /// Suspends the account until `until`, after which [`Account::expire_suspension`]
+    /// brings it back to active.
+    pub fn suspend(&mut self, until: SystemTime) -> Result<(), AccountError> {
+        if self.status != AccountStatus::Suspended {
+            self.transition(AccountStatus::Suspended)?;
+        }
+        self.suspended_until = Some(until);
         Ok(())
     }
 
-    pub fn withdraw(&mut self, amount: Decimal) -> Result<(), AccountError> {
-        if self.locked {
-            return Err(AccountError::AccountLocked(self.client_id));
+    /// Reactivates a suspended account whose suspension has run out by `now`. Returns
+    /// whether the account was reactivated.
+    pub fn expire_suspension(&mut self, now: SystemTime) -> bool {
+        match self.suspended_until {
+            Some(until) if self.status == AccountStatus::Suspended && until <= now => {
+                self.status = AccountStatus::Active;
+                self.suspended_until = None;
+                true
+            }
+            _ => false,
         }
+    }
+
+    pub(crate) fn check_status(&self, tx_type: TransactionType) -> Result<(), AccountError> {
+        match self.status {
+            AccountStatus::Frozen => Err(AccountError::AccountLocked(self.client_id)),
+            status if !status.permits(&tx_type) => {
+                Err(AccountError::StatusForbids(self.client_id, status, tx_type))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves the balances to the ones worked out for a transaction of `amount`, leaving
+    /// the account untouched when any of them overflowed.
+    fn set_balances(
+        &mut self,
+        amount: Amount,
+        available: Option<Amount>,
+        held: Option<Amount>,
+        total: Option<Amount>,
+    ) -> Result<(), AccountError> {
+        let (Some(available), Some(held), Some(total)) = (available, held, total) else {
+            return Err(AccountError::Overflow(self.client_id, amount));
+        };
+        self.available_funds = available;
+        self.held_funds = held;
+        self.total_funds = total;
+        Ok(())
+    }
+
+    pub fn deposit(&mut self, amount: Amount) -> Result<(), AccountError> {
+        self.check_status(TransactionType::Deposit)?;
+
+        self.set_balances(
+            amount,
+            self.available_funds.checked_add(amount),
+            Some(self.held_funds),
+            self.total_funds.checked_add(amount),
+        )?;
+
+        assert_eq!(self.total_funds, self.available_funds + self.held_funds);
+        self.sequence += 1;
+        Ok(())
+    }
+
+    pub fn withdraw(&mut self, amount: Amount) -> Result<(), AccountError> {
+        self.check_status(TransactionType::Withdrawal)?;
 
         if self.available_funds < amount {
             return Err(AccountError::NotEnoughFunds(self.client_id, amount));
         }
 
-        self.available_funds -= amount;
-        self.total_funds -= amount;
+        self.set_balances(
+            amount,
+            self.available_funds.checked_sub(amount),
+            Some(self.held_funds),
+            self.total_funds.checked_sub(amount),
+        )?;
 
         assert_eq!(self.total_funds, self.available_funds + self.held_funds);
+        self.sequence += 1;
 
         Ok(())
     }
 
-    pub fn dispute(&mut self, amount: Decimal) -> Result<(), AccountError> {
-        if self.locked {
-            return Err(AccountError::AccountLocked(self.client_id));
-        }
+    pub fn dispute(&mut self, amount: Amount) -> Result<(), AccountError> {
+        self.check_status(TransactionType::Dispute)?;
 
         if self.available_funds < amount {
             return Err(AccountError::NotEnoughFunds(self.client_id, amount));
         }
 
-        self.available_funds -= amount;
-        self.held_funds += amount;
+        self.set_balances(
+            amount,
+            self.available_funds.checked_sub(amount),
+            self.held_funds.checked_add(amount),
+            Some(self.total_funds),
+        )?;
         assert_eq!(self.total_funds, self.available_funds + self.held_funds);
+        self.sequence += 1;
 
         Ok(())
     }
 
-    pub fn resolve(&mut self, amount: Decimal) -> Result<(), AccountError> {
-        if self.locked {
-            return Err(AccountError::AccountLocked(self.client_id));
-        }
+    pub fn resolve(&mut self, amount: Amount) -> Result<(), AccountError> {
+        self.check_status(TransactionType::Resolve)?;
 
         if self.held_funds < amount {
             return Err(AccountError::NotEnoughFunds(self.client_id, amount));
         }
 
-        self.held_funds -= amount;
-        self.available_funds += amount;
+        self.set_balances(
+            amount,
+            self.available_funds.checked_add(amount),
+            self.held_funds.checked_sub(amount),
+            Some(self.total_funds),
+        )?;
 
         assert_eq!(self.total_funds, self.available_funds + self.held_funds);
+        self.sequence += 1;
 
         Ok(())
     }
 
-    pub fn chargeback(&mut self, amount: Decimal) -> Result<(), AccountError> {
-        if self.locked {
-            return Err(AccountError::AccountLocked(self.client_id));
-        };
+    pub fn chargeback(&mut self, amount: Amount) -> Result<(), AccountError> {
+        self.check_status(TransactionType::Chargeback)?;
 
         if self.held_funds < amount {
-            self.locked = true;
+            self.transition(AccountStatus::Frozen)?;
             return Err(AccountError::NotEnoughFunds(self.client_id, amount));
         };
 
-        self.held_funds -= amount;
-        self.total_funds -= amount;
+        self.set_balances(
+            amount,
+            Some(self.available_funds),
+            self.held_funds.checked_sub(amount),
+            self.total_funds.checked_sub(amount),
+        )?;
 
-        self.locked = true;
+        self.sequence += 1;
+        self.transition(AccountStatus::Frozen)?;
 
         assert_eq!(self.total_funds, self.available_funds + self.held_funds);
 
@@ -130,72 +368,245 @@ impl Account {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rust_decimal_macros::dec;
+    use crate::amount::amount;
+    use std::time::Duration;
 
     #[test]
     fn test_new_account_with_zero_balance() {
-        let mut amount = Decimal::new(0, 4);
+        let mut amount = amount::zero();
         let account = Account::new(&mut amount, 1);
-        assert_eq!(account.available_funds, dec!(0.0000));
-        assert_eq!(account.total_funds, dec!(0.0000));
+        assert_eq!(account.available_funds, amount!(0.0000));
+        assert_eq!(account.total_funds, amount!(0.0000));
     }
 
     #[test]
     fn test_deposit_to_locked_account() {
-        let mut account = Account::new(&mut dec!(100.0000), 1);
-        account.locked = true;
-        let result = account.deposit(dec!(50.0000));
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        account.status = AccountStatus::Frozen;
+        let result = account.deposit(amount!(50.0000));
         assert!(matches!(result, Err(AccountError::AccountLocked(1))));
     }
 
     #[test]
     fn test_withdraw_more_than_available() {
-        let mut account = Account::new(&mut dec!(100.0000), 1);
-        let result = account.withdraw(dec!(150.0000));
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        let result = account.withdraw(amount!(150.0000));
         assert!(matches!(result, Err(AccountError::NotEnoughFunds(1, _))));
     }
 
     #[test]
     fn test_withdraw_exact_available_amount() {
-        let mut account = Account::new(&mut dec!(100.0000), 1);
-        let result = account.withdraw(dec!(100.0000));
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        let result = account.withdraw(amount!(100.0000));
         assert!(result.is_ok());
-        assert_eq!(account.available_funds, dec!(0.0000));
-        assert_eq!(account.total_funds, dec!(0.0000));
+        assert_eq!(account.available_funds, amount!(0.0000));
+        assert_eq!(account.total_funds, amount!(0.0000));
     }
 
     #[test]
     fn test_dispute_more_than_available() {
-        let mut account = Account::new(&mut dec!(100.0000), 1);
-        let result = account.dispute(dec!(150.0000));
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        let result = account.dispute(amount!(150.0000));
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_overflowing_deposit_is_refused() {
+        let mut max = Amount::MAX;
+        let mut account = Account::new(&mut max, 1);
+        let result = account.deposit(amount!(1.0000));
+        assert!(matches!(result, Err(AccountError::Overflow(1, _))));
+        assert_eq!(account.total_funds, Amount::MAX);
+        assert_eq!(account.sequence, 1);
+    }
+
     #[test]
     fn test_resolve_more_than_held() {
-        let mut account = Account::new(&mut dec!(100.0000), 1);
-        account.dispute(dec!(50.0000)).unwrap();
-        let result = account.resolve(dec!(100.0000));
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        account.dispute(amount!(50.0000)).unwrap();
+        let result = account.resolve(amount!(100.0000));
         assert!(result.is_err());
     }
 
     #[test]
     fn test_chargeback_more_than_held() {
-        let mut account = Account::new(&mut dec!(100.0000), 1);
-        account.dispute(dec!(50.0000)).unwrap();
-        let result = account.chargeback(dec!(100.0000));
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        account.dispute(amount!(50.0000)).unwrap();
+        let result = account.chargeback(amount!(100.0000));
         assert!(result.is_err());
-        assert!(account.locked);
+        assert!(account.is_locked());
     }
 
     #[test]
     fn test_operations_on_locked_account() {
-        let mut account = Account::new(&mut dec!(100.0000), 1);
-        account.locked = true;
-        assert!(account.deposit(dec!(50.0000)).is_err());
-        assert!(account.withdraw(dec!(50.0000)).is_err());
-        assert!(account.dispute(dec!(50.0000)).is_err());
-        assert!(account.resolve(dec!(50.0000)).is_err());
-        assert!(account.chargeback(dec!(50.0000)).is_err());
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        account.status = AccountStatus::Frozen;
+        assert!(account.deposit(amount!(50.0000)).is_err());
+        assert!(account.withdraw(amount!(50.0000)).is_err());
+        assert!(account.dispute(amount!(50.0000)).is_err());
+        assert!(account.resolve(amount!(50.0000)).is_err());
+        assert!(account.chargeback(amount!(50.0000)).is_err());
+    }
+
+    #[test]
+    fn test_status_transitions() {
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        assert!(account.transition(AccountStatus::UnderReview).is_ok());
+        assert!(account.transition(AccountStatus::Active).is_ok());
+        assert!(account.transition(AccountStatus::Closed).is_ok());
+        assert!(matches!(
+            account.transition(AccountStatus::Active),
+            Err(AccountError::InvalidTransition(
+                1,
+                AccountStatus::Closed,
+                AccountStatus::Active
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_suspended_account_only_runs_disputes() {
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        account.transition(AccountStatus::Suspended).unwrap();
+        assert!(matches!(
+            account.deposit(amount!(1.0000)),
+            Err(AccountError::StatusForbids(1, AccountStatus::Suspended, _))
+        ));
+        assert!(account.withdraw(amount!(1.0000)).is_err());
+        assert!(account.dispute(amount!(50.0000)).is_ok());
+        assert!(account.resolve(amount!(50.0000)).is_ok());
+    }
+
+    #[test]
+    fn test_suspension_expires() {
+        let now = SystemTime::now();
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        account.suspend(now + Duration::from_secs(60)).unwrap();
+
+        assert!(!account.expire_suspension(now));
+        assert_eq!(account.status, AccountStatus::Suspended);
+
+        assert!(account.expire_suspension(now + Duration::from_secs(60)));
+        assert_eq!(account.status, AccountStatus::Active);
+        assert_eq!(account.suspended_until, None);
+        assert!(account.deposit(amount!(1.0000)).is_ok());
+    }
+
+    #[test]
+    fn test_frozen_account_cannot_be_suspended() {
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        account.status = AccountStatus::Frozen;
+        assert!(account.suspend(SystemTime::now()).is_err());
+        assert!(!account.expire_suspension(SystemTime::now()));
+        assert_eq!(account.status, AccountStatus::Frozen);
+    }
+
+    #[test]
+    fn test_under_review_account_blocks_withdrawals() {
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        account.transition(AccountStatus::UnderReview).unwrap();
+        assert!(account.deposit(amount!(1.0000)).is_ok());
+        assert!(matches!(
+            account.withdraw(amount!(1.0000)),
+            Err(AccountError::StatusForbids(
+                1,
+                AccountStatus::UnderReview,
+                TransactionType::Withdrawal
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_chargeback_freezes_account_under_review() {
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        account.transition(AccountStatus::UnderReview).unwrap();
+        account.dispute(amount!(10.0000)).unwrap();
+        account.chargeback(amount!(10.0000)).unwrap();
+        assert_eq!(account.status, AccountStatus::Frozen);
+    }
+
+    #[test]
+    fn test_report_row_includes_locked_and_status() {
+        let mut account = Account::new(&mut amount!(1.0000), 1);
+        account.status = AccountStatus::Frozen;
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        wtr.serialize(&account).unwrap();
+        let output = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "client_id,available_funds,held_funds,total_funds,locked,status,sequence\n1,1.0000,0.0000,1.0000,true,frozen,1\n"
+        );
+    }
+
+    #[test]
+    fn test_sequence_bumped_on_balance_changes_only() {
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        assert_eq!(account.sequence, 1);
+
+        account.deposit(amount!(10.0000)).unwrap();
+        account.dispute(amount!(10.0000)).unwrap();
+        account.resolve(amount!(10.0000)).unwrap();
+        assert_eq!(account.sequence, 4);
+
+        assert!(account.withdraw(amount!(500.0000)).is_err());
+        account.transition(AccountStatus::UnderReview).unwrap();
+        assert_eq!(account.sequence, 4);
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw_move_available_and_total() {
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        account.deposit(amount!(25.5000)).unwrap();
+        assert_eq!(account.available_funds, amount!(125.5000));
+        assert_eq!(account.held_funds, amount!(0.0000));
+        assert_eq!(account.total_funds, amount!(125.5000));
+
+        account.withdraw(amount!(0.5000)).unwrap();
+        assert_eq!(account.available_funds, amount!(125.0000));
+        assert_eq!(account.held_funds, amount!(0.0000));
+        assert_eq!(account.total_funds, amount!(125.0000));
+    }
+
+    #[test]
+    fn test_dispute_exact_available_amount() {
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        account.dispute(amount!(100.0000)).unwrap();
+        assert_eq!(account.available_funds, amount!(0.0000));
+        assert_eq!(account.held_funds, amount!(100.0000));
+        assert_eq!(account.total_funds, amount!(100.0000));
+    }
+
+    #[test]
+    fn test_resolve_exact_held_amount() {
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        account.dispute(amount!(40.0000)).unwrap();
+        account.resolve(amount!(40.0000)).unwrap();
+        assert_eq!(account.available_funds, amount!(100.0000));
+        assert_eq!(account.held_funds, amount!(0.0000));
+        assert_eq!(account.total_funds, amount!(100.0000));
+    }
+
+    #[test]
+    fn test_chargeback_exact_held_amount() {
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        account.dispute(amount!(40.0000)).unwrap();
+        account.chargeback(amount!(40.0000)).unwrap();
+        assert_eq!(account.available_funds, amount!(60.0000));
+        assert_eq!(account.held_funds, amount!(0.0000));
+        assert_eq!(account.total_funds, amount!(60.0000));
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn test_rejected_operations_leave_balances_untouched() {
+        let mut account = Account::new(&mut amount!(100.0000), 1);
+        account.dispute(amount!(30.0000)).unwrap();
+
+        assert!(account.withdraw(amount!(70.0001)).is_err());
+        assert!(account.dispute(amount!(70.0001)).is_err());
+        assert!(account.resolve(amount!(30.0001)).is_err());
+        assert_eq!(account.available_funds, amount!(70.0000));
+        assert_eq!(account.held_funds, amount!(30.0000));
+        assert_eq!(account.total_funds, amount!(100.0000));
     }
 }
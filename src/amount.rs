@@ -0,0 +1,373 @@
+//! Monetary amount representation.
+//!
+//! By default balances are kept as `Decimal`. Building with the `fixed-point` feature
+//! swaps in [`Fixed`], an `i64` scaled by 10^4, which is considerably cheaper to add and
+//! compare. The tradeoff is range and precision: a `Fixed` holds at most
+//! ±922,337,203,685,477.5807 units and amounts are rounded to 4 decimal places when they
+//! are read, while `Decimal` keeps whatever precision the input had. Account balances are
+//! changed with `checked_add` and `checked_sub`, so a transaction that would overflow one
+//! is refused, while the operators used for report totals saturate at the limits of a
+//! `Fixed` rather than wrapping. Amounts still cross the serde boundary as `Decimal`, so
+//! the csv input and output formats are the same in both modes.
+use rust_decimal::{prelude::ToPrimitive, Decimal, RoundingStrategy};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Neg, Sub, SubAssign},
+    str::FromStr,
+};
+use thiserror::Error;
+
+#[cfg(not(feature = "fixed-point"))]
+pub type Amount = Decimal;
+
+#[cfg(feature = "fixed-point")]
+pub type Amount = Fixed;
+
+/// Zero with the 4 decimal places used in reports.
+#[cfg(not(feature = "fixed-point"))]
+pub fn zero() -> Amount {
+    Decimal::new(0, 4)
+}
+
+#[cfg(feature = "fixed-point")]
+pub fn zero() -> Amount {
+    Fixed::ZERO
+}
+
+/// Brings an amount to the 4 decimal places used in reports.
+#[cfg(not(feature = "fixed-point"))]
+pub fn normalize(amount: &mut Amount) {
+    amount.rescale(Fixed::DECIMALS);
+}
+
+#[cfg(feature = "fixed-point")]
+pub fn normalize(_amount: &mut Amount) {}
+
+/// The amount as a `Decimal`, whichever representation is compiled in.
+#[cfg(not(feature = "fixed-point"))]
+pub fn to_decimal(amount: Amount) -> Decimal {
+    amount
+}
+
+#[cfg(feature = "fixed-point")]
+pub fn to_decimal(amount: Amount) -> Decimal {
+    amount.to_decimal()
+}
+
+/// Amount literal for tests that compiles in both decimal and fixed-point mode.
+#[cfg(test)]
+macro_rules! amount {
+    ($value:literal) => {
+        <$crate::amount::Amount as TryFrom<rust_decimal::Decimal>>::try_from(
+            rust_decimal_macros::dec!($value),
+        )
+        .unwrap()
+    };
+}
+
+#[cfg(test)]
+pub(crate) use amount;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AmountError {
+    #[error("Amount out of fixed-point range: {0}")]
+    OutOfRange(Decimal),
+
+    #[error("Amount is not a number: {0}")]
+    Invalid(String),
+
+    #[error("Amount in scientific notation: {0}")]
+    Scientific(String),
+
+    #[error("Amount with thousands separators: {0}")]
+    ThousandsSeparators(String),
+
+    #[error("Amount with more than {max} decimal places: {amount}")]
+    TooManyDecimals { amount: String, max: u32 },
+}
+
+/// Which spellings of an amount the input readers accept. The default takes all of
+/// them, [`AmountFormat::STRICT`] only plain numbers with up to 4 decimal places.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmountFormat {
+    /// Accept scientific notation such as `1.5e3`
+    pub scientific: bool,
+    /// Accept `,` or `_` grouping the digits, as in `1,000.50`
+    pub thousands_separators: bool,
+    /// Reject amounts with more decimal places than this, not counting trailing zeros
+    pub max_decimals: Option<u32>,
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        Self {
+            scientific: true,
+            thousands_separators: true,
+            max_decimals: None,
+        }
+    }
+}
+
+impl AmountFormat {
+    pub const STRICT: AmountFormat = AmountFormat {
+        scientific: false,
+        thousands_separators: false,
+        max_decimals: Some(Fixed::DECIMALS),
+    };
+
+    pub fn parse(&self, text: &str) -> Result<Amount, AmountError> {
+        let scientific = text.contains(['e', 'E']);
+        if scientific && !self.scientific {
+            return Err(AmountError::Scientific(text.to_string()));
+        }
+        let grouped = text.contains([',', '_']);
+        if grouped && !self.thousands_separators {
+            return Err(AmountError::ThousandsSeparators(text.to_string()));
+        }
+
+        let digits = text.replace([',', '_'], "");
+        let value = if scientific {
+            Decimal::from_scientific(&digits)
+        } else {
+            Decimal::from_str(&digits)
+        }
+        .map_err(|_| AmountError::Invalid(text.to_string()))?;
+
+        if let Some(max) = self.max_decimals {
+            if value.normalize().scale() > max {
+                return Err(AmountError::TooManyDecimals {
+                    amount: text.to_string(),
+                    max,
+                });
+            }
+        }
+        from_decimal(value)
+    }
+}
+
+#[cfg(not(feature = "fixed-point"))]
+fn from_decimal(value: Decimal) -> Result<Amount, AmountError> {
+    Ok(value)
+}
+
+#[cfg(feature = "fixed-point")]
+fn from_decimal(value: Decimal) -> Result<Amount, AmountError> {
+    Fixed::try_from(value)
+}
+
+/// Fixed-point amount with 4 decimal places stored as an `i64` count of 10^-4 units.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed(i64);
+
+impl Fixed {
+    pub const DECIMALS: u32 = 4;
+    pub const SCALE: i64 = 10_000;
+    pub const ZERO: Fixed = Fixed(0);
+    pub const MAX: Fixed = Fixed(i64::MAX);
+    pub const MIN: Fixed = Fixed(i64::MIN);
+
+    /// Builds an amount from a raw count of 10^-4 units.
+    pub const fn from_units(units: i64) -> Self {
+        Self(units)
+    }
+
+    pub const fn units(self) -> i64 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    pub fn to_decimal(self) -> Decimal {
+        Decimal::new(self.0, Self::DECIMALS)
+    }
+}
+
+impl TryFrom<Decimal> for Fixed {
+    type Error = AmountError;
+
+    fn try_from(value: Decimal) -> Result<Self, Self::Error> {
+        value
+            .round_dp_with_strategy(Self::DECIMALS, RoundingStrategy::MidpointNearestEven)
+            .checked_mul(Decimal::from(Self::SCALE))
+            .and_then(|units| units.to_i64())
+            .map(Self)
+            .ok_or(AmountError::OutOfRange(value))
+    }
+}
+
+impl From<Fixed> for Decimal {
+    fn from(value: Fixed) -> Self {
+        value.to_decimal()
+    }
+}
+
+impl PartialEq<Decimal> for Fixed {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.to_decimal() == *other
+    }
+}
+
+impl FromStr for Fixed {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::try_from(Decimal::from_str(s)?)?)
+    }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.to_decimal().fmt(f)
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Self::Output {
+        Self(self.0.saturating_neg())
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl Serialize for Fixed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Serialize::serialize(&self.to_decimal(), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Fixed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = <Decimal as Deserialize>::deserialize(deserializer)?;
+        Fixed::try_from(value).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Serde adapter for the optional csv `amount` column, parsing as `Decimal` and then
+/// converting into [`Amount`].
+pub mod serde_option {
+    use super::Amount;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<Amount>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let value = value.map(rust_decimal::Decimal::from);
+        rust_decimal::serde::float_option::serialize(&value, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Amount>, D::Error> {
+        let value = rust_decimal::serde::float_option::deserialize(deserializer)?;
+        value
+            .map(Amount::try_from)
+            .transpose()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_fixed_from_decimal_rounds_to_four_places() {
+        let fixed = Fixed::try_from(dec!(1.23456)).unwrap();
+        assert_eq!(fixed.units(), 12346);
+        assert_eq!(fixed.to_string(), "1.2346");
+    }
+
+    #[test]
+    fn test_fixed_out_of_range() {
+        assert_eq!(
+            Fixed::try_from(dec!(1_000_000_000_000_000)),
+            Err(AmountError::OutOfRange(dec!(1_000_000_000_000_000)))
+        );
+    }
+
+    #[test]
+    fn test_fixed_arithmetic() {
+        let mut amount = Fixed::from_str("10.5").unwrap();
+        amount -= Fixed::from_str("0.0001").unwrap();
+        amount += Fixed::from_str("2").unwrap();
+        assert_eq!(amount, dec!(12.4999));
+        assert!(Fixed::MAX.checked_add(Fixed::from_units(1)).is_none());
+        assert_eq!(Fixed::MAX + Fixed::from_units(1), Fixed::MAX);
+        assert_eq!(-Fixed::MIN, Fixed::MAX);
+    }
+
+    #[test]
+    fn test_amount_formats() {
+        let lenient = AmountFormat::default();
+        assert_eq!(lenient.parse("1.5e3").unwrap(), amount!(1500));
+        assert_eq!(lenient.parse("1,000.25").unwrap(), amount!(1000.25));
+        assert!(lenient.parse("1.23456").is_ok());
+        assert!(lenient.parse("1.2.3").is_err());
+
+        let strict = AmountFormat::STRICT;
+        assert_eq!(strict.parse("1000.2500").unwrap(), amount!(1000.25));
+        assert_eq!(
+            strict.parse("1.5e3"),
+            Err(AmountError::Scientific(String::from("1.5e3")))
+        );
+        assert_eq!(
+            strict.parse("1_000"),
+            Err(AmountError::ThousandsSeparators(String::from("1_000")))
+        );
+        assert_eq!(
+            strict.parse("1.23456"),
+            Err(AmountError::TooManyDecimals {
+                amount: String::from("1.23456"),
+                max: 4
+            })
+        );
+        // trailing zeros do not count
+        assert!(strict.parse("1.000000").is_ok());
+    }
+
+    #[test]
+    fn test_fixed_overflow_saturates() {
+        assert_eq!(Fixed::MIN - Fixed::from_units(1), Fixed::MIN);
+        assert!(Fixed::MIN.checked_sub(Fixed::from_units(1)).is_none());
+        let mut total = Fixed::MAX;
+        total += Fixed::MAX;
+        assert_eq!(total, Fixed::MAX);
+    }
+}
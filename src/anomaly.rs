@@ -0,0 +1,253 @@
+//! Amount-distribution statistics for spotting unusual clients.
+//!
+//! The first significant digits of naturally occurring amounts follow Benford's law, so a
+//! client whose deposits and withdrawals stray far from it, are mostly round numbers, or
+//! include amounts far outside the batch's spread is worth a closer look. The report is
+//! advisory and does not affect processing.
+use crate::{
+    amount,
+    ledger::{Client, Ledger},
+    transaction::TransactionType,
+};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt};
+
+/// Fewer amounts than this say nothing about a client's digit distribution
+pub const MIN_SAMPLE: usize = 20;
+
+/// Mean absolute deviation from Benford above which first digits are nonconforming
+pub const BENFORD_THRESHOLD: f64 = 0.015;
+
+/// Share of round amounts above which a client is flagged
+pub const ROUND_THRESHOLD: f64 = 0.5;
+
+/// Distance from the batch mean, in standard deviations, that makes an amount an outlier
+pub const Z_THRESHOLD: f64 = 3.0;
+
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct AmountStats {
+    pub count: usize,
+    /// How many amounts start with each digit 1 to 9
+    pub first_digits: [usize; 9],
+    /// Share of amounts that are whole multiples of 10
+    pub round_ratio: f64,
+    /// Mean absolute deviation of the first-digit shares from Benford's law
+    pub benford_deviation: f64,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ClientAnomaly {
+    pub client: Client,
+    pub stats: AmountStats,
+    /// Amounts more than [`Z_THRESHOLD`] standard deviations from the batch mean
+    pub outliers: usize,
+    pub flagged: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct AnomalyReport {
+    pub batch: AmountStats,
+    pub clients: Vec<ClientAnomaly>,
+}
+
+impl AnomalyReport {
+    pub fn flagged(&self) -> impl Iterator<Item = &ClientAnomaly> {
+        self.clients.iter().filter(|client| client.flagged)
+    }
+}
+
+impl fmt::Display for AnomalyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} amounts, benford deviation {:.4}, {:.1}% round",
+            self.batch.count,
+            self.batch.benford_deviation,
+            self.batch.round_ratio * 100.0
+        )?;
+        for c in self.flagged() {
+            writeln!(
+                f,
+                "client {}: {} amounts, benford deviation {:.4}, {:.1}% round, {} outliers",
+                c.client,
+                c.stats.count,
+                c.stats.benford_deviation,
+                c.stats.round_ratio * 100.0,
+                c.outliers
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn benford_share(digit: usize) -> f64 {
+    (1.0 + 1.0 / digit as f64).log10()
+}
+
+fn first_digit(amount: Decimal) -> Option<usize> {
+    amount
+        .abs()
+        .to_string()
+        .bytes()
+        .find(|b| (b'1'..=b'9').contains(b))
+        .map(|b| (b - b'0') as usize)
+}
+
+fn is_round(amount: Decimal) -> bool {
+    !amount.is_zero() && (amount % Decimal::TEN).is_zero()
+}
+
+impl AmountStats {
+    fn from_amounts(amounts: &[Decimal]) -> Self {
+        let mut stats = AmountStats {
+            count: amounts.len(),
+            ..Default::default()
+        };
+        if amounts.is_empty() {
+            return stats;
+        }
+
+        let mut round = 0;
+        for amount in amounts {
+            if let Some(digit) = first_digit(*amount) {
+                stats.first_digits[digit - 1] += 1;
+            }
+            if is_round(*amount) {
+                round += 1;
+            }
+        }
+        stats.round_ratio = round as f64 / amounts.len() as f64;
+
+        let counted: usize = stats.first_digits.iter().sum();
+        if counted > 0 {
+            stats.benford_deviation = stats
+                .first_digits
+                .iter()
+                .enumerate()
+                .map(|(i, n)| (*n as f64 / counted as f64 - benford_share(i + 1)).abs())
+                .sum::<f64>()
+                / 9.0;
+        }
+
+        stats
+    }
+}
+
+impl Ledger {
+    /// Computes amount statistics over every deposit and withdrawal in the history, for
+    /// the batch as a whole and per client, flagging clients that look unusual.
+    pub fn anomaly_report(&self) -> AnomalyReport {
+        let mut by_client: BTreeMap<Client, Vec<Decimal>> = BTreeMap::new();
+        for transaction in self.history.values() {
+            if let (TransactionType::Deposit | TransactionType::Withdrawal, Some(amount)) =
+                (&transaction.tx_type, transaction.amount)
+            {
+                by_client
+                    .entry(transaction.client)
+                    .or_default()
+                    .push(amount::to_decimal(amount));
+            }
+        }
+
+        let all: Vec<Decimal> = by_client.values().flatten().copied().collect();
+        let values: Vec<f64> = all.iter().filter_map(|a| a.to_f64()).collect();
+        let mean = values.iter().sum::<f64>() / values.len().max(1) as f64;
+        let variance =
+            values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len().max(1) as f64;
+        let std_dev = variance.sqrt();
+
+        let clients = by_client
+            .into_iter()
+            .map(|(client, amounts)| {
+                let stats = AmountStats::from_amounts(&amounts);
+                let outliers = if std_dev > 0.0 {
+                    amounts
+                        .iter()
+                        .filter_map(|a| a.to_f64())
+                        .filter(|v| ((v - mean) / std_dev).abs() > Z_THRESHOLD)
+                        .count()
+                } else {
+                    0
+                };
+                let flagged = outliers > 0
+                    || stats.round_ratio > ROUND_THRESHOLD
+                    || (stats.count >= MIN_SAMPLE && stats.benford_deviation > BENFORD_THRESHOLD);
+
+                ClientAnomaly {
+                    client,
+                    stats,
+                    outliers,
+                    flagged,
+                }
+            })
+            .collect();
+
+        AnomalyReport {
+            batch: AmountStats::from_amounts(&all),
+            clients,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{apply_all, tx};
+
+    #[test]
+    fn test_first_digit() {
+        assert_eq!(first_digit(Decimal::new(500, 4)), Some(5));
+        assert_eq!(first_digit(Decimal::new(1234, 0)), Some(1));
+        assert_eq!(first_digit(Decimal::ZERO), None);
+    }
+
+    #[test]
+    fn test_benford_conforming_amounts() {
+        // amounts spread over several orders of magnitude follow Benford closely
+        let amounts: Vec<Decimal> = (1..=1000)
+            .map(|i| {
+                Decimal::from_f64_retain(1.02f64.powi(i))
+                    .unwrap()
+                    .round_dp(4)
+            })
+            .collect();
+        let stats = AmountStats::from_amounts(&amounts);
+        assert!(stats.benford_deviation < BENFORD_THRESHOLD);
+    }
+
+    #[test]
+    fn test_flags_round_number_client() {
+        let mut ledger = Ledger::new();
+        apply_all(
+            &mut ledger,
+            [
+                tx::deposit(1, 1, "13.27"),
+                tx::deposit(1, 2, "4.91"),
+                tx::deposit(2, 3, "100"),
+                tx::deposit(2, 4, "50"),
+            ],
+        );
+
+        let report = ledger.anomaly_report();
+        assert_eq!(report.batch.count, 4);
+        assert_eq!(report.batch.round_ratio, 0.5);
+        let flagged: Vec<Client> = report.flagged().map(|c| c.client).collect();
+        assert_eq!(flagged, vec![2]);
+    }
+
+    #[test]
+    fn test_flags_outlier() {
+        let mut ledger = Ledger::new();
+        let mut transactions: Vec<_> = (1..=30)
+            .map(|id| tx::deposit(1, id, &format!("{}.{:02}", id % 9 + 1, id)))
+            .collect();
+        transactions.push(tx::deposit(2, 31, "9876.54"));
+        apply_all(&mut ledger, transactions);
+
+        let report = ledger.anomaly_report();
+        assert_eq!(report.clients[1].outliers, 1);
+        assert!(report.clients[1].flagged);
+        assert_eq!(report.clients[0].outliers, 0);
+    }
+}
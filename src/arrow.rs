@@ -0,0 +1,197 @@
+//! Arrow IPC input, behind the `arrow` feature.
+//!
+//! Reads Arrow IPC files, which is also what Feather v2 files are, and Arrow IPC streams,
+//! told apart by the `ARROW1` magic a file starts with. Record batches need `type`,
+//! `client` and `tx` columns and may have an `amount` column. Each column is converted
+//! once per batch, `type` and `amount` to strings and `client` and `tx` to their integer
+//! types, and transactions are read straight out of the converted arrays, so no row gets
+//! an allocation of its own beyond the name of a custom type. `type` may be dictionary
+//! encoded, `client` and `tx` any integer type whose values fit, and `amount` a string,
+//! decimal, integer or float column; strings and decimals keep every digit. Rows are
+//! numbered from 1 across batches.
+use crate::{
+    amount::AmountFormat,
+    reader::on_blocking_pool,
+    source::Row,
+    transaction::{Transaction, TransactionType},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use arrow_array::{
+    cast::AsArray,
+    types::{UInt16Type, UInt32Type},
+    Array, ArrayRef, RecordBatch,
+};
+use arrow_cast::{cast_with_options, CastOptions};
+use arrow_ipc::reader::{FileReader, StreamReader};
+use arrow_schema::{ArrowError, DataType};
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek},
+    path::PathBuf,
+};
+use tokio::sync::mpsc::Sender;
+
+const FILE_MAGIC: [u8; 6] = *b"ARROW1";
+
+pub async fn arrow_reader(
+    path: &PathBuf,
+    amounts: AmountFormat,
+    channel: Sender<Row>,
+) -> Result<()> {
+    let path = path.clone();
+    on_blocking_pool(move || {
+        let mut file = File::open(&path).with_context(|| format!("opening {}", path.display()))?;
+        let mut magic = [0; 6];
+        let is_file = file.read_exact(&mut magic).is_ok() && magic == FILE_MAGIC;
+        file.rewind()?;
+
+        let batches: Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>>> = if is_file {
+            Box::new(FileReader::try_new(BufReader::new(file), None)?)
+        } else {
+            Box::new(StreamReader::try_new(BufReader::new(file), None)?)
+        };
+
+        let mut read = 0;
+        for batch in batches {
+            let rows = read_batch(&batch?, read, &amounts)?;
+            read += rows.len() as u64;
+            for row in rows {
+                if channel.blocking_send(row).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Reads the transactions of one batch, numbering its rows on from `read`.
+fn read_batch(batch: &RecordBatch, read: u64, amounts: &AmountFormat) -> Result<Vec<Row>> {
+    // values that do not fit the target type are errors rather than nulls
+    let options = CastOptions {
+        safe: false,
+        ..CastOptions::default()
+    };
+    let column = |name: &str, to: DataType| -> Result<Option<ArrayRef>> {
+        batch
+            .column_by_name(name)
+            .map(|column| {
+                cast_with_options(column, &to, &options).with_context(|| format!("column {name}"))
+            })
+            .transpose()
+    };
+    let required = |name: &str, to: DataType| -> Result<ArrayRef> {
+        column(name, to)?.ok_or_else(|| anyhow!("record batch has no {name} column"))
+    };
+
+    let types = required("type", DataType::Utf8)?;
+    let clients = required("client", DataType::UInt16)?;
+    let txs = required("tx", DataType::UInt32)?;
+    let amount_column = column("amount", DataType::Utf8)?;
+    let types = types.as_string::<i32>();
+    let clients = clients.as_primitive::<UInt16Type>();
+    let txs = txs.as_primitive::<UInt32Type>();
+    let amount_values = amount_column
+        .as_ref()
+        .map(|column| column.as_string::<i32>());
+
+    let mut rows = Vec::with_capacity(batch.num_rows());
+    for index in 0..batch.num_rows() {
+        let row = read + index as u64 + 1;
+        if types.is_null(index) || clients.is_null(index) || txs.is_null(index) {
+            bail!("row {row}: type, client and tx cannot be null");
+        }
+        let amount = match amount_values {
+            Some(values) if values.is_valid(index) => Some(
+                amounts
+                    .parse(values.value(index).trim())
+                    .with_context(|| format!("row {row}"))?,
+            ),
+            _ => None,
+        };
+        rows.push((
+            row,
+            Transaction {
+                tx_type: TransactionType::from_name(types.value(index).trim()),
+                client: clients.value(index),
+                tx: txs.value(index),
+                amount,
+            },
+        ));
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::amount;
+    use arrow_array::{Decimal128Array, Int32Array, Int64Array, StringArray};
+    use arrow_ipc::writer::{FileWriter, StreamWriter};
+    use std::sync::Arc;
+    use tokio::sync::mpsc::channel;
+
+    fn batch(clients: Vec<i64>) -> RecordBatch {
+        let amounts = Decimal128Array::from(vec![Some(12345), Some(5000), None])
+            .with_precision_and_scale(10, 4)
+            .unwrap();
+        RecordBatch::try_from_iter([
+            (
+                "type",
+                Arc::new(StringArray::from(vec!["deposit", "withdrawal", "dispute"])) as ArrayRef,
+            ),
+            ("client", Arc::new(Int64Array::from(clients)) as ArrayRef),
+            ("tx", Arc::new(Int32Array::from(vec![1, 2, 1])) as ArrayRef),
+            ("amount", Arc::new(amounts) as ArrayRef),
+        ])
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_arrow_reader_reads_files_and_streams() {
+        let batch = batch(vec![3, 3, 3]);
+        let mut file = FileWriter::try_new(Vec::new(), &batch.schema()).unwrap();
+        file.write(&batch).unwrap();
+        file.finish().unwrap();
+        let mut stream = StreamWriter::try_new(Vec::new(), &batch.schema()).unwrap();
+        stream.write(&batch).unwrap();
+        stream.write(&batch).unwrap();
+        stream.finish().unwrap();
+
+        let path = std::env::temp_dir().join(format!("mpe-{}-feed.arrow", std::process::id()));
+        for (written, rows) in [
+            (file.into_inner().unwrap(), 3),
+            (stream.into_inner().unwrap(), 6),
+        ] {
+            std::fs::write(&path, written).unwrap();
+            let (sender, mut receiver) = channel(8);
+            arrow_reader(&path, AmountFormat::default(), sender)
+                .await
+                .unwrap();
+
+            let (row, deposit) = receiver.recv().await.unwrap();
+            assert_eq!(row, 1);
+            assert_eq!(deposit.tx_type, TransactionType::Deposit);
+            assert_eq!((deposit.client, deposit.tx), (3, 1));
+            assert_eq!(deposit.amount, Some(amount!(1.2345)));
+            let (_, withdrawal) = receiver.recv().await.unwrap();
+            assert_eq!(withdrawal.amount, Some(amount!(0.5)));
+            let (_, dispute) = receiver.recv().await.unwrap();
+            assert_eq!(dispute.amount, None);
+
+            let mut last = 3;
+            while let Some((row, _)) = receiver.recv().await {
+                last = row;
+            }
+            assert_eq!(last, rows);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_batch_rejects_values_that_do_not_fit() {
+        let rows = read_batch(&batch(vec![1, 2, 70_000]), 0, &AmountFormat::default());
+        assert!(rows.is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use crate::{
+    amount::Amount,
+    ledger::{Client, Ledger},
+    transaction::{TransactionStatus, TransactionType},
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+/// Balance or flag of an account that can disagree with the history
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditField {
+    Available,
+    Held,
+    Total,
+    Locked,
+    /// The history references a client for which no account exists
+    Account,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Discrepancy {
+    pub client: Client,
+    pub field: AuditField,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct AuditReport {
+    /// Number of accounts whose balances were recomputed
+    pub accounts_checked: usize,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl AuditReport {
+    pub fn is_clean(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+impl fmt::Display for AuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "audited {} accounts, {} discrepancies",
+            self.accounts_checked,
+            self.discrepancies.len()
+        )?;
+        for d in &self.discrepancies {
+            writeln!(
+                f,
+                "client {}: {:?} expected {} but found {}",
+                d.client, d.field, d.expected, d.actual
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Expected {
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    charged_back: bool,
+}
+
+impl Ledger {
+    /// Recomputes every account's balances from the transaction history, without going
+    /// through `Account`, and lists every place where the stored balances disagree.
+    /// Accounts touched by a custom transaction type are left out, since only its handler
+    /// knows what it did to the balances.
+    pub fn audit(&self) -> AuditReport {
+        let mut expected: BTreeMap<Client, Expected> = BTreeMap::new();
+        let mut custom = HashSet::new();
+
+        for transaction in self.history.values() {
+            let amount = match (transaction.status, transaction.amount) {
+                (TransactionStatus::Applied | TransactionStatus::ChargedBack, Some(amount)) => {
+                    amount
+                }
+                _ => continue,
+            };
+
+            let change = match transaction.tx_type {
+                TransactionType::Deposit => amount,
+                TransactionType::Withdrawal => -amount,
+                TransactionType::Custom(_) => {
+                    custom.insert(transaction.client);
+                    continue;
+                }
+                _ => continue,
+            };
+
+            let balances = expected.entry(transaction.client).or_default();
+            balances.available += change;
+            balances.total += change;
+
+            if transaction.disputed {
+                balances.available -= amount;
+                balances.held += amount;
+            }
+
+            if transaction.status == TransactionStatus::ChargedBack {
+                // the dispute moved the funds to held, the chargeback then removed them
+                balances.available -= amount;
+                balances.total -= amount;
+                balances.charged_back = true;
+            }
+        }
+
+        expected.retain(|client, _| !custom.contains(client));
+
+        let mut report = AuditReport {
+            accounts_checked: expected.len(),
+            ..Default::default()
+        };
+
+        for (client, balances) in expected {
+            let Some(account) = self.accounts.get(&client) else {
+                report.discrepancies.push(Discrepancy {
+                    client,
+                    field: AuditField::Account,
+                    expected: "present".to_string(),
+                    actual: "missing".to_string(),
+                });
+                continue;
+            };
+
+            let checks = [
+                (
+                    AuditField::Available,
+                    balances.available,
+                    account.available_funds,
+                ),
+                (AuditField::Held, balances.held, account.held_funds),
+                (AuditField::Total, balances.total, account.total_funds),
+            ];
+
+            for (field, expected, actual) in checks {
+                if expected != actual {
+                    report.discrepancies.push(Discrepancy {
+                        client,
+                        field,
+                        expected: expected.to_string(),
+                        actual: actual.to_string(),
+                    });
+                }
+            }
+
+            // a failed chargeback also locks the account, so only the forward direction holds
+            if balances.charged_back && !account.is_locked() {
+                report.discrepancies.push(Discrepancy {
+                    client,
+                    field: AuditField::Locked,
+                    expected: true.to_string(),
+                    actual: false.to_string(),
+                });
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::AccountStatus;
+    use crate::amount::amount;
+    use crate::test_support::{amount, apply_all, ledger_with_accounts, tx};
+
+    #[test]
+    fn test_audit_clean_after_dispute_lifecycle() {
+        let mut ledger = ledger_with_accounts(&[(1, "10.00"), (2, "3.00")]);
+        apply_all(
+            &mut ledger,
+            [
+                tx::deposit(1, 3, "5.00"),
+                tx::withdrawal(1, 4, "1.50"),
+                tx::dispute(1, 3),
+                tx::resolve(1, 3),
+                tx::dispute(2, 2),
+                tx::chargeback(2, 2),
+                tx::dispute(1, 1),
+            ],
+        );
+
+        let report = ledger.audit();
+        assert_eq!(report.accounts_checked, 2);
+        assert!(report.is_clean(), "{report}");
+    }
+
+    #[test]
+    fn test_audit_ignores_rejected_transactions() {
+        let mut ledger = ledger_with_accounts(&[(1, "1.00")]);
+        assert!(ledger
+            .process_transaction(tx::withdrawal(1, 2, "5.00"))
+            .is_err());
+
+        assert!(ledger.audit().is_clean());
+    }
+
+    #[test]
+    fn test_audit_detects_tampered_balances() {
+        let mut ledger = ledger_with_accounts(&[(1, "10.00")]);
+        let account = ledger.accounts.get_mut(&1).unwrap();
+        account.available_funds += amount!(1);
+        account.total_funds += amount!(1);
+
+        let report = ledger.audit();
+        assert_eq!(
+            report
+                .discrepancies
+                .iter()
+                .map(|d| d.field)
+                .collect::<Vec<_>>(),
+            vec![AuditField::Available, AuditField::Total]
+        );
+        assert_eq!(amount(&report.discrepancies[0].expected), amount!(10));
+        assert_eq!(amount(&report.discrepancies[0].actual), amount!(11));
+    }
+
+    #[test]
+    fn test_audit_detects_unlocked_chargeback() {
+        let mut ledger = ledger_with_accounts(&[(1, "10.00")]);
+        apply_all(&mut ledger, [tx::dispute(1, 1), tx::chargeback(1, 1)]);
+        ledger.accounts.get_mut(&1).unwrap().status = AccountStatus::Active;
+
+        let report = ledger.audit();
+        assert_eq!(report.discrepancies.len(), 1);
+        assert_eq!(report.discrepancies[0].field, AuditField::Locked);
+    }
+
+    #[test]
+    fn test_audit_detects_missing_account() {
+        let mut ledger = ledger_with_accounts(&[(1, "10.00")]);
+        ledger.accounts.clear();
+
+        let report = ledger.audit();
+        assert_eq!(report.discrepancies.len(), 1);
+        assert_eq!(report.discrepancies[0].field, AuditField::Account);
+    }
+}
@@ -0,0 +1,179 @@
+//! Avro container file input, behind the `avro` feature.
+//!
+//! Records are decoded against the writer schema stored in the file and matched to
+//! [`Transaction`] by field name, so any schema with `type`, `client`, `tx` and an
+//! optional `amount` field works. `type` may be a string or an enum, the ids any integer
+//! type, and `amount` a string, a number or null, optionally inside a union.
+use crate::{
+    amount::{Amount, AmountFormat},
+    reader::{on_blocking_pool, open_input},
+    source::Row,
+    transaction::{Transaction, TransactionType},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use apache_avro::{types::Value, Reader};
+use std::path::PathBuf;
+use tokio::sync::mpsc::Sender;
+
+pub async fn avro_reader(
+    path: &PathBuf,
+    amounts: AmountFormat,
+    channel: Sender<Row>,
+) -> Result<()> {
+    let path = path.clone();
+    on_blocking_pool(move || {
+        let reader = Reader::new(open_input(&path)?)?;
+        for (index, value) in reader.enumerate() {
+            let record = index as u64 + 1;
+            let transaction = value
+                .map_err(anyhow::Error::from)
+                .and_then(|value| parse_record(value, &amounts))
+                .with_context(|| format!("record {record}"))?;
+            if channel.blocking_send((record, transaction)).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+pub fn parse_record(value: Value, amounts: &AmountFormat) -> Result<Transaction> {
+    let Value::Record(fields) = value else {
+        bail!("expected a record");
+    };
+
+    let mut tx_type = None;
+    let mut client = None;
+    let mut tx = None;
+    let mut amount = None;
+    for (name, value) in fields {
+        match name.as_str() {
+            "type" => tx_type = Some(parse_type(value)?),
+            "client" => client = Some(u16::try_from(integer(value)?)?),
+            "tx" => tx = Some(u32::try_from(integer(value)?)?),
+            "amount" => amount = parse_amount(value, amounts)?,
+            _ => {}
+        }
+    }
+
+    Ok(Transaction {
+        tx_type: tx_type.ok_or_else(|| anyhow!("missing field type"))?,
+        client: client.ok_or_else(|| anyhow!("missing field client"))?,
+        tx: tx.ok_or_else(|| anyhow!("missing field tx"))?,
+        amount,
+    })
+}
+
+fn unwrap_union(value: Value) -> Value {
+    match value {
+        Value::Union(_, inner) => *inner,
+        other => other,
+    }
+}
+
+fn parse_type(value: Value) -> Result<TransactionType> {
+    let name = match unwrap_union(value) {
+        Value::String(name) | Value::Enum(_, name) => name,
+        other => bail!("type must be a string or an enum, found {other:?}"),
+    };
+    Ok(TransactionType::from_name(&name))
+}
+
+fn integer(value: Value) -> Result<i64> {
+    match unwrap_union(value) {
+        Value::Int(value) => Ok(value.into()),
+        Value::Long(value) => Ok(value),
+        other => bail!("expected an integer, found {other:?}"),
+    }
+}
+
+fn parse_amount(value: Value, amounts: &AmountFormat) -> Result<Option<Amount>> {
+    let text = match unwrap_union(value) {
+        Value::Null => return Ok(None),
+        Value::String(text) => text,
+        Value::Int(value) => value.to_string(),
+        Value::Long(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Double(value) => value.to_string(),
+        other => bail!("amount must be a string, a number or null, found {other:?}"),
+    };
+    Ok(Some(amounts.parse(text.trim())?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::amount;
+    use apache_avro::{types::Record, Schema, Writer};
+    use tokio::sync::mpsc::channel;
+
+    const SCHEMA: &str = r#"{
+        "type": "record",
+        "name": "Transaction",
+        "fields": [
+            {"name": "type", "type": {"type": "enum", "name": "Kind",
+                "symbols": ["deposit", "withdrawal", "dispute", "resolve", "chargeback"]}},
+            {"name": "client", "type": "int"},
+            {"name": "tx", "type": "long"},
+            {"name": "amount", "type": ["null", "string"]},
+            {"name": "memo", "type": "string"}
+        ]
+    }"#;
+
+    #[tokio::test]
+    async fn test_avro_reader() {
+        let schema = Schema::parse_str(SCHEMA).unwrap();
+        let mut writer = Writer::new(&schema, Vec::new());
+        for (kind, symbol, tx, amount) in [("deposit", 0, 1, Some("2.5")), ("dispute", 2, 1, None)]
+        {
+            let mut record = Record::new(&schema).unwrap();
+            record.put("type", Value::Enum(symbol, kind.to_string()));
+            record.put("client", 7);
+            record.put("tx", Value::Long(tx));
+            record.put(
+                "amount",
+                match amount {
+                    Some(amount) => Value::Union(1, Box::new(Value::String(amount.into()))),
+                    None => Value::Union(0, Box::new(Value::Null)),
+                },
+            );
+            record.put("memo", "ignored");
+            writer.append(record).unwrap();
+        }
+
+        let path = std::env::temp_dir().join(format!("mpe-{}-in.avro", std::process::id()));
+        std::fs::write(&path, writer.into_inner().unwrap()).unwrap();
+        let (sender, mut receiver) = channel(8);
+        avro_reader(&path, AmountFormat::default(), sender)
+            .await
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (record, deposit) = receiver.recv().await.unwrap();
+        assert_eq!(record, 1);
+        assert_eq!(deposit.tx_type, TransactionType::Deposit);
+        assert_eq!((deposit.client, deposit.tx), (7, 1));
+        assert_eq!(deposit.amount, Some(amount!(2.5)));
+
+        let (_, dispute) = receiver.recv().await.unwrap();
+        assert_eq!(dispute.tx_type, TransactionType::Dispute);
+        assert_eq!(dispute.amount, None);
+    }
+
+    #[test]
+    fn test_parse_record_errors() {
+        let missing_tx = Value::Record(vec![
+            ("type".into(), Value::String("deposit".into())),
+            ("client".into(), Value::Int(1)),
+        ]);
+        assert!(parse_record(missing_tx, &AmountFormat::default()).is_err());
+
+        let negative_client = Value::Record(vec![
+            ("type".into(), Value::String("deposit".into())),
+            ("client".into(), Value::Int(-1)),
+            ("tx".into(), Value::Int(1)),
+        ]);
+        assert!(parse_record(negative_client, &AmountFormat::default()).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! Adaptive batching between the input readers and the ledger.
+//!
+//! Rows are handed to the ledger in batches whose size follows the depth of the queue in
+//! front of it: while the ledger keeps up the batches shrink towards single rows so each
+//! one is applied as soon as it is read, and once the queue backs up they grow so the
+//! ledger spends less time on channel hand-offs. A batch only ever holds rows that have
+//! already arrived, so batching never delays a row waiting for more to show up.
+use tokio::sync::mpsc::{Receiver, Sender};
+
+/// Capacity of the queue of batches in front of the ledger
+pub const BATCH_QUEUE: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchSize {
+    pub max: usize,
+    current: usize,
+}
+
+impl BatchSize {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max: max.max(1),
+            current: 1,
+        }
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Doubles the size while more than half of the queue is taken and halves it once
+    /// the queue has drained.
+    pub fn adapt(&mut self, queued: usize, capacity: usize) -> usize {
+        if queued * 2 > capacity {
+            self.current = (self.current * 2).min(self.max);
+        } else if queued == 0 {
+            self.current = (self.current / 2).max(1);
+        }
+        self.current
+    }
+}
+
+/// Groups the rows of `input` into batches sized by [`BatchSize`] and sends them on.
+pub async fn batch<T>(mut input: Receiver<T>, output: Sender<Vec<T>>, mut size: BatchSize) {
+    while let Some(first) = input.recv().await {
+        let queued = output.max_capacity() - output.capacity();
+        let limit = size.adapt(queued, output.max_capacity());
+
+        let mut rows = Vec::with_capacity(limit);
+        rows.push(first);
+        while rows.len() < limit {
+            match input.try_recv() {
+                Ok(row) => rows.push(row),
+                Err(_) => break,
+            }
+        }
+
+        if output.send(rows).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::tx;
+    use tokio::sync::mpsc::channel;
+
+    #[test]
+    fn test_batch_size_follows_queue_depth() {
+        let mut size = BatchSize::new(8);
+        assert_eq!(size.adapt(10, 16), 2);
+        assert_eq!(size.adapt(12, 16), 4);
+        assert_eq!(size.adapt(16, 16), 8);
+        assert_eq!(size.adapt(16, 16), 8);
+        // somewhere in between, keep the size
+        assert_eq!(size.adapt(4, 16), 8);
+        assert_eq!(size.adapt(0, 16), 4);
+        assert_eq!(size.adapt(0, 16), 2);
+        assert_eq!(size.adapt(0, 16), 1);
+        assert_eq!(size.adapt(0, 16), 1);
+    }
+
+    #[tokio::test]
+    async fn test_batch_keeps_rows_in_order() {
+        let (sender, receiver) = channel(16);
+        for id in 1..=10 {
+            sender
+                .send((id, tx::deposit(1, id as u32, "1.0")))
+                .await
+                .unwrap();
+        }
+        drop(sender);
+
+        let (output, mut batches) = channel(1);
+        let batcher = tokio::spawn(batch(receiver, output, BatchSize::new(4)));
+
+        let mut sizes = vec![];
+        let mut ids = vec![];
+        while let Some(rows) = batches.recv().await {
+            sizes.push(rows.len());
+            ids.extend(rows.iter().map(|(_, transaction)| transaction.tx));
+        }
+        batcher.await.unwrap();
+
+        assert_eq!(ids, (1..=10u32).collect::<Vec<_>>());
+        assert!(sizes.iter().all(|size| *size <= 4));
+    }
+}
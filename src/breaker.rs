@@ -0,0 +1,158 @@
+//! Global circuit breaker on dispute and chargeback volume.
+//!
+//! A compromised or misbehaving upstream can push a flood of disputes and chargebacks that
+//! drain and freeze accounts in one batch. The breaker counts them over a sliding window of
+//! the most recent transactions, and once the count reaches the threshold, chargebacks are
+//! parked in [`Ledger::quarantine`] instead of being applied until an operator approves or
+//! rejects them.
+use crate::{
+    ledger::Ledger,
+    transaction::{TransactionState, TransactionType},
+};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone)]
+pub struct DisputeBreaker {
+    /// Disputes and chargebacks within the window that trip the breaker
+    pub threshold: usize,
+    /// Number of most recent transactions the count runs over
+    pub window: usize,
+    recent: VecDeque<bool>,
+    count: usize,
+    tripped: bool,
+}
+
+impl DisputeBreaker {
+    pub fn new(threshold: usize, window: usize) -> Self {
+        Self {
+            threshold,
+            window,
+            recent: VecDeque::with_capacity(window),
+            count: 0,
+            tripped: false,
+        }
+    }
+
+    /// Counts one transaction towards the window. Returns whether the breaker is open.
+    pub fn record(&mut self, tx_type: &TransactionType) -> bool {
+        let counted = matches!(
+            tx_type,
+            TransactionType::Dispute | TransactionType::Chargeback
+        );
+        self.recent.push_back(counted);
+        self.count += counted as usize;
+        if self.recent.len() > self.window {
+            self.count -= self.recent.pop_front().unwrap_or(false) as usize;
+        }
+
+        // once open, the breaker stays open until an operator resets it
+        self.tripped |= self.count >= self.threshold;
+        self.tripped
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.tripped
+    }
+
+    /// Closes the breaker and starts counting from scratch.
+    pub fn reset(&mut self) {
+        self.recent.clear();
+        self.count = 0;
+        self.tripped = false;
+    }
+}
+
+impl Ledger {
+    /// Whether an installed breaker is open and holding back chargebacks
+    pub fn breaker_tripped(&self) -> bool {
+        self.breaker
+            .as_ref()
+            .is_some_and(DisputeBreaker::is_tripped)
+    }
+
+    /// Closes the installed breaker, if any, so chargebacks are applied again. Those
+    /// already quarantined stay there until approved or rejected.
+    pub fn reset_breaker(&mut self) {
+        if let Some(breaker) = self.breaker.as_mut() {
+            breaker.reset();
+        }
+    }
+
+    /// Counts `tx` towards the breaker, if one is installed, and parks it in the
+    /// quarantine when it is a chargeback arriving while the breaker is open. Returns
+    /// whether the transaction was parked.
+    pub(crate) fn quarantine_if_tripped(&mut self, tx: &TransactionState) -> bool {
+        let Some(breaker) = self.breaker.as_mut() else {
+            return false;
+        };
+
+        if breaker.record(&tx.tx_type) && tx.tx_type == TransactionType::Chargeback {
+            self.quarantine.push(tx.clone());
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{amount, ledger_with_accounts, tx};
+
+    #[test]
+    fn test_breaker_counts_over_sliding_window() {
+        let mut breaker = DisputeBreaker::new(2, 3);
+        assert!(!breaker.record(&TransactionType::Dispute));
+        assert!(!breaker.record(&TransactionType::Deposit));
+        assert!(!breaker.record(&TransactionType::Deposit));
+        // the first dispute has left the window
+        assert!(!breaker.record(&TransactionType::Chargeback));
+        assert!(breaker.record(&TransactionType::Dispute));
+
+        // stays open until reset
+        assert!(breaker.record(&TransactionType::Deposit));
+        breaker.reset();
+        assert!(!breaker.is_tripped());
+    }
+
+    #[test]
+    fn test_chargebacks_quarantined_while_tripped() {
+        let mut ledger = ledger_with_accounts(&[(1, "10.0"), (2, "10.0")]);
+        ledger.breaker = Some(DisputeBreaker::new(2, 10));
+
+        ledger.process_transaction(tx::dispute(1, 1)).unwrap();
+        ledger.process_transaction(tx::dispute(2, 2)).unwrap();
+        ledger.process_transaction(tx::chargeback(1, 1)).unwrap();
+        ledger.process_transaction(tx::chargeback(2, 2)).unwrap();
+
+        assert_eq!(ledger.quarantine.len(), 2);
+        assert!(!ledger.accounts[&1].is_locked());
+        assert_eq!(ledger.accounts[&1].held_funds, amount("10.0"));
+
+        ledger.approve_quarantined(1).unwrap();
+        assert!(ledger.accounts[&1].is_locked());
+        assert_eq!(ledger.accounts[&1].total_funds, amount("0"));
+
+        ledger.reject_quarantined(2).unwrap();
+        assert!(ledger.quarantine.is_empty());
+        assert!(!ledger.accounts[&2].is_locked());
+        assert!(ledger.approve_quarantined(2).is_err());
+    }
+
+    #[test]
+    fn test_chargebacks_apply_again_after_reset() {
+        let mut ledger = ledger_with_accounts(&[(1, "10.0"), (2, "10.0")]);
+        ledger.breaker = Some(DisputeBreaker::new(2, 10));
+        ledger.process_transaction(tx::dispute(1, 1)).unwrap();
+        ledger.process_transaction(tx::dispute(2, 2)).unwrap();
+        ledger.process_transaction(tx::chargeback(1, 1)).unwrap();
+        assert!(ledger.breaker_tripped());
+        assert_eq!(ledger.quarantine.len(), 1);
+
+        ledger.reset_breaker();
+        assert!(!ledger.breaker_tripped());
+        ledger.process_transaction(tx::chargeback(2, 2)).unwrap();
+        assert!(ledger.accounts[&2].is_locked());
+        assert_eq!(ledger.quarantine.len(), 1);
+    }
+}
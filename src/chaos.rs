@@ -0,0 +1,144 @@
+//! Fault injection for resilience tests, behind the `chaos` feature.
+//!
+//! [`FaultySource`] wraps any [`TransactionSource`] and misbehaves on cue: it can slow
+//! every row down, hang up partway through, fail with an error or panic, each after a
+//! fixed number of rows so the test that uses it is deterministic.
+use crate::source::{Row, SourceFuture, TransactionSource};
+use anyhow::bail;
+use std::time::Duration;
+use tokio::sync::mpsc::{channel, Sender};
+
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    /// Wait this long before handing over each row
+    pub delay: Option<Duration>,
+    /// Drop the channel after this many rows, as if the source had hung up mid-stream
+    pub hang_up_after: Option<usize>,
+    /// Fail with an error after this many rows
+    pub fail_after: Option<usize>,
+    /// Panic after this many rows
+    pub panic_after: Option<usize>,
+}
+
+pub struct FaultySource {
+    pub inner: Box<dyn TransactionSource>,
+    pub faults: Faults,
+}
+
+impl TransactionSource for FaultySource {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn read(self: Box<Self>, output: Sender<Row>) -> SourceFuture {
+        let Self { inner, faults } = *self;
+        Box::pin(async move {
+            let (sender, mut rows) = channel(1);
+            let reading = tokio::spawn(inner.read(sender));
+
+            let mut sent = 0;
+            while let Some(row) = rows.recv().await {
+                if faults.hang_up_after == Some(sent) {
+                    return Ok(());
+                }
+                if faults.fail_after == Some(sent) {
+                    bail!("injected failure after {sent} rows");
+                }
+                if faults.panic_after == Some(sent) {
+                    panic!("injected panic after {sent} rows");
+                }
+                if let Some(delay) = faults.delay {
+                    tokio::time::sleep(delay).await;
+                }
+                if output.send(row).await.is_err() {
+                    break;
+                }
+                sent += 1;
+            }
+
+            reading.await?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{command::Command, sink::CsvStdout, test_support::tx};
+    use clap::Parser;
+
+    fn faulty(faults: Faults) -> Box<dyn TransactionSource> {
+        Box::new(FaultySource {
+            inner: Box::new(vec![
+                tx::deposit(1, 1, "1.0"),
+                tx::deposit(1, 2, "1.0"),
+                tx::deposit(1, 3, "1.0"),
+            ]),
+            faults,
+        })
+    }
+
+    async fn read_all(source: Box<dyn TransactionSource>) -> (anyhow::Result<()>, Vec<u32>) {
+        let (sender, mut receiver) = channel(8);
+        let result = source.read(sender).await;
+        let mut ids = vec![];
+        while let Some((_, transaction)) = receiver.recv().await {
+            ids.push(transaction.tx);
+        }
+        (result, ids)
+    }
+
+    #[tokio::test]
+    async fn test_delayed_rows_all_arrive() {
+        let (result, ids) = read_all(faulty(Faults {
+            delay: Some(Duration::from_millis(1)),
+            ..Faults::default()
+        }))
+        .await;
+        assert!(result.is_ok());
+        assert_eq!(ids, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_hang_up_ends_input_early() {
+        let (result, ids) = read_all(faulty(Faults {
+            hang_up_after: Some(1),
+            ..Faults::default()
+        }))
+        .await;
+        assert!(result.is_ok());
+        assert_eq!(ids, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_failure_after_rows() {
+        let (result, ids) = read_all(faulty(Faults {
+            fail_after: Some(2),
+            ..Faults::default()
+        }))
+        .await;
+        assert!(result.is_err());
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_failing_or_panicking_source_fails_the_run() {
+        let command = Command::parse_from(["mini-payments-engine", "unused.csv"]);
+        for faults in [
+            Faults {
+                fail_after: Some(1),
+                ..Faults::default()
+            },
+            Faults {
+                panic_after: Some(1),
+                ..Faults::default()
+            },
+        ] {
+            let err = command
+                .run_with_sources(vec![faulty(faults)], Box::new(CsvStdout))
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("memory"), "{err:#}");
+        }
+    }
+}
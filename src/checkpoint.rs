@@ -0,0 +1,277 @@
+//! Checkpoints for resuming an interrupted run.
+//!
+//! Every `--checkpoint-every` rows the ledger's state is written to the checkpoint file
+//! together with the last line of each input that reached the ledger, replacing the
+//! previous checkpoint in one rename so a crash mid-write leaves the old one intact.
+//! `--resume` restores the ledger and skips each input's rows up to its saved line. The
+//! inputs are still read from the start, but nothing before the checkpoint is applied
+//! twice. The dispute breaker's window is not saved and starts out empty after a resume.
+//! With the `aws` or `gcs` feature the checkpoint can also be an `s3://` or `gs://`
+//! object, which is replaced in one request.
+use crate::{
+    account::{Account, LockReason},
+    ledger::{Client, Ledger, TransactionId},
+    summary::SourceTotals,
+    transaction::TransactionState,
+};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+/// Borrowed view of the state written out, mirroring [`Checkpoint`]
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    totals: &'a [SourceTotals],
+    lines: &'a [u64],
+    accounts: Vec<&'a Account>,
+    locks: Vec<(Client, &'a LockReason, TransactionId)>,
+    suspensions: Vec<(Client, SystemTime)>,
+    history: Vec<&'a TransactionState>,
+    unprocessed: &'a VecDeque<TransactionState>,
+    skipped: &'a BTreeSet<TransactionId>,
+    generated: &'a BTreeSet<TransactionId>,
+    quarantine: &'a [TransactionState],
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Checkpoint {
+    /// Per-input counts so far, which also name the inputs the checkpoint was taken over
+    pub totals: Vec<SourceTotals>,
+    /// Last line of each input that reached the ledger
+    pub lines: Vec<u64>,
+    pub accounts: Vec<Account>,
+    /// What froze each frozen account, which the accounts as written leave out
+    #[serde(default)]
+    pub locks: Vec<(Client, LockReason, TransactionId)>,
+    /// When each suspended account goes back to active, also left out of the accounts
+    #[serde(default)]
+    pub suspensions: Vec<(Client, SystemTime)>,
+    /// History entries in the order they were added
+    pub history: Vec<TransactionState>,
+    pub unprocessed: VecDeque<TransactionState>,
+    /// Ids skipped by a client filter after the last history entry
+    #[serde(default)]
+    pub skipped: BTreeSet<TransactionId>,
+    /// Ids of history entries the engine created rather than read from an input
+    #[serde(default)]
+    pub generated: BTreeSet<TransactionId>,
+    pub quarantine: Vec<TransactionState>,
+}
+
+impl<'a> CheckpointRef<'a> {
+    fn new(ledger: &'a Ledger, totals: &'a [SourceTotals], lines: &'a [u64]) -> Self {
+        CheckpointRef {
+            totals,
+            lines,
+            accounts: ledger.accounts.values().collect(),
+            locks: ledger
+                .accounts
+                .values()
+                .filter_map(|account| {
+                    Some((
+                        account.client_id,
+                        account.locked_reason.as_ref()?,
+                        account.locked_by_tx?,
+                    ))
+                })
+                .collect(),
+            suspensions: ledger
+                .accounts
+                .values()
+                .filter_map(|account| Some((account.client_id, account.suspended_until?)))
+                .collect(),
+            history: ledger.history.values().collect(),
+            unprocessed: &ledger.unprocessed,
+            skipped: &ledger.skipped,
+            generated: &ledger.generated,
+            quarantine: &ledger.quarantine,
+        }
+    }
+}
+
+impl Checkpoint {
+    /// Writes the ledger and the progress through each input to `path`.
+    pub fn save(
+        path: &Path,
+        ledger: &Ledger,
+        totals: &[SourceTotals],
+        lines: &[u64],
+    ) -> Result<()> {
+        let checkpoint = CheckpointRef::new(ledger, totals, lines);
+        let partial = path.with_extension("partial");
+        let mut writer = BufWriter::new(
+            File::create(&partial)
+                .with_context(|| format!("creating checkpoint {}", partial.display()))?,
+        );
+        serde_json::to_writer(&mut writer, &checkpoint)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&partial, path)
+            .with_context(|| format!("replacing checkpoint {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("opening checkpoint {}", path.display()))?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("reading checkpoint {}", path.display()))
+    }
+
+    /// Like [`Checkpoint::save`], but `location` may also be an object url.
+    pub async fn save_to(
+        location: &Path,
+        ledger: &Ledger,
+        totals: &[SourceTotals],
+        lines: &[u64],
+    ) -> Result<()> {
+        #[cfg(feature = "aws")]
+        if let Some(url) = object_url(location) {
+            let checkpoint = serde_json::to_vec(&CheckpointRef::new(ledger, totals, lines))?;
+            return crate::s3::put_object(url, checkpoint).await;
+        }
+        Self::save(location, ledger, totals, lines)
+    }
+
+    /// Like [`Checkpoint::load`], but `location` may also be an object url.
+    pub async fn load_from(location: &Path) -> Result<Self> {
+        #[cfg(feature = "aws")]
+        if let Some(url) = object_url(location) {
+            let checkpoint = crate::s3::get_object(url).await?;
+            return serde_json::from_slice(&checkpoint)
+                .with_context(|| format!("reading checkpoint {url}"));
+        }
+        Self::load(location)
+    }
+
+    /// Removes the checkpoint of a finished run, if one was written.
+    pub async fn remove(location: &Path) {
+        #[cfg(feature = "aws")]
+        if let Some(url) = object_url(location) {
+            let _ = crate::s3::delete_object(url).await;
+            return;
+        }
+        let _ = fs::remove_file(location);
+    }
+
+    /// Checks the checkpoint was taken over the same inputs, in the same order.
+    pub fn check_inputs(&self, names: &[String]) -> Result<()> {
+        let saved: Vec<&str> = self
+            .totals
+            .iter()
+            .map(|totals| totals.name.as_str())
+            .collect();
+        if saved != names {
+            bail!(
+                "checkpoint was taken over {}, not {}",
+                saved.join(", "),
+                names.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Puts the saved state back into `ledger`, returning the per-input counts and lines.
+    pub fn restore(self, ledger: &mut Ledger) -> (Vec<SourceTotals>, Vec<u64>) {
+        ledger.accounts = self
+            .accounts
+            .into_iter()
+            .map(|account| (account.client_id, account))
+            .collect();
+        for (client, reason, tx) in self.locks {
+            if let Some(account) = ledger.accounts.get_mut(&client) {
+                account.locked_reason = Some(reason);
+                account.locked_by_tx = Some(tx);
+            }
+        }
+        for (client, until) in self.suspensions {
+            if let Some(account) = ledger.accounts.get_mut(&client) {
+                account.suspended_until = Some(until);
+            }
+        }
+        ledger.history = self
+            .history
+            .into_iter()
+            .map(|transaction| (transaction.tx, transaction))
+            .collect();
+        ledger.unprocessed = self.unprocessed;
+        ledger.skipped = self.skipped;
+        ledger.generated = self.generated;
+        ledger.quarantine = self.quarantine;
+        (self.totals, self.lines)
+    }
+}
+
+#[cfg(feature = "aws")]
+fn object_url(location: &Path) -> Option<&str> {
+    location
+        .to_str()
+        .filter(|location| location.starts_with("s3://") || location.starts_with("gs://"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::AccountStatus,
+        test_support::{amount, ledger_with_accounts, tx},
+    };
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let mut ledger = ledger_with_accounts(&[(1, "10.5"), (2, "3.25"), (3, "1.0")]);
+        ledger.process_transaction(tx::dispute(1, 1)).unwrap();
+        ledger
+            .process_transaction(tx::deposit(2, 9, "1.0"))
+            .unwrap();
+        ledger.process_transaction(tx::dispute(3, 3)).unwrap();
+        ledger.process_transaction(tx::chargeback(3, 3)).unwrap();
+        let until = UNIX_EPOCH + Duration::from_secs(60);
+        ledger.suspend(2, until).unwrap();
+        let mut totals = SourceTotals::new(String::from("in.csv"));
+        totals.rows = 4;
+
+        let path = std::env::temp_dir().join(format!("mpe-{}-checkpoint.json", std::process::id()));
+        Checkpoint::save(&path, &ledger, &[totals], &[5]).unwrap();
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(checkpoint.check_inputs(&[String::from("in.csv")]).is_ok());
+        assert!(checkpoint
+            .check_inputs(&[String::from("other.csv")])
+            .is_err());
+
+        let mut restored = Ledger::new();
+        let (totals, lines) = checkpoint.restore(&mut restored);
+        assert_eq!(totals[0].rows, 4);
+        assert_eq!(lines, vec![5]);
+        assert_eq!(restored.accounts[&1].held_funds, amount("10.5"));
+        assert_eq!(restored.accounts[&2].total_funds, amount("3.25"));
+        assert_eq!(
+            restored.accounts[&3].locked_reason,
+            Some(LockReason::Chargeback)
+        );
+        assert_eq!(restored.accounts[&3].locked_by_tx, Some(3));
+        assert_eq!(restored.accounts[&2].status, AccountStatus::Suspended);
+        assert_eq!(restored.accounts[&2].suspended_until, Some(until));
+        assert!(restored.history[&1].disputed);
+        assert_eq!(restored.unprocessed.len(), 1);
+
+        // the restored ledger carries on where the original left off
+        restored.process_transaction(tx::resolve(1, 1)).unwrap();
+        assert_eq!(restored.accounts[&1].available_funds, amount("10.5"));
+        // and the suspension, long run out by the system clock, ends with the next
+        // transaction for the account
+        restored
+            .process_transaction(tx::deposit(2, 4, "1.0"))
+            .unwrap();
+        assert_eq!(restored.accounts[&2].status, AccountStatus::Active);
+    }
+}
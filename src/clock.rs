@@ -0,0 +1,51 @@
+//! Where the ledger gets the time from.
+//!
+//! Suspensions run out at a point in time, checked as each transaction for the account
+//! is processed. The ledger asks its [`Clock`] for the time rather than the system, so
+//! a replay or a test can pin it with a [`ManualClock`] and get the same balances however
+//! fast the input is processed. Ledgers use the [`SystemClock`] unless another one is
+//! installed with [`crate::ledger::LedgerBuilder::clock`].
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The system's wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the time, so keep one to move the
+/// clock of the ledger it was installed on.
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<Mutex<SystemTime>>);
+
+impl ManualClock {
+    pub fn at(now: SystemTime) -> Self {
+        Self(Arc::new(Mutex::new(now)))
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *self.0.lock().expect("clock poisoned") = now;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().expect("clock poisoned") += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().expect("clock poisoned")
+    }
+}
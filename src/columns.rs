@@ -0,0 +1,115 @@
+//! Computed columns added to the account report.
+//!
+//! A [`ReportColumn`] registered with [`crate::ledger::LedgerBuilder::column`] is called
+//! once per account when the report is written, with the account and the ledger's
+//! history, and its value is appended to the account's row under the column's name. This
+//! lets embedders report things like a risk score or a dispute ratio without touching the
+//! report writer.
+use crate::{account::Account, ledger::History};
+use serde_json::Value;
+
+pub type ReportColumn = fn(&Account, &History) -> Value;
+
+/// The columns `--extended-report` adds: what froze each frozen account
+pub const EXTENDED: [(&str, ReportColumn); 2] = [
+    ("locked_reason", locked_reason),
+    ("locked_by_tx", locked_by_tx),
+];
+
+/// `chargeback`, or the custom transaction type whose handler froze the account
+pub fn locked_reason(account: &Account, _: &History) -> Value {
+    account
+        .locked_reason
+        .as_ref()
+        .map_or(Value::Null, |reason| Value::from(reason.to_string()))
+}
+
+/// Id of the transaction that froze the account
+pub fn locked_by_tx(account: &Account, _: &History) -> Value {
+    account.locked_by_tx.map_or(Value::Null, Value::from)
+}
+
+/// The csv field for a column value: strings as they are, nothing for null and json for
+/// anything else.
+pub(crate) fn field(value: Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text,
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ledger::Ledger, test_support::tx, transaction::TransactionType};
+    use serde_json::json;
+
+    fn dispute_ratio(account: &Account, history: &History) -> Value {
+        let deposits = history.values().filter(|transaction| {
+            transaction.client == account.client_id
+                && transaction.tx_type == TransactionType::Deposit
+        });
+        let (mut total, mut disputed) = (0, 0);
+        for deposit in deposits {
+            total += 1;
+            if deposit.disputed {
+                disputed += 1;
+            }
+        }
+        json!(disputed as f64 / total as f64)
+    }
+
+    fn tier(account: &Account, _: &History) -> Value {
+        match account.client_id {
+            1 => json!("gold"),
+            _ => Value::Null,
+        }
+    }
+
+    #[test]
+    fn test_columns_are_appended_to_report() {
+        let mut ledger = Ledger::builder()
+            .column("dispute_ratio", dispute_ratio)
+            .column("tier", tier)
+            .build();
+        for transaction in [
+            tx::deposit(1, 1, "1.0000"),
+            tx::deposit(1, 2, "1.0000"),
+            tx::dispute(1, 1),
+        ] {
+            ledger.process_transaction(transaction).unwrap();
+        }
+
+        let mut report = vec![];
+        ledger.write_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client_id,available_funds,held_funds,total_funds,locked,status,sequence,dispute_ratio,tier\n\
+             1,1.0000,1.0000,2.0000,false,active,3,0.5,gold\n"
+        );
+    }
+
+    #[test]
+    fn test_extended_columns_show_lock_cause() {
+        let mut ledger = Ledger::new();
+        ledger.columns = EXTENDED
+            .map(|(name, column)| (name.to_string(), column))
+            .into();
+        for transaction in [
+            tx::deposit(1, 1, "1.0000"),
+            tx::dispute(1, 1),
+            tx::chargeback(1, 1),
+        ] {
+            ledger.process_transaction(transaction).unwrap();
+        }
+
+        let mut report = vec![];
+        ledger.write_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client_id,available_funds,held_funds,total_funds,locked,status,sequence,locked_reason,locked_by_tx\n\
+             1,0.0000,0.0000,0.0000,true,frozen,3,chargeback,1\n"
+        );
+    }
+}
@@ -1,40 +1,1040 @@
-use crate::{ledger::Ledger, reader::reader, writer::output_report};
-use anyhow::Result;
-use clap::Parser;
-use std::path::PathBuf;
+use crate::{
+    amount::{Amount, AmountFormat},
+    batching::{batch, BatchSize, BATCH_QUEUE},
+    breaker::DisputeBreaker,
+    checkpoint::Checkpoint,
+    columns,
+    dedup::DedupWindow,
+    explain::Locale,
+    export::export_history,
+    filter::{ClientRanges, SampleRate},
+    latency::Latency,
+    ledger::{Ledger, Refusal, TransactionId},
+    reader::merge,
+    rejected::{write_refused, write_rejected, RefusedTransaction, RejectedRow, RejectedRows},
+    report_files::{ReportCompression, ReportFiles},
+    rollup::Groupings,
+    sink::{CsvFile as CsvFileSink, CsvStdout, ReportSink},
+    source::{
+        Chain, CsvFile, CsvLayout, CsvQuirks, FollowedCsvFile, JsonLinesFile, TextEncoding,
+        TransactionSource,
+    },
+    summary::{RunSummary, SourceTotals},
+    throttle::throttle,
+    timing::{timed, Timing},
+    transaction::{Transaction, TransactionState, TransactionType},
+};
+use anyhow::{bail, Context, Result};
+use clap::{Parser, ValueEnum};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 use tokio::{
     spawn,
-    sync::{mpsc::channel, oneshot},
+    sync::{mpsc::channel, oneshot, watch},
 };
 
+/// Layout of the input file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// Comma separated with a `type, client, tx, amount` header row
+    #[default]
+    Csv,
+    /// One json object per line with `type`, `client`, `tx` and an optional `amount`
+    Jsonl,
+    /// Avro container file whose records have `type`, `client`, `tx` and an optional
+    /// `amount` field. Needs a build with the avro feature
+    Avro,
+    /// Length-delimited `payments.v1.Transaction` messages as defined in
+    /// `proto/transaction.proto`. Needs a build with the protobuf feature
+    Protobuf,
+    /// A sequence of MessagePack maps or arrays with `type`, `client`, `tx` and an optional
+    /// `amount`. Needs a build with the msgpack feature
+    Msgpack,
+    /// Excel workbook whose first sheet, or the one named by --sheet, is laid out like a
+    /// csv file. Needs a build with the xlsx feature
+    Xlsx,
+    /// Arrow IPC file, such as a Feather file, or stream whose batches have `type`,
+    /// `client`, `tx` and an optional `amount` column. Needs a build with the arrow feature
+    Arrow,
+}
+
 #[derive(Debug, Parser)]
 pub struct Command {
-    /// Csv input file
-    pub input_file: PathBuf,
+    /// Input files, csv urls when built with the http or aws feature, or
+    /// `sqlite://path?table=name` when built with the sqlite feature. A directory or a
+    /// quoted glob pattern such as 'txns/*.csv' reads every matching file in lexicographic
+    /// order as one input. Several inputs are merged into one stream ordered by
+    /// transaction id, so each of them should be in id order itself
+    #[arg(required = true)]
+    pub input_files: Vec<PathBuf>,
+
+    /// Format of the input file
+    #[arg(long, value_enum, default_value_t = InputFormat::Csv)]
+    pub format: InputFormat,
+
+    /// Sheet of an xlsx workbook to read instead of the first one
+    #[arg(long)]
+    pub sheet: Option<String>,
+
+    /// The csv input has no header row; its columns are `type, client, tx, amount`
+    #[arg(long)]
+    pub no_headers: bool,
+
+    /// Field separator of the csv input, e.g. `|` or `;`
+    #[arg(long, default_value_t = ',')]
+    pub delimiter: char,
+
+    /// One-based positions of the type, client, tx and amount columns, e.g. `4,3,1,5`
+    /// for rows laid out as `tx, memo, client, type, amount`. Other columns are ignored
+    #[arg(long, value_delimiter = ',', num_args = 1)]
+    pub columns: Option<Vec<usize>>,
+
+    /// Character encoding of the csv input, e.g. `windows-1252` or `utf-16le`, or `auto` to
+    /// tell it from the start of the input
+    #[arg(long, default_value = "utf-8")]
+    pub encoding: TextEncoding,
+
+    /// Skip header rows repeated further down a csv input and ignore empty columns after
+    /// the amount, with a warning, instead of failing on them
+    #[arg(long, value_enum, default_value_t = CsvQuirks::Fail)]
+    pub csv_quirks: CsvQuirks,
+
+    /// Fail on amounts in scientific notation, with thousands separators or with more than
+    /// 4 decimal places instead of reading them
+    #[arg(long)]
+    pub strict_amounts: bool,
+
+    /// Recompute every balance from the history once the batch is done and fail on any mismatch
+    #[arg(long)]
+    pub self_check: bool,
+
+    /// Parse the input with the SIMD fast path, which only supports unquoted four-column csv
+    #[arg(long)]
+    pub fast_csv: bool,
+
+    /// Keep reading the csv input as rows are appended to it, like `tail -f`, until
+    /// interrupted. Compressed input and --format jsonl are not supported
+    #[arg(long)]
+    pub follow: bool,
+
+    /// Seconds between account reports while following the input
+    #[arg(long, default_value_t = 10)]
+    pub report_interval: u64,
+
+    /// Milliseconds to wait before checking a followed input for new rows again
+    #[arg(long, default_value_t = 200)]
+    pub poll_interval: u64,
+
+    /// Keep going past rejected rows, explaining each one on stderr
+    #[arg(long)]
+    pub verbose_rejects: bool,
+
+    /// Drop rows repeating the tx id and type of one of this many preceding rows, such
+    /// as rows sent twice by an upstream retry
+    #[arg(long)]
+    pub dedup_window: Option<usize>,
+
+    /// Only apply the rows of these clients, given as ids and ranges like 1-100,250, and
+    /// drop the rest
+    #[arg(long)]
+    pub only_clients: Option<ClientRanges>,
+
+    /// Only apply the rows of this share of the clients, like `1%`, picked by client id
+    /// so that every row of a picked client is kept, and drop the rest
+    #[arg(long)]
+    pub sample: Option<SampleRate>,
+
+    /// Seed picking the clients kept by --sample
+    #[arg(long, default_value_t = 0)]
+    pub sample_seed: u64,
+
+    /// Skip rows of a transaction type nothing handles, such as a typo, and list them at
+    /// the end instead of failing the run on them
+    #[arg(long)]
+    pub skip_unknown_types: bool,
+
+    /// Record csv rows that cannot be read, with their line number, content and error, in
+    /// this csv file instead of stopping the run at the first one
+    #[arg(long)]
+    pub rejected_rows: Option<PathBuf>,
+
+    /// Keep going past transactions the ledger refuses, writing each one with the reason
+    /// to this csv file
+    #[arg(long)]
+    pub errors: Option<PathBuf>,
+
+    /// Write every transaction in the ledger history, with its dispute flag and final
+    /// status, to this file once the run is done: json for a .json name, JSON Lines for
+    /// .jsonl and csv otherwise
+    #[arg(long)]
+    pub export_history: Option<PathBuf>,
+
+    /// Quarantine chargebacks once this many disputes and chargebacks arrive within
+    /// --breaker-window transactions
+    #[arg(long)]
+    pub breaker_threshold: Option<usize>,
+
+    /// Number of most recent transactions the dispute circuit breaker counts over
+    #[arg(long, default_value_t = 1000)]
+    pub breaker_window: usize,
+
+    /// Quarantine deposits and withdrawals above this amount instead of applying them
+    #[arg(long)]
+    pub max_amount: Option<Amount>,
+
+    /// File of transaction ids, one per line, approved to go past --max-amount
+    #[arg(long)]
+    pub approvals: Option<PathBuf>,
+
+    /// Print amount-distribution statistics and the clients they flag as unusual to stderr
+    #[arg(long)]
+    pub anomalies: bool,
+
+    /// Print total available and held funds, the largest client exposure and the amount
+    /// under open dispute to stderr
+    #[arg(long)]
+    pub risk: bool,
+
+    /// Add the locked_reason and locked_by_tx columns to the report
+    #[arg(long)]
+    pub extended_report: bool,
+
+    /// Write the report in client id order, so the same input always gives the same report
+    #[arg(long)]
+    pub sort: bool,
+
+    /// Csv file mapping clients to groups, with a `client` column followed by one column
+    /// per grouping such as `desk` or `region`. Balances rolled up per group are printed
+    /// to stderr
+    #[arg(long)]
+    pub groups: Option<PathBuf>,
+
+    /// Periodically save the ledger and the progress through each input to this file,
+    /// so an interrupted run can pick up from it with --resume
+    #[arg(long)]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Rows between checkpoints
+    #[arg(long, default_value_t = 100_000)]
+    pub checkpoint_every: u64,
+
+    /// Restore the ledger from --checkpoint and skip the rows it already covers
+    #[arg(long, requires = "checkpoint")]
+    pub resume: bool,
+
+    /// Print a json breakdown of time spent reading, applying and writing to stderr
+    #[arg(long)]
+    pub timing: bool,
+
+    /// Write an HdrHistogram interval log of how long rows took from being read to being
+    /// processed, with an interval per --report-interval when following a file
+    #[arg(long)]
+    pub latency_log: Option<PathBuf>,
+
+    /// Write the report to this file instead of stdout, replacing it only once the whole
+    /// report is written
+    #[arg(long, short, conflicts_with = "report_dir")]
+    pub output: Option<PathBuf>,
+
+    /// Write the report into numbered chunk files with a manifest.json in this directory
+    /// instead of stdout
+    #[arg(long)]
+    pub report_dir: Option<PathBuf>,
+
+    /// Compression of the report chunks
+    #[arg(long, value_enum, default_value_t = ReportCompression::None, requires = "report_dir")]
+    pub report_compression: ReportCompression,
+
+    /// Most bytes of csv, before compression, in one report chunk
+    #[arg(long, requires = "report_dir")]
+    pub report_chunk_bytes: Option<u64>,
+
+    /// Json file mapping hosts to the headers, such as Authorization, sent with every
+    /// request for a url input on that host
+    #[cfg(feature = "http")]
+    #[arg(long)]
+    pub http_headers: Option<PathBuf>,
+
+    /// Stream the report into this `s3://` or `gs://` object instead of stdout
+    #[cfg(feature = "aws")]
+    #[arg(long, conflicts_with_all = ["report_dir", "output"])]
+    pub report_url: Option<String>,
+
+    /// Largest batch of rows handed to the ledger at once when it falls behind the readers
+    #[arg(long, default_value_t = 256)]
+    pub max_batch: usize,
+
+    /// Most transactions a second pulled from the inputs into the ledger, to keep a
+    /// replay from hammering downstream sinks
+    #[arg(long)]
+    pub max_tps: Option<u32>,
+
+    /// Language of the --verbose-rejects explanations
+    #[arg(long, value_enum, default_value_t = Locale::En)]
+    pub locale: Locale,
+
+    /// Slack or Teams incoming webhook to post a run summary to once the batch finishes
+    #[cfg(feature = "webhook")]
+    #[arg(long)]
+    pub summary_webhook: Option<String>,
 }
 
 impl Command {
-    pub async fn run(&self) -> Result<()> {
-        let (tx, mut rx) = channel(100);
+    /// The sink the report flags ask for: an object with --report-url, a file with
+    /// --output, chunk files with --report-dir and csv on stdout otherwise.
+    pub fn report_sink(&self) -> Box<dyn ReportSink> {
+        #[cfg(feature = "aws")]
+        if let Some(url) = &self.report_url {
+            return Box::new(crate::sink::ObjectUpload(url.clone()));
+        }
+        match (&self.output, &self.report_dir) {
+            (Some(path), _) => Box::new(CsvFileSink(path.clone())),
+            (None, Some(dir)) => Box::new(ReportFiles {
+                dir: dir.clone(),
+                compression: self.report_compression,
+                chunk_bytes: self.report_chunk_bytes,
+            }),
+            (None, None) => Box::new(CsvStdout),
+        }
+    }
+
+    /// Runs the batch over the input files, writing the report into `sink`.
+    pub async fn run(&self, sink: Box<dyn ReportSink>) -> Result<()> {
+        for remote in self.input_files.iter().filter(|path| {
+            is_url(path)
+                || is_s3_url(path)
+                || is_gs_url(path)
+                || is_azure_url(path)
+                || is_nats_url(path)
+                || is_amqp_url(path)
+                || is_sqlite_url(path)
+        }) {
+            let (feature, enabled) = if is_url(remote) {
+                ("http", cfg!(feature = "http"))
+            } else if is_s3_url(remote) {
+                ("aws", cfg!(feature = "aws"))
+            } else if is_gs_url(remote) {
+                ("gcs", cfg!(feature = "gcs"))
+            } else if is_azure_url(remote) {
+                ("azure", cfg!(feature = "azure"))
+            } else if is_nats_url(remote) {
+                ("nats", cfg!(feature = "nats"))
+            } else if is_amqp_url(remote) {
+                ("amqp", cfg!(feature = "amqp"))
+            } else {
+                ("sqlite", cfg!(feature = "sqlite"))
+            };
+            if !enabled {
+                bail!(
+                    "reading {} needs a build with the {feature} feature",
+                    remote.display()
+                );
+            }
+            if is_amqp_url(remote) && self.checkpoint.is_some() {
+                // delivery tags start over with every connection
+                bail!("amqp inputs cannot be checkpointed, their rows are renumbered every run");
+            }
+            if is_nats_url(remote) || is_amqp_url(remote) || is_sqlite_url(remote) {
+                if self.follow {
+                    bail!("--follow reads a single file, not {}", remote.display());
+                }
+                // nats and amqp messages are always json and sqlite rows are columns,
+                // whatever --format says
+                continue;
+            }
+            if self.follow || self.format != InputFormat::Csv {
+                bail!("url inputs are only supported as csv without --follow");
+            }
+        }
+        if let Some(checkpoint) = &self.checkpoint {
+            if is_s3_url(checkpoint) && !cfg!(feature = "aws") {
+                bail!("checkpointing to s3 needs a build with the aws feature");
+            }
+            if is_gs_url(checkpoint) && !cfg!(feature = "gcs") {
+                bail!("checkpointing to gcs needs a build with the gcs feature");
+            }
+        }
+        #[cfg(feature = "aws")]
+        if let Some(url) = self.report_url.as_deref().map(Path::new) {
+            if !is_s3_url(url) && !is_gs_url(url) {
+                bail!("--report-url takes an s3:// or gs:// object");
+            }
+            if is_gs_url(url) && !cfg!(feature = "gcs") {
+                bail!("writing the report to gcs needs a build with the gcs feature");
+            }
+            if self.follow {
+                bail!(
+                    "--report-url only writes the final report, and cannot be used with --follow"
+                );
+            }
+        }
+        if self.format == InputFormat::Avro && !cfg!(feature = "avro") {
+            bail!("--format avro needs a build with the avro feature");
+        }
+        if self.format == InputFormat::Protobuf && !cfg!(feature = "protobuf") {
+            bail!("--format protobuf needs a build with the protobuf feature");
+        }
+        if self.format == InputFormat::Msgpack && !cfg!(feature = "msgpack") {
+            bail!("--format msgpack needs a build with the msgpack feature");
+        }
+        if self.format == InputFormat::Xlsx && !cfg!(feature = "xlsx") {
+            bail!("--format xlsx needs a build with the xlsx feature");
+        }
+        if self.format == InputFormat::Arrow && !cfg!(feature = "arrow") {
+            bail!("--format arrow needs a build with the arrow feature");
+        }
+        if self.sheet.is_some() && self.format != InputFormat::Xlsx {
+            bail!("--sheet only applies to --format xlsx");
+        }
+        if self.follow && self.format != InputFormat::Csv {
+            bail!("--follow only supports csv input");
+        }
+
+        let delimiter = match u8::try_from(self.delimiter) {
+            Ok(delimiter) if delimiter.is_ascii() => delimiter,
+            _ => bail!("--delimiter must be a single ascii character"),
+        };
+        let columns = match self.columns.as_deref().map(<[usize; 4]>::try_from) {
+            None => None,
+            Some(Ok(positions)) if !positions.contains(&0) => {
+                Some(positions.map(|position| position - 1))
+            }
+            Some(_) => bail!("--columns takes four one-based positions: type,client,tx,amount"),
+        };
+        let layout = CsvLayout {
+            headers: !self.no_headers,
+            delimiter,
+            columns,
+            amounts: if self.strict_amounts {
+                AmountFormat::STRICT
+            } else {
+                AmountFormat::default()
+            },
+            quirks: self.csv_quirks,
+            encoding: self.encoding,
+        };
+
+        let sources = self
+            .input_files
+            .iter()
+            .map(|path| self.input_source(path, layout))
+            .collect::<Result<_>>()?;
+        self.run_with_sources(sources, sink).await
+    }
+
+    /// A directory or glob pattern becomes one source reading every matching file in
+    /// lexicographic order; anything else is a single file or url.
+    fn input_source(&self, path: &Path, layout: CsvLayout) -> Result<Box<dyn TransactionSource>> {
+        // an existing file is read as is, even when its name looks like a pattern
+        let pattern = !path.exists() && is_glob(path);
+        let remote = is_url(path)
+            || is_s3_url(path)
+            || is_gs_url(path)
+            || is_azure_url(path)
+            || is_nats_url(path)
+            || is_amqp_url(path)
+            || is_sqlite_url(path);
+        if remote || !(path.is_dir() || pattern) {
+            return Ok(self.file_source(path.to_path_buf(), layout));
+        }
+        if self.follow {
+            bail!("--follow reads a single file, not {}", path.display());
+        }
+
+        let mut files = Vec::new();
+        if pattern {
+            let pattern = path.to_string_lossy();
+            for file in
+                glob::glob(&pattern).with_context(|| format!("invalid glob pattern {pattern}"))?
+            {
+                files.push(file?);
+            }
+        } else {
+            for entry in fs::read_dir(path)
+                .with_context(|| format!("listing directory {}", path.display()))?
+            {
+                files.push(entry?.path());
+            }
+        }
+        files.retain(|file| file.is_file());
+
+        if files.is_empty() {
+            bail!("no input files match {}", path.display());
+        }
+        files.sort();
+        Ok(Box::new(Chain {
+            name: path.display().to_string(),
+            sources: files
+                .into_iter()
+                .map(|file| self.file_source(file, layout))
+                .collect(),
+        }))
+    }
+
+    fn file_source(&self, path: PathBuf, layout: CsvLayout) -> Box<dyn TransactionSource> {
+        #[cfg(feature = "http")]
+        if is_url(&path) {
+            return Box::new(crate::source::CsvUrl {
+                url: path.to_string_lossy().into_owned(),
+                layout,
+                fast: self.fast_csv,
+                http_headers: self.http_headers.clone(),
+            });
+        }
+
+        #[cfg(feature = "azure")]
+        if is_azure_url(&path) {
+            return Box::new(crate::source::AzureBlob {
+                url: path.to_string_lossy().into_owned(),
+                layout,
+                fast: self.fast_csv,
+            });
+        }
+
+        #[cfg(feature = "aws")]
+        if is_s3_url(&path) || is_gs_url(&path) {
+            return Box::new(crate::source::CsvS3Object {
+                url: path.to_string_lossy().into_owned(),
+                layout,
+                fast: self.fast_csv,
+            });
+        }
+
+        #[cfg(feature = "nats")]
+        if is_nats_url(&path) {
+            return Box::new(crate::source::JetStreamConsumer::new(
+                path.to_string_lossy().into_owned(),
+                layout.amounts,
+            ));
+        }
+
+        #[cfg(feature = "amqp")]
+        if is_amqp_url(&path) {
+            return Box::new(crate::source::AmqpQueue::new(
+                path.to_string_lossy().into_owned(),
+                layout.amounts,
+            ));
+        }
+
+        #[cfg(feature = "sqlite")]
+        if is_sqlite_url(&path) {
+            return Box::new(crate::source::SqliteTable {
+                url: path.to_string_lossy().into_owned(),
+                amounts: layout.amounts,
+            });
+        }
+
+        if self.follow {
+            return Box::new(FollowedCsvFile {
+                path,
+                layout,
+                poll: Duration::from_millis(self.poll_interval),
+            });
+        }
+
+        match self.format {
+            InputFormat::Csv => Box::new(CsvFile {
+                path,
+                layout,
+                fast: self.fast_csv,
+                rejected: None,
+            }),
+            InputFormat::Jsonl => Box::new(JsonLinesFile {
+                path,
+                amounts: layout.amounts,
+            }),
+            #[cfg(feature = "avro")]
+            InputFormat::Avro => Box::new(crate::source::AvroFile {
+                path,
+                amounts: layout.amounts,
+            }),
+            // rejected in `run` before any source is built
+            #[cfg(not(feature = "avro"))]
+            InputFormat::Avro => unreachable!("avro input needs the avro feature"),
+            #[cfg(feature = "protobuf")]
+            InputFormat::Protobuf => Box::new(crate::source::ProtobufFile {
+                path,
+                amounts: layout.amounts,
+            }),
+            #[cfg(not(feature = "protobuf"))]
+            InputFormat::Protobuf => unreachable!("protobuf input needs the protobuf feature"),
+            #[cfg(feature = "msgpack")]
+            InputFormat::Msgpack => Box::new(crate::source::MessagePackFile {
+                path,
+                amounts: layout.amounts,
+            }),
+            #[cfg(not(feature = "msgpack"))]
+            InputFormat::Msgpack => unreachable!("msgpack input needs the msgpack feature"),
+            #[cfg(feature = "xlsx")]
+            InputFormat::Xlsx => Box::new(crate::source::XlsxFile {
+                path,
+                sheet: self.sheet.clone(),
+                layout,
+            }),
+            #[cfg(not(feature = "xlsx"))]
+            InputFormat::Xlsx => unreachable!("xlsx input needs the xlsx feature"),
+            #[cfg(feature = "arrow")]
+            InputFormat::Arrow => Box::new(crate::source::ArrowFile {
+                path,
+                amounts: layout.amounts,
+            }),
+            #[cfg(not(feature = "arrow"))]
+            InputFormat::Arrow => unreachable!("arrow input needs the arrow feature"),
+        }
+    }
+
+    /// Runs the batch over `sources` instead of the input files. Several sources are
+    /// merged by transaction id like several input files are.
+    pub async fn run_with_sources(
+        &self,
+        sources: Vec<Box<dyn TransactionSource>>,
+        mut sink: Box<dyn ReportSink>,
+    ) -> Result<()> {
+        let started = Instant::now();
+        let (tx, rows_rx) = channel(100);
+        let (batch_tx, mut rx) = channel(BATCH_QUEUE);
         let (tx_ledger, rx_ledger) = oneshot::channel();
-        let file = self.input_file.clone();
+        let verbose_rejects = self.verbose_rejects;
+        let collect_refused = self.errors.is_some();
+        let mut dedup = self.dedup_window.map(DedupWindow::new);
+        let only_clients = self.only_clients.clone();
+        let sample = self.sample.map(|rate| (rate, self.sample_seed));
+        let skip_unknown_types = self.skip_unknown_types;
+        let report_interval = self
+            .follow
+            .then(|| Duration::from_secs(self.report_interval.max(1)));
+        let locale = self.locale;
+        let groupings = self
+            .groups
+            .as_deref()
+            .map(Groupings::from_path)
+            .transpose()?;
+
+        let mut ledger = Ledger::new();
+        ledger.breaker = self
+            .breaker_threshold
+            .map(|threshold| DisputeBreaker::new(threshold, self.breaker_window));
+        ledger.max_amount = self.max_amount;
+        ledger.approved = match &self.approvals {
+            Some(path) => read_approvals(path)?,
+            None => HashSet::new(),
+        };
+        ledger.sort_report = self.sort;
+        if self.extended_report {
+            ledger.columns = columns::EXTENDED
+                .map(|(name, column)| (name.to_string(), column))
+                .into();
+        }
+
+        let names: Vec<String> = sources.iter().map(|source| source.name()).collect();
+        let (mut totals, mut lines) = match &self.checkpoint {
+            Some(path) if self.resume => {
+                let checkpoint = Checkpoint::load_from(path).await?;
+                checkpoint.check_inputs(&names)?;
+                checkpoint.restore(&mut ledger)
+            }
+            _ => (
+                names.iter().cloned().map(SourceTotals::new).collect(),
+                vec![0; names.len()],
+            ),
+        };
+        let mut latency = Latency::new(self.latency_log.as_deref())?;
+        // rows at or before these lines were applied before the checkpoint was taken
+        let resume_from = lines.clone();
+        let checkpoint = self.checkpoint.clone();
+        let checkpoint_every = self.checkpoint_every.max(1);
 
-        spawn(async move { reader(&file, tx).await });
+        let mut readers = Vec::with_capacity(sources.len());
+        let mut inputs = Vec::with_capacity(sources.len());
+        let mut applied = Vec::with_capacity(sources.len());
+        let mut rejected = Vec::with_capacity(sources.len());
+        for ((mut source, name), line) in sources.into_iter().zip(names).zip(&lines) {
+            let (applied_tx, applied_rx) = watch::channel(*line);
+            source.track_applied(applied_rx);
+            if self.rejected_rows.is_some() {
+                let rows = RejectedRows::default();
+                source.collect_rejected(rows.clone());
+                rejected.push((name.clone(), rows));
+            }
+            applied.push(applied_tx);
+            let (input_tx, input_rx) = channel(100);
+            readers.push((name, spawn(timed(source.read(input_tx)))));
+            inputs.push(input_rx);
+        }
+        match self.max_tps {
+            Some(max_tps) => {
+                let (merged_tx, merged_rx) = channel(100);
+                spawn(merge(inputs, merged_tx));
+                spawn(throttle(merged_rx, tx, max_tps));
+            }
+            None => {
+                spawn(merge(inputs, tx));
+            }
+        }
+        spawn(batch(rows_rx, batch_tx, BatchSize::new(self.max_batch)));
 
         spawn(async move {
-            let mut ledger = Ledger::new();
-            while let Some(transaction) = rx.recv().await {
-                ledger
-                    .process_transaction(transaction.into())
-                    .expect("failed to send transaction");
+            let mut since_checkpoint = 0;
+            let mut timing = Timing::default();
+            let mut refused = Vec::new();
+            let mut reports = report_interval.map(tokio::time::interval);
+            let mut changed = false;
+            loop {
+                let waiting = Instant::now();
+                let received = match reports.as_mut() {
+                    Some(reports) => tokio::select! {
+                        received = rx.recv() => received,
+                        _ = reports.tick() => {
+                            if let Err(err) = latency.log_interval() {
+                                eprintln!("failed to write latency log: {err:#}");
+                            }
+                            if changed {
+                                if let Err(err) = sink.write(&ledger) {
+                                    eprintln!("failed to write report: {err:#}");
+                                }
+                                changed = false;
+                            }
+                            continue;
+                        }
+                    },
+                    None => rx.recv().await,
+                };
+                let Some(received) = received else {
+                    break;
+                };
+                changed = true;
+                timing.queue_wait.record(waiting.elapsed());
+
+                for (source, (line, transaction), read_at) in received {
+                    if line <= resume_from[source] {
+                        continue;
+                    }
+                    lines[source] = line;
+                    let client = transaction.client;
+                    let kept = only_clients
+                        .as_ref()
+                        .map_or(true, |clients| clients.contains(client))
+                        && sample.map_or(true, |(rate, seed)| rate.keeps(client, seed));
+                    if !kept {
+                        // buffered rows it releases are applied, and refused like any
+                        // other row
+                        let refusals = ledger.skip(&transaction.tx_type, transaction.tx);
+                        tally_refusals(
+                            &ledger,
+                            refusals,
+                            &mut totals,
+                            &mut refused,
+                            verbose_rejects,
+                            collect_refused,
+                            locale,
+                        );
+                        if checkpoint.is_none() {
+                            applied[source].send_replace(line);
+                        }
+                        continue;
+                    }
+                    if let TransactionType::Custom(name) = &transaction.tx_type {
+                        if skip_unknown_types && !ledger.handlers.contains_key(name) {
+                            *totals[source]
+                                .unknown_types
+                                .entry(name.clone())
+                                .or_default() += 1;
+                            let refusals = ledger.skip(&transaction.tx_type, transaction.tx);
+                            tally_refusals(
+                                &ledger,
+                                refusals,
+                                &mut totals,
+                                &mut refused,
+                                verbose_rejects,
+                                collect_refused,
+                                locale,
+                            );
+                            if checkpoint.is_none() {
+                                applied[source].send_replace(line);
+                            }
+                            continue;
+                        }
+                    }
+                    if let Some(dedup) = dedup.as_mut() {
+                        if dedup.is_duplicate(&transaction) {
+                            totals[source].duplicates += 1;
+                            if checkpoint.is_none() {
+                                applied[source].send_replace(line);
+                            }
+                            continue;
+                        }
+                    }
+                    totals[source].rows += 1;
+                    let mut state = TransactionState::from(transaction);
+                    state.source = source;
+                    state.line = line;
+                    let applying = Instant::now();
+                    let refusals = ledger.process(state);
+                    timing.apply_secs += applying.elapsed().as_secs_f64();
+                    latency.record(read_at);
+                    tally_refusals(
+                        &ledger,
+                        refusals,
+                        &mut totals,
+                        &mut refused,
+                        verbose_rejects,
+                        collect_refused,
+                        locale,
+                    );
+
+                    since_checkpoint += 1;
+                    match checkpoint.as_deref() {
+                        Some(path) if since_checkpoint >= checkpoint_every => {
+                            since_checkpoint = 0;
+                            match Checkpoint::save_to(path, &ledger, &totals, &lines).await {
+                                Ok(()) => {
+                                    for (applied, line) in applied.iter().zip(&lines) {
+                                        applied.send_replace(*line);
+                                    }
+                                }
+                                Err(err) => eprintln!("failed to write checkpoint: {err:#}"),
+                            }
+                        }
+                        Some(_) => {}
+                        None => {
+                            applied[source].send_replace(line);
+                        }
+                    }
+                }
             }
 
-            tx_ledger.send(ledger).expect("Failed to send ledger");
+            tx_ledger
+                .send((
+                    ledger, sink, totals, timing, latency, refused, lines, applied,
+                ))
+                .expect("Failed to send ledger");
         });
 
-        let ledger = rx_ledger.await.expect("failed to recieve ledger");
-        output_report(&ledger)?;
+        let (ledger, mut sink, totals, mut timing, mut latency, refused, lines, applied) =
+            rx_ledger.await.expect("failed to recieve ledger");
+        for (name, reader) in readers {
+            let (read, elapsed) = reader.await.with_context(|| format!("reading {name}"))?;
+            read.with_context(|| format!("reading {name}"))?;
+            timing.read_parse_secs = timing.read_parse_secs.max(elapsed.as_secs_f64());
+        }
+
+        if let Some(path) = &self.rejected_rows {
+            let rows: Vec<(String, RejectedRow)> = rejected
+                .iter()
+                .flat_map(|(name, rows)| rows.take().into_iter().map(|row| (name.clone(), row)))
+                .collect();
+            write_rejected(path, &rows)
+                .with_context(|| format!("writing rejected rows to {}", path.display()))?;
+            if !rows.is_empty() {
+                eprintln!(
+                    "{} rows could not be read, see {}",
+                    rows.len(),
+                    path.display()
+                );
+            }
+        }
+
+        if let Some(path) = &self.errors {
+            let names: Vec<&str> = totals.iter().map(|totals| totals.name.as_str()).collect();
+            write_refused(path, &names, &refused)
+                .with_context(|| format!("writing refused transactions to {}", path.display()))?;
+            if !refused.is_empty() {
+                eprintln!(
+                    "{} transactions were refused, see {}",
+                    refused.len(),
+                    path.display()
+                );
+            }
+        }
+
+        if let Some(path) = &self.export_history {
+            let names: Vec<&str> = totals.iter().map(|totals| totals.name.as_str()).collect();
+            export_history(path, &ledger, &names)
+                .with_context(|| format!("exporting history to {}", path.display()))?;
+        }
+
+        let mut unknown_types = BTreeMap::<&str, u64>::new();
+        for (name, count) in totals.iter().flat_map(|totals| &totals.unknown_types) {
+            *unknown_types.entry(name).or_default() += count;
+        }
+        if !unknown_types.is_empty() {
+            let skipped: Vec<String> = unknown_types
+                .iter()
+                .map(|(name, count)| format!("{name} ({count})"))
+                .collect();
+            eprintln!(
+                "rows of unknown transaction types skipped: {}",
+                skipped.join(", ")
+            );
+        }
+
+        #[cfg_attr(not(feature = "webhook"), allow(unused_variables))]
+        let summary = RunSummary::new(&ledger, totals, started.elapsed());
+
+        if self.self_check {
+            let report = ledger.audit();
+            if !report.is_clean() {
+                eprint!("{report}");
+                bail!(
+                    "self-check failed with {} discrepancies",
+                    report.discrepancies.len()
+                );
+            }
+        }
+
+        if !ledger.quarantine.is_empty() {
+            let held: Vec<String> = ledger
+                .quarantine
+                .iter()
+                .map(|transaction| transaction.tx.to_string())
+                .collect();
+            eprintln!(
+                "transactions quarantined, awaiting approval: {}",
+                held.join(", ")
+            );
+        }
+
+        let gaps = ledger.gap_report();
+        if !gaps.is_empty() {
+            eprint!("transactions left unapplied at end of input:\n{gaps}");
+        }
+
+        if self.anomalies {
+            eprint!("{}", ledger.anomaly_report());
+        }
+
+        if self.risk {
+            eprint!("{}", ledger.risk_report());
+        }
+
+        if let Some(groupings) = &groupings {
+            eprint!("{}", ledger.rollup(groupings));
+        }
+
+        let (written, elapsed) =
+            timed(tokio::task::spawn_blocking(move || sink.write(&ledger))).await;
+        written??;
+        // every row read is in the report, and can be acknowledged upstream
+        for (applied, line) in applied.iter().zip(&lines) {
+            applied.send_replace(*line);
+        }
+        if let Some(path) = &self.checkpoint {
+            // the run is complete, and resuming from its checkpoint would skip every row.
+            // There is no checkpoint to remove when the run was shorter than the interval
+            Checkpoint::remove(path).await;
+        }
+        timing.write_secs = elapsed.as_secs_f64();
+        timing.total_secs = started.elapsed().as_secs_f64();
+        latency.log_interval().context("writing the latency log")?;
+        timing.latency_us = latency.summary();
+        if self.timing {
+            eprintln!("{}", serde_json::to_string(&timing)?);
+        }
+
+        #[cfg(feature = "webhook")]
+        if let Some(url) = self.summary_webhook.clone() {
+            let posted =
+                tokio::task::spawn_blocking(move || crate::summary::post_webhook(&url, &summary))
+                    .await?;
+            if let Err(err) = posted {
+                eprintln!("failed to post run summary: {err:#}");
+            }
+        }
 
         Ok(())
     }
 }
+
+/// Counts refused transactions against the input they came from, explaining them with
+/// `--verbose-rejects` and keeping them for `--errors`. A refusal may be of a buffered
+/// transaction the current row released, so it is blamed on that transaction's own line.
+fn tally_refusals(
+    ledger: &Ledger,
+    refusals: Vec<Refusal>,
+    totals: &mut [SourceTotals],
+    refused: &mut Vec<RefusedTransaction>,
+    verbose_rejects: bool,
+    collect_refused: bool,
+    locale: Locale,
+) {
+    for (transaction, err) in refusals {
+        totals[transaction.source].rejected += 1;
+        if !(verbose_rejects || collect_refused) {
+            continue;
+        }
+        let row = Transaction::from(&transaction);
+        let explanation = ledger.explain_rejection(&row, &err);
+        if verbose_rejects {
+            eprintln!(
+                "{}: {} {}",
+                explanation.code(),
+                explanation.render(locale),
+                locale.at_line(transaction.line)
+            );
+        }
+        if collect_refused {
+            refused.push(RefusedTransaction {
+                source: transaction.source,
+                line: transaction.line,
+                transaction: row,
+                code: explanation.code(),
+                reason: explanation.render(locale),
+            });
+        }
+    }
+}
+
+fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("https://") || path.starts_with("http://"))
+}
+
+fn is_glob(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.contains(['*', '?', '[']))
+}
+
+fn is_s3_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with("s3://"))
+}
+
+fn is_gs_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with("gs://"))
+}
+
+fn is_azure_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with("az://"))
+}
+
+fn is_amqp_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("amqp://") || path.starts_with("amqps://"))
+}
+
+fn is_sqlite_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("sqlite://"))
+}
+
+fn is_nats_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("nats://"))
+}
+
+fn read_approvals(path: &Path) -> Result<HashSet<TransactionId>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("reading approvals file {}", path.display()))?;
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.parse()
+                .with_context(|| format!("invalid transaction id in approvals file: {line}"))
+        })
+        .collect()
+}
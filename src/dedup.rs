@@ -0,0 +1,70 @@
+//! Dropping rows repeated by upstream retries.
+//!
+//! A retried export often sends the same row twice, and applied twice a deposit or
+//! withdrawal moves the balance twice. The window remembers the `(tx, type)` of the most
+//! recent rows and drops any row that matches one of them before it reaches the ledger.
+//! Only the key is compared, so a repeat with a different amount is dropped as well.
+use crate::{
+    ledger::TransactionId,
+    transaction::{Transaction, TransactionType},
+};
+use std::collections::{HashSet, VecDeque};
+
+#[derive(Debug, Clone)]
+pub struct DedupWindow {
+    /// Number of most recent rows a row is checked against
+    pub window: usize,
+    recent: VecDeque<(TransactionId, TransactionType)>,
+    seen: HashSet<(TransactionId, TransactionType)>,
+}
+
+impl DedupWindow {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            recent: VecDeque::with_capacity(window),
+            seen: HashSet::with_capacity(window),
+        }
+    }
+
+    /// Returns whether `transaction` repeats a row within the window. Rows that do not
+    /// are remembered, pushing the oldest one out once the window is full.
+    pub fn is_duplicate(&mut self, transaction: &Transaction) -> bool {
+        let key = (transaction.tx, transaction.tx_type.clone());
+        if self.seen.contains(&key) {
+            return true;
+        }
+        if self.window == 0 {
+            return false;
+        }
+
+        if self.recent.len() == self.window {
+            if let Some(oldest) = self.recent.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(key.clone());
+        self.recent.push_back(key);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::tx;
+
+    #[test]
+    fn test_drops_repeats_within_window() {
+        let mut dedup = DedupWindow::new(2);
+        assert!(!dedup.is_duplicate(&tx::deposit(1, 1, "1.0")));
+        assert!(dedup.is_duplicate(&tx::deposit(1, 1, "1.0")));
+        // same tx, another type
+        assert!(!dedup.is_duplicate(&tx::dispute(1, 1)));
+        assert!(!dedup.is_duplicate(&tx::deposit(1, 2, "1.0")));
+
+        // the first deposit has left the window
+        assert!(!dedup.is_duplicate(&tx::deposit(1, 1, "1.0")));
+        assert!(dedup.is_duplicate(&tx::deposit(1, 2, "5.0")));
+    }
+}
@@ -0,0 +1,48 @@
+//! A small example dataset compiled into the crate, for documentation, examples and
+//! trying the engine out without an input file.
+//!
+//! The rows cover every transaction type, a chargeback that locks client 2 and a
+//! withdrawal from client 3 that is rejected for insufficient funds.
+//!
+//! ```
+//! use mini_payments_engine::{amount::to_decimal, example, prelude::Ledger};
+//! use rust_decimal::Decimal;
+//!
+//! let mut ledger = Ledger::new();
+//! let rejected = example::transactions()
+//!     .into_iter()
+//!     .filter(|transaction| ledger.process_transaction(transaction.clone()).is_err())
+//!     .count();
+//!
+//! assert_eq!(rejected, 1);
+//! assert_eq!(to_decimal(ledger.account(1).unwrap().total_funds), Decimal::new(90, 1));
+//! assert!(ledger.account(2).unwrap().is_locked());
+//! assert_eq!(to_decimal(ledger.account(3).unwrap().available_funds), Decimal::new(70, 1));
+//! ```
+use crate::transaction::Transaction;
+use csv::{ReaderBuilder, Trim};
+
+/// The dataset as a csv file, header row included.
+pub const TRANSACTIONS_CSV: &str = include_str!("../examples/data/transactions.csv");
+
+/// The rows of [`TRANSACTIONS_CSV`] in file order.
+pub fn transactions() -> Vec<Transaction> {
+    ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .from_reader(TRANSACTIONS_CSV.as_bytes())
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .expect("the example dataset is valid csv")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_row_parses() {
+        let rows = TRANSACTIONS_CSV.lines().count() - 1;
+        assert_eq!(transactions().len(), rows);
+    }
+}
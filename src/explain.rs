@@ -0,0 +1,291 @@
+//! Plain-language explanations of rejected transactions.
+//!
+//! A rejection is turned into a [`Rejection`] with a stable machine-readable code, and only
+//! the message rendered from it depends on the [`Locale`].
+use crate::{
+    account::{AccountError, AccountStatus},
+    amount::Amount,
+    ledger::{Client, Ledger, LedgerError, TransactionId},
+    transaction::{Transaction, TransactionType},
+};
+use clap::ValueEnum;
+
+/// Language of user-facing rejection messages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Locale {
+    #[default]
+    En,
+    Es,
+    Fr,
+}
+
+/// Why a transaction was refused
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+    InsufficientAvailable { amount: Amount, available: Amount },
+    InsufficientHeld { amount: Amount, held: Amount },
+    AccountLocked,
+    StatusForbids(AccountStatus),
+    UnknownTransaction,
+    AmountMissing,
+    AccountMissing,
+    NotDisputed,
+    NotApplied,
+    UnsupportedType,
+    Other(String),
+}
+
+impl Rejection {
+    /// Identifier that stays the same across locales and releases.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Rejection::InsufficientAvailable { .. } => "insufficient_available",
+            Rejection::InsufficientHeld { .. } => "insufficient_held",
+            Rejection::AccountLocked => "account_locked",
+            Rejection::StatusForbids(_) => "status_forbids",
+            Rejection::UnknownTransaction => "unknown_transaction",
+            Rejection::AmountMissing => "amount_missing",
+            Rejection::AccountMissing => "account_missing",
+            Rejection::NotDisputed => "not_disputed",
+            Rejection::NotApplied => "not_applied",
+            Rejection::UnsupportedType => "unsupported_type",
+            Rejection::Other(_) => "other",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    pub tx_type: TransactionType,
+    pub client: Client,
+    pub tx: TransactionId,
+    pub rejection: Rejection,
+}
+
+impl Explanation {
+    pub fn code(&self) -> &'static str {
+        self.rejection.code()
+    }
+
+    /// The explanation as a sentence in `locale`.
+    pub fn render(&self, locale: Locale) -> String {
+        use Locale::*;
+        use Rejection::*;
+
+        let Explanation {
+            tx_type: t,
+            client: c,
+            tx,
+            rejection,
+        } = self;
+
+        match (rejection, locale) {
+            (InsufficientAvailable { amount, available }, En) => {
+                format!("{t} of {amount} exceeds available {available} for client {c}")
+            }
+            (InsufficientAvailable { amount, available }, Es) => {
+                format!("{t} de {amount} supera el saldo disponible {available} del cliente {c}")
+            }
+            (InsufficientAvailable { amount, available }, Fr) => {
+                format!("{t} de {amount} dépasse le solde disponible {available} du client {c}")
+            }
+            (InsufficientHeld { amount, held }, En) => {
+                format!("{t} of {amount} exceeds held {held} for client {c}")
+            }
+            (InsufficientHeld { amount, held }, Es) => {
+                format!("{t} de {amount} supera el saldo retenido {held} del cliente {c}")
+            }
+            (InsufficientHeld { amount, held }, Fr) => {
+                format!("{t} de {amount} dépasse le solde bloqué {held} du client {c}")
+            }
+            (AccountLocked, En) => {
+                format!("{t} {tx} refused, client {c} is locked after a chargeback")
+            }
+            (AccountLocked, Es) => format!(
+                "{t} {tx} rechazado, la cuenta del cliente {c} está bloqueada tras un contracargo"
+            ),
+            (AccountLocked, Fr) => format!(
+                "{t} {tx} refusé, le compte du client {c} est gelé après une rétrofacturation"
+            ),
+            (StatusForbids(status), En) => {
+                format!("{t} {tx} refused, client {c} is {status}")
+            }
+            (StatusForbids(status), Es) => {
+                format!("{t} {tx} rechazado, la cuenta del cliente {c} está en estado {status}")
+            }
+            (StatusForbids(status), Fr) => {
+                format!("{t} {tx} refusé, le compte du client {c} est à l'état {status}")
+            }
+            (UnknownTransaction, En) => {
+                format!("{t} of unknown transaction {tx} for client {c}")
+            }
+            (UnknownTransaction, Es) => {
+                format!("{t} de la transacción desconocida {tx} del cliente {c}")
+            }
+            (UnknownTransaction, Fr) => {
+                format!("{t} de la transaction inconnue {tx} du client {c}")
+            }
+            (AmountMissing, En) => format!("{t} {tx} for client {c} has no amount"),
+            (AmountMissing, Es) => format!("{t} {tx} del cliente {c} no tiene importe"),
+            (AmountMissing, Fr) => format!("{t} {tx} du client {c} n'a pas de montant"),
+            (AccountMissing, En) => format!("{t} {tx} refused, client {c} has no account"),
+            (AccountMissing, Es) => format!("{t} {tx} rechazado, el cliente {c} no tiene cuenta"),
+            (AccountMissing, Fr) => format!("{t} {tx} refusé, le client {c} n'a pas de compte"),
+            (NotDisputed, En) => {
+                format!("{t} of transaction {tx} for client {c} without an open dispute")
+            }
+            (NotDisputed, Es) => {
+                format!("{t} de la transacción {tx} del cliente {c} sin una disputa abierta")
+            }
+            (NotDisputed, Fr) => {
+                format!("{t} de la transaction {tx} du client {c} sans litige ouvert")
+            }
+            (NotApplied, En) => {
+                format!("{t} of transaction {tx} for client {c}, which was never applied")
+            }
+            (NotApplied, Es) => {
+                format!("{t} de la transacción {tx} del cliente {c}, que nunca se aplicó")
+            }
+            (NotApplied, Fr) => {
+                format!("{t} de la transaction {tx} du client {c}, qui n'a jamais été appliquée")
+            }
+            (UnsupportedType, En) => {
+                format!("{t} {tx} for client {c} refused, {t} is not a supported type")
+            }
+            (UnsupportedType, Es) => {
+                format!("{t} {tx} del cliente {c} rechazado, {t} no es un tipo admitido")
+            }
+            (UnsupportedType, Fr) => {
+                format!("{t} {tx} du client {c} refusé, {t} n'est pas un type pris en charge")
+            }
+            (Other(message), En) => format!("{t} {tx} for client {c} refused: {message}"),
+            (Other(message), Es) => format!("{t} {tx} del cliente {c} rechazado: {message}"),
+            (Other(message), Fr) => format!("{t} {tx} du client {c} refusé : {message}"),
+        }
+    }
+}
+
+impl Locale {
+    /// Where in the input file a row came from.
+    pub fn at_line(self, line: u64) -> String {
+        match self {
+            Locale::En => format!("at line {line}"),
+            Locale::Es => format!("en la línea {line}"),
+            Locale::Fr => format!("à la ligne {line}"),
+        }
+    }
+}
+
+impl Ledger {
+    /// Works out why `transaction` was refused with `err`.
+    ///
+    /// Rejected transactions leave the account untouched, so the balances quoted are the
+    /// ones the transaction was checked against.
+    pub fn explain_rejection(&self, transaction: &Transaction, err: &anyhow::Error) -> Explanation {
+        let rejection = if let Some(err) = err.downcast_ref::<AccountError>() {
+            match err {
+                AccountError::NotEnoughFunds(client, amount) => {
+                    let account = &self.accounts[client];
+                    match transaction.tx_type {
+                        TransactionType::Resolve | TransactionType::Chargeback => {
+                            Rejection::InsufficientHeld {
+                                amount: *amount,
+                                held: account.held_funds,
+                            }
+                        }
+                        _ => Rejection::InsufficientAvailable {
+                            amount: *amount,
+                            available: account.available_funds,
+                        },
+                    }
+                }
+                AccountError::AccountLocked(_) => Rejection::AccountLocked,
+                AccountError::StatusForbids(_, status, _) => Rejection::StatusForbids(*status),
+                AccountError::InvalidTransition(..) | AccountError::Overflow(..) => {
+                    Rejection::Other(err.to_string())
+                }
+            }
+        } else if let Some(err) = err.downcast_ref::<LedgerError>() {
+            match err {
+                LedgerError::TransactionNotFound(_) => Rejection::UnknownTransaction,
+                LedgerError::TransactionAmountMissing(_) => Rejection::AmountMissing,
+                LedgerError::AccountMissing(_) => Rejection::AccountMissing,
+                LedgerError::TransactionIsNotDisputed(_) => Rejection::NotDisputed,
+                LedgerError::TransactionNotApplied(_) => Rejection::NotApplied,
+                LedgerError::UnknownTransactionType(_) => Rejection::UnsupportedType,
+                LedgerError::NoIdAllocator => Rejection::Other(err.to_string()),
+            }
+        } else {
+            Rejection::Other(err.to_string())
+        };
+
+        Explanation {
+            tx_type: transaction.tx_type.clone(),
+            client: transaction.client,
+            tx: transaction.tx,
+            rejection,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{ledger_with_accounts, tx};
+
+    fn explain(ledger: &mut Ledger, transaction: Transaction) -> Explanation {
+        let err = ledger.process_transaction(transaction.clone()).unwrap_err();
+        ledger.explain_rejection(&transaction, &err)
+    }
+
+    #[test]
+    fn test_explain_insufficient_funds() {
+        let mut ledger = ledger_with_accounts(&[(7, "50.0000")]);
+        let explanation = explain(&mut ledger, tx::withdrawal(7, 2, "100.0000"));
+        assert_eq!(explanation.code(), "insufficient_available");
+        assert_eq!(
+            explanation.render(Locale::En),
+            "withdrawal of 100.0000 exceeds available 50.0000 for client 7"
+        );
+    }
+
+    #[test]
+    fn test_explain_missing_dispute() {
+        let mut ledger = ledger_with_accounts(&[(1, "1.0")]);
+        assert_eq!(
+            explain(&mut ledger, tx::resolve(1, 1)).render(Locale::En),
+            "resolve of transaction 1 for client 1 without an open dispute"
+        );
+        assert_eq!(
+            explain(&mut ledger, tx::dispute(1, 9)).render(Locale::En),
+            "dispute of unknown transaction 9 for client 1"
+        );
+    }
+
+    #[test]
+    fn test_explain_locked_account() {
+        let mut ledger = ledger_with_accounts(&[(1, "1.0")]);
+        ledger.process_transaction(tx::dispute(1, 1)).unwrap();
+        ledger.process_transaction(tx::chargeback(1, 1)).unwrap();
+        assert_eq!(
+            explain(&mut ledger, tx::deposit(1, 2, "1.0")).render(Locale::En),
+            "deposit 2 refused, client 1 is locked after a chargeback"
+        );
+    }
+
+    #[test]
+    fn test_code_is_the_same_in_every_locale() {
+        let mut ledger = ledger_with_accounts(&[(1, "1.0")]);
+        let explanation = explain(&mut ledger, tx::dispute(2, 1));
+        assert_eq!(explanation.code(), "account_missing");
+        assert_eq!(
+            explanation.render(Locale::Es),
+            "dispute 1 rechazado, el cliente 2 no tiene cuenta"
+        );
+        assert_eq!(
+            explanation.render(Locale::Fr),
+            "dispute 1 refusé, le client 2 n'a pas de compte"
+        );
+        assert_eq!(Locale::Fr.at_line(3), "à la ligne 3");
+    }
+}
@@ -0,0 +1,191 @@
+//! The transaction history of a run, for `--export-history`.
+//!
+//! Auditors need every transaction and what became of it, not just the ending balances.
+//! Each history entry is written out in the order it was added, with whether it is still
+//! disputed and its final status: applied, rejected, charged back, or pending for one
+//! held in quarantine. Disputes, resolves and chargebacks are not entries of their own
+//! but show in the flags of the transaction they refer to, and transactions still
+//! buffered behind a missing id at the end of the run are not in the history yet.
+//!
+//! The file is csv, json when its name ends in `.json`, or JSON Lines when it ends in
+//! `.jsonl`.
+use crate::{
+    amount::Amount,
+    ledger::{Client, Ledger, TransactionId},
+    transaction::{TransactionStatus, TransactionType},
+};
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryFormat {
+    Csv,
+    Json,
+    JsonLines,
+}
+
+impl HistoryFormat {
+    /// The format named by the extension of `path`, csv unless it is `json` or `jsonl`.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => HistoryFormat::Json,
+            Some("jsonl") => HistoryFormat::JsonLines,
+            _ => HistoryFormat::Csv,
+        }
+    }
+}
+
+/// One history entry as exported
+#[derive(Debug, Serialize)]
+struct HistoryRow<'a> {
+    tx: TransactionId,
+    #[serde(rename = "type")]
+    tx_type: &'a TransactionType,
+    client: Client,
+    amount: Option<Amount>,
+    disputed: bool,
+    status: TransactionStatus,
+    /// Input the transaction was read from, empty for one the engine generated
+    source: &'a str,
+    generated: bool,
+}
+
+impl Ledger {
+    /// Writes every history entry to `out`, naming the input each was read from by its
+    /// index into `names`. An entry whose index has no name gets an empty source.
+    pub fn write_history<W: Write>(
+        &self,
+        mut out: W,
+        format: HistoryFormat,
+        names: &[&str],
+    ) -> Result<()> {
+        let rows = self.history.values().map(|entry| {
+            let generated = self.generated.contains(&entry.tx);
+            HistoryRow {
+                tx: entry.tx,
+                tx_type: &entry.tx_type,
+                client: entry.client,
+                amount: entry.amount,
+                disputed: entry.disputed,
+                status: entry.status,
+                source: if generated {
+                    ""
+                } else {
+                    names.get(entry.source).copied().unwrap_or("")
+                },
+                generated,
+            }
+        });
+        match format {
+            HistoryFormat::Csv => {
+                let mut wtr = csv::Writer::from_writer(out);
+                for row in rows {
+                    wtr.serialize(row)?;
+                }
+                wtr.flush()?;
+            }
+            HistoryFormat::Json => {
+                serde_json::to_writer(&mut out, &rows.collect::<Vec<_>>())?;
+                writeln!(out)?;
+            }
+            HistoryFormat::JsonLines => {
+                for row in rows {
+                    serde_json::to_writer(&mut out, &row)?;
+                    writeln!(out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the history of `ledger` to `path`, in the format its extension names.
+pub fn export_history(path: &Path, ledger: &Ledger, names: &[&str]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    ledger.write_history(&mut writer, HistoryFormat::from_path(path), names)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{apply_all, tx};
+
+    fn ledger() -> Ledger {
+        let mut ledger = Ledger::new();
+        apply_all(
+            &mut ledger,
+            [
+                tx::deposit(1, 1, "10.0"),
+                tx::deposit(2, 2, "5.0"),
+                tx::dispute(1, 1),
+                tx::dispute(2, 2),
+                tx::chargeback(2, 2),
+            ],
+        );
+        // waits on the missing id 3
+        apply_all(&mut ledger, [tx::withdrawal(1, 4, "1.0")]);
+        ledger
+    }
+
+    #[test]
+    fn test_history_is_exported_as_csv() {
+        let mut out = Vec::new();
+        ledger()
+            .write_history(&mut out, HistoryFormat::Csv, &["day.csv"])
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[0],
+            "tx,type,client,amount,disputed,status,source,generated"
+        );
+        assert!(lines[1].starts_with("1,deposit,1,"));
+        assert!(lines[1].ends_with(",true,applied,day.csv,false"));
+        assert!(lines[2].ends_with(",false,charged_back,day.csv,false"));
+        // the buffered withdrawal is not in the history yet
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn test_history_export_without_input_names_leaves_source_empty() {
+        let mut out = Vec::new();
+        ledger()
+            .write_history(&mut out, HistoryFormat::Csv, &[])
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out
+            .lines()
+            .nth(1)
+            .unwrap()
+            .ends_with(",true,applied,,false"));
+    }
+
+    #[test]
+    fn test_history_is_exported_as_json() {
+        let mut out = Vec::new();
+        ledger()
+            .write_history(&mut out, HistoryFormat::Json, &["day.csv"])
+            .unwrap();
+        let entries: Vec<serde_json::Value> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["type"], "deposit");
+        assert_eq!(entries[0]["disputed"], true);
+        assert_eq!(entries[1]["status"], "charged_back");
+
+        assert_eq!(
+            HistoryFormat::from_path(Path::new("history.jsonl")),
+            HistoryFormat::JsonLines
+        );
+        assert_eq!(
+            HistoryFormat::from_path(Path::new("history")),
+            HistoryFormat::Csv
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Restricting a run to some clients.
+//!
+//! `--only-clients 1-100,250` drops the rows of every other client before the ledger
+//! applies them, for a quick re-run of a few customers out of a full dump. Dropped rows
+//! are still [`Ledger::skip`]ped, so the ids they leave out do not hold up the rows of the
+//! clients that are kept.
+//!
+//!
+//! `--sample 1%` keeps a slice of the clients instead, for a quick check of the pipeline
+//! and rule configuration before a full run. Whether a client is kept only depends on its
+//! id and `--sample-seed`, so every row of a kept client is kept and the same seed picks
+//! the same clients on every run.
+//!
+//! [`Ledger::skip`]: crate::ledger::Ledger::skip
+use crate::ledger::Client;
+use anyhow::{bail, Context, Error, Result};
+use std::{ops::RangeInclusive, str::FromStr};
+
+/// Client ids and inclusive ranges of them, written like `1-100,250`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientRanges(pub Vec<RangeInclusive<Client>>);
+
+impl ClientRanges {
+    pub fn contains(&self, client: Client) -> bool {
+        self.0.iter().any(|range| range.contains(&client))
+    }
+}
+
+impl FromStr for ClientRanges {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut ranges = Vec::new();
+        for part in s.split(',').map(str::trim) {
+            let (first, last) = part.split_once('-').unwrap_or((part, part));
+            let first: Client = first
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid client range {part}"))?;
+            let last: Client = last
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid client range {part}"))?;
+            if first > last {
+                bail!("client range {part} runs backwards");
+            }
+            ranges.push(first..=last);
+        }
+        Ok(Self(ranges))
+    }
+}
+
+/// The share of clients kept by `--sample`, written as a percentage like `1%` or `0.5%`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleRate {
+    /// Kept clients per million
+    per_million: u64,
+}
+
+impl SampleRate {
+    /// Whether the rows of `client` are kept when sampling with `seed`.
+    pub fn keeps(&self, client: Client, seed: u64) -> bool {
+        mix(seed ^ u64::from(client)) % 1_000_000 < self.per_million
+    }
+}
+
+impl FromStr for SampleRate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let percent: f64 = s
+            .trim()
+            .strip_suffix('%')
+            .with_context(|| format!("sample rate {s} must be a percentage, like 1%"))?
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid sample rate {s}"))?;
+        if !(percent > 0.0 && percent <= 100.0) {
+            bail!("sample rate {s} must be above 0% and at most 100%");
+        }
+        Ok(Self {
+            per_million: (percent * 10_000.0).round() as u64,
+        })
+    }
+}
+
+/// splitmix64's finaliser, which spreads neighbouring client ids evenly. Unlike std's
+/// hashers it is the same on every platform and release, so samples can be repeated.
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ledger::Ledger,
+        test_support::{amount, tx},
+    };
+
+    #[test]
+    fn test_parse_client_ranges() {
+        let clients: ClientRanges = "1-3, 7".parse().unwrap();
+        assert_eq!(clients, ClientRanges(vec![1..=3, 7..=7]));
+        assert!(clients.contains(2));
+        assert!(clients.contains(7));
+        assert!(!clients.contains(4));
+
+        assert!("".parse::<ClientRanges>().is_err());
+        assert!("5-2".parse::<ClientRanges>().is_err());
+        assert!("1-x".parse::<ClientRanges>().is_err());
+    }
+
+    #[test]
+    fn test_sample_keeps_a_stable_share_of_clients() {
+        let rate: SampleRate = "10%".parse().unwrap();
+        let sample = |seed| -> Vec<Client> {
+            (0..=u16::MAX)
+                .filter(|client| rate.keeps(*client, seed))
+                .collect()
+        };
+        let kept = sample(7);
+        assert!((6_000..7_100).contains(&kept.len()), "{}", kept.len());
+        assert_eq!(kept, sample(7));
+        assert_ne!(kept, sample(8));
+
+        let all: SampleRate = "100%".parse().unwrap();
+        assert!((0..=u16::MAX).all(|client| all.keeps(client, 0)));
+        assert!("0%".parse::<SampleRate>().is_err());
+        assert!("150%".parse::<SampleRate>().is_err());
+        assert!("0.1".parse::<SampleRate>().is_err());
+    }
+
+    #[test]
+    fn test_skipped_rows_do_not_hold_up_later_ids() {
+        let clients: ClientRanges = "1".parse().unwrap();
+        let mut ledger = Ledger::new();
+        for transaction in [
+            tx::deposit(1, 1, "5.0"),
+            tx::deposit(2, 2, "1.0"),
+            tx::withdrawal(1, 3, "2.0"),
+            tx::deposit(2, 4, "1.0"),
+            tx::deposit(1, 5, "1.0"),
+        ] {
+            if clients.contains(transaction.client) {
+                ledger.process_transaction(transaction).unwrap();
+            } else {
+                assert!(ledger.skip(&transaction.tx_type, transaction.tx).is_empty());
+            }
+        }
+
+        assert!(ledger.account(2).is_none());
+        assert_eq!(ledger.account(1).unwrap().total_funds, amount("4.0"));
+        assert!(ledger.gap_report().is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+use crate::ledger::{Ledger, TransactionId};
+use serde::Serialize;
+use std::fmt;
+
+/// A run of transaction ids that never arrived, and the buffered deposits and withdrawals
+/// waiting on it
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Gap {
+    pub first_missing: TransactionId,
+    pub last_missing: TransactionId,
+    /// Buffered transactions between this gap and the next one
+    pub blocked: Vec<TransactionId>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct GapReport {
+    pub gaps: Vec<Gap>,
+}
+
+impl GapReport {
+    pub fn is_empty(&self) -> bool {
+        self.gaps.is_empty()
+    }
+}
+
+impl fmt::Display for GapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for gap in &self.gaps {
+            let blocked: Vec<String> = gap.blocked.iter().map(|tx| tx.to_string()).collect();
+            writeln!(
+                f,
+                "missing tx {}..={}, blocking {}",
+                gap.first_missing,
+                gap.last_missing,
+                blocked.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Ledger {
+    /// Lists the missing id ranges that keep buffered transactions from being applied,
+    /// each with the transactions it holds back.
+    pub fn gap_report(&self) -> GapReport {
+        let mut report = GapReport::default();
+        let mut expected = self.next_id().unwrap_or(0);
+
+        // the buffer is kept sorted by id, so each gap sits just before the first
+        // transaction it blocks
+        for transaction in &self.unprocessed {
+            if transaction.tx < expected {
+                continue;
+            }
+
+            if transaction.tx > expected {
+                report.gaps.push(Gap {
+                    first_missing: expected,
+                    last_missing: transaction.tx.saturating_sub(1),
+                    blocked: Vec::new(),
+                });
+            }
+
+            if let Some(gap) = report.gaps.last_mut() {
+                gap.blocked.push(transaction.tx);
+            }
+            expected = self.next_after(transaction.tx);
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::tx;
+
+    #[test]
+    fn test_no_gaps_when_everything_applied() {
+        let ledger = crate::test_support::ledger_with_accounts(&[(1, "1.0"), (2, "1.0")]);
+        assert!(ledger.gap_report().is_empty());
+    }
+
+    #[test]
+    fn test_gap_report_lists_ranges_and_blocked_transactions() {
+        let mut ledger = Ledger::new();
+        for transaction in [
+            tx::deposit(1, 1, "1.0"),
+            tx::deposit(1, 4, "1.0"),
+            tx::deposit(1, 5, "1.0"),
+            tx::withdrawal(1, 9, "1.0"),
+        ] {
+            ledger.process_transaction(transaction).unwrap();
+        }
+
+        let report = ledger.gap_report();
+        assert_eq!(
+            report.gaps,
+            vec![
+                Gap {
+                    first_missing: 2,
+                    last_missing: 3,
+                    blocked: vec![4, 5],
+                },
+                Gap {
+                    first_missing: 6,
+                    last_missing: 8,
+                    blocked: vec![9],
+                },
+            ]
+        );
+        assert_eq!(
+            report.to_string(),
+            "missing tx 2..=3, blocking 4, 5\nmissing tx 6..=8, blocking 9\n"
+        );
+    }
+}
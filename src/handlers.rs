@@ -0,0 +1,111 @@
+//! Product-specific transaction types.
+//!
+//! Input rows whose type the engine does not know, such as `bonus` or `adjustment`, are
+//! read as [`TransactionType::Custom`] and applied by the [`TransactionHandler`]
+//! registered for that name with [`crate::ledger::LedgerBuilder::handler`]. They take
+//! part in transaction id ordering and the history like deposits and withdrawals, so they
+//! can be disputed as well. Rows of a type with no handler are rejected.
+use crate::{
+    account::{Account, LockReason},
+    ledger::{Ledger, LedgerError},
+    transaction::{TransactionState, TransactionType},
+};
+use anyhow::Result;
+use std::fmt;
+
+pub trait TransactionHandler: fmt::Debug + Send + Sync {
+    /// Applies `tx` to the account of its client. An error rejects the transaction and
+    /// should leave the account as it was, which [`Account::deposit`] and
+    /// [`Account::withdraw`] already do.
+    fn apply(&self, account: &mut Account, tx: &TransactionState) -> Result<()>;
+}
+
+impl Ledger {
+    /// Applies a custom-type transaction with the handler registered for it, once the
+    /// account's status has been checked.
+    pub(crate) fn apply_custom(&mut self, name: &str, tx: &TransactionState) -> Result<()> {
+        let handler = self
+            .handlers
+            .get(name)
+            .cloned()
+            .ok_or_else(|| LedgerError::UnknownTransactionType(name.to_string()))?;
+        let account = self.get_account(tx)?;
+        account.check_status(tx.tx_type.clone())?;
+        let result = handler.apply(account, tx);
+        account.record_lock(LockReason::Custom(name.to_string()), tx.tx);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        amount::Amount,
+        test_support::{amount, ledger_with_accounts, tx},
+        transaction::Transaction,
+    };
+
+    /// Credits the amount like a deposit
+    #[derive(Debug)]
+    struct Bonus;
+
+    impl TransactionHandler for Bonus {
+        fn apply(&self, account: &mut Account, tx: &TransactionState) -> Result<()> {
+            Ok(account.deposit(tx.amount.unwrap_or_default())?)
+        }
+    }
+
+    fn custom(name: &str, client: u16, tx: u32, value: &str) -> Transaction {
+        Transaction {
+            tx_type: TransactionType::Custom(name.to_string()),
+            client,
+            tx,
+            amount: Some(amount(value)),
+        }
+    }
+
+    #[test]
+    fn test_custom_types_use_their_handler() {
+        let mut ledger = Ledger::builder().handler("bonus", Bonus).build();
+        ledger
+            .process_transaction(tx::deposit(1, 1, "10.0"))
+            .unwrap();
+        ledger
+            .process_transaction(custom("bonus", 1, 2, "2.5"))
+            .unwrap();
+        assert_eq!(ledger.accounts[&1].total_funds, amount("12.5"));
+
+        let err = ledger
+            .process_transaction(custom("refund", 1, 3, "1.0"))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<LedgerError>(),
+            Some(LedgerError::UnknownTransactionType(_))
+        ));
+        // the rejected id still counts, so the next deposit is not held back
+        ledger
+            .process_transaction(tx::deposit(1, 4, "1.0"))
+            .unwrap();
+        assert_eq!(ledger.accounts[&1].total_funds, amount("13.5"));
+
+        // custom transactions can be disputed like deposits
+        ledger.process_transaction(tx::dispute(1, 2)).unwrap();
+        assert_eq!(ledger.accounts[&1].held_funds, amount("2.5"));
+    }
+
+    #[test]
+    fn test_locked_accounts_refuse_custom_types() {
+        let mut ledger = ledger_with_accounts(&[(1, "10.0")]);
+        ledger
+            .handlers
+            .insert(String::from("bonus"), std::sync::Arc::new(Bonus));
+        ledger.process_transaction(tx::dispute(1, 1)).unwrap();
+        ledger.process_transaction(tx::chargeback(1, 1)).unwrap();
+
+        assert!(ledger
+            .process_transaction(custom("bonus", 1, 2, "1.0"))
+            .is_err());
+        assert_eq!(ledger.accounts[&1].total_funds, Amount::default());
+    }
+}
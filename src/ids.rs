@@ -0,0 +1,241 @@
+//! Ids for transactions the engine creates itself.
+//!
+//! Fees, interest and compensating entries need transaction ids of their own, which must
+//! never collide with the ids upstream hands out, in this run or a later one. An
+//! [`IdAllocator`] installed with [`crate::ledger::LedgerBuilder::id_allocator`] hands
+//! them out to [`crate::ledger::Ledger::process_generated`]. Generated transactions are
+//! kept in the history like any other, so they can be disputed, but they are not part of
+//! upstream's id sequence and never hold up or release a buffered deposit or withdrawal.
+//! Ids already in the history are passed over, so an allocator that starts over on a
+//! ledger restored from a checkpoint carries on after the ids it handed out before.
+use crate::ledger::TransactionId;
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::Write,
+    ops::RangeInclusive,
+    path::PathBuf,
+};
+
+pub trait IdAllocator: fmt::Debug + Send + Sync {
+    /// Hands out the next id, failing once there are none left.
+    fn allocate(&mut self) -> Result<TransactionId>;
+}
+
+/// Ids counted up through a range upstream never uses. The count starts over every run,
+/// so use [`RunScoped`] or [`StoredSequence`] when generated ids have to stay unique
+/// across runs that do not share a checkpoint.
+#[derive(Debug, Clone)]
+pub struct ReservedRange {
+    next: Option<TransactionId>,
+    last: TransactionId,
+}
+
+impl ReservedRange {
+    /// The top 2^28 ids
+    pub const DEFAULT: RangeInclusive<TransactionId> = 0xF000_0000..=TransactionId::MAX;
+
+    pub fn new(range: RangeInclusive<TransactionId>) -> Self {
+        Self {
+            next: (!range.is_empty()).then_some(*range.start()),
+            last: *range.end(),
+        }
+    }
+}
+
+impl Default for ReservedRange {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT)
+    }
+}
+
+impl IdAllocator for ReservedRange {
+    fn allocate(&mut self) -> Result<TransactionId> {
+        let id = self
+            .next
+            .ok_or_else(|| anyhow!("the reserved id range is used up"))?;
+        self.next = id.checked_add(1).filter(|next| *next <= self.last);
+        Ok(id)
+    }
+}
+
+/// Ids derived from a run id, such as a UUID: the top bit set, 15 bits taken from a hash
+/// of the run id and a 16 bit count, so runs whose 15 bits differ never share an id. Two
+/// runs with random ids share them with odds of 1 in 32768, and a run can generate 65536
+/// ids.
+#[derive(Debug, Clone)]
+pub struct RunScoped {
+    prefix: TransactionId,
+    next: Option<u16>,
+}
+
+impl RunScoped {
+    pub fn new(run_id: u128) -> Self {
+        let hash = mix((run_id as u64) ^ mix((run_id >> 64) as u64));
+        Self {
+            prefix: 0x8000_0000 | ((hash as TransactionId & 0x7fff) << 16),
+            next: Some(0),
+        }
+    }
+}
+
+impl IdAllocator for RunScoped {
+    fn allocate(&mut self) -> Result<TransactionId> {
+        let count = self
+            .next
+            .ok_or_else(|| anyhow!("the run has used up its 65536 generated ids"))?;
+        self.next = count.checked_add(1);
+        Ok(self.prefix | TransactionId::from(count))
+    }
+}
+
+/// splitmix64's finaliser, spreading every bit of `x` over the result
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Ids from a sequence kept in a file, which carries on from run to run. Ids are taken
+/// from the file a block at a time, and the end of a block is written and synced before
+/// any id in it is handed out, so a crash can leave a gap in the sequence but never
+/// hand an id out twice. Runs sharing a file must not run at the same time.
+#[derive(Debug)]
+pub struct StoredSequence {
+    path: PathBuf,
+    next: u64,
+    /// End of the block taken from the file, exclusive
+    taken: u64,
+    /// End of the range, exclusive
+    end: u64,
+    block: u64,
+}
+
+impl StoredSequence {
+    /// Carries on the sequence in `path` within `range`, starting at the start of the range
+    /// when there is no file yet, and taking `block` ids from the file at a time.
+    pub fn open(
+        path: impl Into<PathBuf>,
+        range: RangeInclusive<TransactionId>,
+        block: u32,
+    ) -> Result<Self> {
+        let path = path.into();
+        let start = u64::from(*range.start());
+        let end = u64::from(*range.end()) + 1;
+        let next = match fs::read_to_string(&path) {
+            Ok(stored) => stored
+                .trim()
+                .parse()
+                .with_context(|| format!("reading id sequence {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => start,
+            Err(err) => {
+                return Err(err).with_context(|| format!("reading id sequence {}", path.display()))
+            }
+        };
+        if next < start {
+            bail!(
+                "id sequence {} is at {next}, before the range starting at {start}",
+                path.display()
+            );
+        }
+        Ok(Self {
+            path,
+            next,
+            taken: next,
+            end,
+            block: u64::from(block.max(1)),
+        })
+    }
+
+    fn take_block(&mut self) -> Result<()> {
+        let taken = (self.next + self.block).min(self.end);
+        let partial = self.path.with_extension("partial");
+        let mut file = File::create(&partial)?;
+        write!(file, "{taken}")?;
+        file.sync_all()?;
+        fs::rename(&partial, &self.path)?;
+        self.taken = taken;
+        Ok(())
+    }
+}
+
+impl IdAllocator for StoredSequence {
+    fn allocate(&mut self) -> Result<TransactionId> {
+        if self.next >= self.end {
+            bail!("id sequence {} is used up", self.path.display());
+        }
+        if self.next >= self.taken {
+            self.take_block()
+                .with_context(|| format!("writing id sequence {}", self.path.display()))?;
+        }
+        let id = self.next as TransactionId;
+        self.next += 1;
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assert_balances,
+        ledger::Ledger,
+        test_support::{amount, apply_all, tx},
+        transaction::TransactionType,
+    };
+
+    #[test]
+    fn test_reserved_range_runs_out() {
+        let mut ids = ReservedRange::new(TransactionId::MAX - 1..=TransactionId::MAX);
+        assert_eq!(ids.allocate().unwrap(), TransactionId::MAX - 1);
+        assert_eq!(ids.allocate().unwrap(), TransactionId::MAX);
+        assert!(ids.allocate().is_err());
+    }
+
+    #[test]
+    fn test_run_scoped_ids_differ_between_runs() {
+        let mut first = RunScoped::new(0x1234_5678_9abc_def0_1234_5678_9abc_def0);
+        let mut second = RunScoped::new(0x0fed_cba9_8765_4321_0fed_cba9_8765_4321);
+        let (a, b) = (first.allocate().unwrap(), second.allocate().unwrap());
+        assert_ne!(a >> 16, b >> 16);
+        assert!(a >= 0x8000_0000 && b >= 0x8000_0000);
+        assert_eq!(first.allocate().unwrap(), a + 1);
+    }
+
+    #[test]
+    fn test_stored_sequence_carries_on_across_runs() {
+        let path = std::env::temp_dir().join(format!("mpe-{}-ids", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut ids = StoredSequence::open(&path, 1000..=1999, 10).unwrap();
+        assert_eq!(ids.allocate().unwrap(), 1000);
+        assert_eq!(ids.allocate().unwrap(), 1001);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1010");
+
+        // the rest of the first run's block is never handed out
+        let mut ids = StoredSequence::open(&path, 1000..=1999, 10).unwrap();
+        assert_eq!(ids.allocate().unwrap(), 1010);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_generated_transactions_stay_out_of_the_sequence() {
+        let mut ledger = Ledger::builder()
+            .id_allocator(ReservedRange::new(5000..=5999))
+            .build();
+        apply_all(&mut ledger, [tx::deposit(1, 1, "10.0")]);
+
+        let fee = ledger
+            .process_generated(TransactionType::Withdrawal, 1, Some(amount("1.5")))
+            .unwrap();
+        assert_eq!(fee, 5000);
+        // upstream's next id is still applied straight away rather than buffered
+        apply_all(&mut ledger, [tx::deposit(1, 2, "1.0")]);
+        assert_balances!(ledger, 1, available: "9.5", held: "0", total: "9.5");
+
+        // the fee can be disputed like any other withdrawal
+        apply_all(&mut ledger, [tx::dispute(1, fee)]);
+        assert_balances!(ledger, 1, available: "8.0", held: "1.5", total: "9.5");
+    }
+}
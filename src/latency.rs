@@ -0,0 +1,170 @@
+//! End-to-end latency of the rows of a run, for `--timing` and `--latency-log`.
+//!
+//! Each row is timed from when the run takes it off its input until the ledger has
+//! processed it, so time spent throttled, batched and queued in front of the ledger is
+//! included. A deposit or withdrawal held back waiting for an earlier id counts as
+//! processed once it is buffered. Latencies are recorded in microseconds into an
+//! HdrHistogram with three significant digits.
+//!
+//! `--latency-log` writes the latencies as an HdrHistogram interval log, which
+//! HistogramLogAnalyzer and the other HdrHistogram tools read: one interval per
+//! `--report-interval` when following a file, and one for the whole run otherwise.
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hdrhistogram::{
+    serialization::{Serializer, V2DeflateSerializer},
+    Histogram,
+};
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Latency percentiles in microseconds
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p999: u64,
+    pub max: u64,
+}
+
+pub struct Latency {
+    /// Every latency of the run
+    total: Histogram<u64>,
+    /// Latencies since the last interval was logged
+    interval: Histogram<u64>,
+    started: Instant,
+    interval_started: Instant,
+    log: Option<BufWriter<File>>,
+}
+
+impl Latency {
+    /// Starts timing rows, writing the header of an interval log to `log` when given.
+    pub fn new(log: Option<&Path>) -> Result<Self> {
+        let log = log
+            .map(|path| {
+                let mut file = BufWriter::new(
+                    File::create(path)
+                        .with_context(|| format!("creating latency log {}", path.display()))?,
+                );
+                write_header(&mut file)?;
+                anyhow::Ok(file)
+            })
+            .transpose()?;
+        let started = Instant::now();
+        Ok(Self {
+            total: new_histogram(),
+            interval: new_histogram(),
+            started,
+            interval_started: started,
+            log,
+        })
+    }
+
+    /// Records a row taken off its input at `read_at` as processed now.
+    pub fn record(&mut self, read_at: Instant) {
+        let micros = u64::try_from(read_at.elapsed().as_micros()).unwrap_or(u64::MAX);
+        self.total.saturating_record(micros);
+        self.interval.saturating_record(micros);
+    }
+
+    /// Writes the latencies recorded since the last interval to the log, if there is one,
+    /// and starts a new interval.
+    pub fn log_interval(&mut self) -> Result<()> {
+        let now = Instant::now();
+        if let Some(log) = self.log.as_mut() {
+            let mut encoded = Vec::new();
+            V2DeflateSerializer::new()
+                .serialize(&self.interval, &mut encoded)
+                .map_err(|err| anyhow!("encoding latency histogram: {err:?}"))?;
+            writeln!(
+                log,
+                "{:.3},{:.3},{:.3},{}",
+                (self.interval_started - self.started).as_secs_f64(),
+                (now - self.interval_started).as_secs_f64(),
+                // interval maximums are conventionally given in milliseconds
+                self.interval.max() as f64 / 1000.0,
+                STANDARD.encode(&encoded)
+            )?;
+            log.flush()?;
+        }
+        self.interval.reset();
+        self.interval_started = now;
+        Ok(())
+    }
+
+    /// Percentiles over the whole run, `None` before any row was processed.
+    pub fn summary(&self) -> Option<LatencySummary> {
+        let histogram = &self.total;
+        (!histogram.is_empty()).then(|| LatencySummary {
+            count: histogram.len(),
+            p50: histogram.value_at_quantile(0.5),
+            p90: histogram.value_at_quantile(0.9),
+            p99: histogram.value_at_quantile(0.99),
+            p999: histogram.value_at_quantile(0.999),
+            max: histogram.max(),
+        })
+    }
+}
+
+fn new_histogram() -> Histogram<u64> {
+    Histogram::new(3).expect("three significant digits are supported")
+}
+
+fn write_header(log: &mut impl Write) -> Result<()> {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    writeln!(log, "#[Histogram log format version 1.3]")?;
+    writeln!(log, "#[StartTime: {since_epoch:.3} (seconds since epoch)]")?;
+    writeln!(log, "#[BaseTime: {since_epoch:.3} (seconds since epoch)]")?;
+    writeln!(
+        log,
+        "\"StartTimestamp\",\"Interval_Length\",\"Interval_Max\",\"Interval_Compressed_Histogram\""
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_latency_is_logged_per_interval() {
+        let path = std::env::temp_dir().join(format!("mpe-{}-latency.hlog", std::process::id()));
+        let mut latency = Latency::new(Some(&path)).unwrap();
+        assert_eq!(latency.summary(), None);
+
+        let read_at = Instant::now() - Duration::from_millis(20);
+        for _ in 0..10 {
+            latency.record(read_at);
+        }
+        latency.log_interval().unwrap();
+        latency.log_interval().unwrap();
+
+        let summary = latency.summary().unwrap();
+        assert_eq!(summary.count, 10);
+        assert!(summary.p50 >= 20_000);
+        assert!(summary.max >= summary.p999);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let intervals: Vec<&str> = log
+            .lines()
+            .filter(|line| !line.starts_with(['#', '"']))
+            .collect();
+        assert_eq!(intervals.len(), 2);
+        let fields: Vec<&str> = intervals[0].split(',').collect();
+        assert_eq!(fields[0], "0.000");
+        assert!(fields[2].parse::<f64>().unwrap() >= 20.0);
+        assert!(fields[3].starts_with("HIST"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
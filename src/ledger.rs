@@ -1,21 +1,72 @@
 use crate::{
-    account::Account,
-    transaction::{Transaction, TransactionState, TransactionType},
+    account::{Account, AccountStatus, LockReason},
+    amount::Amount,
+    breaker::DisputeBreaker,
+    clock::{Clock, SystemClock},
+    columns::ReportColumn,
+    handlers::TransactionHandler,
+    ids::IdAllocator,
+    rules::Rule,
+    transaction::{TransactionState, TransactionStatus, TransactionType},
 };
 use anyhow::Result;
 use indexmap::IndexMap;
-use rust_decimal::Decimal;
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::SystemTime,
+};
 use thiserror::Error;
 
 pub type Client = u16;
 pub type TransactionId = u32;
 
+/// Every transaction the ledger has seen, in the order it was added
+pub type History = IndexMap<TransactionId, TransactionState>;
+
+/// A transaction the ledger refused, with why
+pub type Refusal = (TransactionState, anyhow::Error);
+
+/// The balances and transaction history of every client. Its fields are internal, so
+/// the ledger is configured through [`LedgerBuilder`] and read through its methods.
 #[derive(Debug)]
 pub struct Ledger {
-    pub accounts: HashMap<Client, Account>,
-    pub history: IndexMap<TransactionId, TransactionState>,
-    pub unprocessed: VecDeque<TransactionState>,
+    pub(crate) accounts: HashMap<Client, Account>,
+    pub(crate) history: History,
+    pub(crate) unprocessed: VecDeque<TransactionState>,
+    /// Ids after the last history entry that were skipped instead of applied, so the
+    /// transactions after them are not held up waiting for them
+    pub(crate) skipped: BTreeSet<TransactionId>,
+    /// Holds back chargebacks when dispute volume spikes, off unless installed
+    pub(crate) breaker: Option<DisputeBreaker>,
+    /// Transactions held back by the breaker, the amount cap or a rule, waiting for an
+    /// operator decision
+    pub(crate) quarantine: Vec<TransactionState>,
+    /// Deposits and withdrawals above this amount are quarantined instead of applied
+    pub(crate) max_amount: Option<Amount>,
+    /// Transactions an operator has cleared to go past the amount cap and the rules
+    pub(crate) approved: HashSet<TransactionId>,
+    /// House rules that can hold deposits and withdrawals for review
+    pub(crate) rules: Vec<Arc<dyn Rule>>,
+    /// Handlers for custom transaction types, by type name
+    pub(crate) handlers: HashMap<String, Arc<dyn TransactionHandler>>,
+    /// Extra columns appended to the report, in the order they were added
+    pub(crate) columns: Vec<(String, ReportColumn)>,
+    /// Write the report in client id order rather than in no particular order
+    pub(crate) sort_report: bool,
+    /// Hands out ids for transactions the engine creates itself, off unless installed
+    pub(crate) ids: Option<Box<dyn IdAllocator>>,
+    /// Ids of the history entries the engine created, which are not part of upstream's
+    /// id sequence
+    pub(crate) generated: BTreeSet<TransactionId>,
+    /// Tells when suspensions run out
+    pub(crate) clock: Arc<dyn Clock>,
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Debug, Error)]
@@ -31,6 +82,83 @@ pub enum LedgerError {
 
     #[error("Transaction is not disputed: {0}")]
     TransactionIsNotDisputed(TransactionId),
+
+    #[error("Transaction was not applied: {0}")]
+    TransactionNotApplied(TransactionId),
+
+    #[error("Transaction type has no handler: {0}")]
+    UnknownTransactionType(String),
+
+    #[error("No id allocator is installed for generated transactions")]
+    NoIdAllocator,
+}
+
+/// Sets up a [`Ledger`] with the checks it should run. Everything is off by default.
+#[derive(Debug, Default)]
+pub struct LedgerBuilder {
+    ledger: Ledger,
+}
+
+impl LedgerBuilder {
+    /// Holds back chargebacks once dispute volume trips `breaker`
+    pub fn breaker(mut self, breaker: DisputeBreaker) -> Self {
+        self.ledger.breaker = Some(breaker);
+        self
+    }
+
+    /// Quarantines deposits and withdrawals above `max_amount`
+    pub fn max_amount(mut self, max_amount: Amount) -> Self {
+        self.ledger.max_amount = Some(max_amount);
+        self
+    }
+
+    /// Lets `approved` go past the amount cap and the rules
+    pub fn approved(mut self, approved: impl IntoIterator<Item = TransactionId>) -> Self {
+        self.ledger.approved.extend(approved);
+        self
+    }
+
+    /// Adds a house rule that can hold deposits and withdrawals for review
+    pub fn rule(mut self, rule: impl Rule + 'static) -> Self {
+        self.ledger.rules.push(Arc::new(rule));
+        self
+    }
+
+    /// Applies transactions of the custom type `name` with `handler`
+    pub fn handler(mut self, name: &str, handler: impl TransactionHandler + 'static) -> Self {
+        self.ledger
+            .handlers
+            .insert(name.to_string(), Arc::new(handler));
+        self
+    }
+
+    /// Appends a column called `name` to the report, filled in by `column`
+    pub fn column(mut self, name: &str, column: ReportColumn) -> Self {
+        self.ledger.columns.push((name.to_string(), column));
+        self
+    }
+
+    /// Writes the report in client id order, so reports of the same input are identical
+    pub fn sort_report(mut self) -> Self {
+        self.ledger.sort_report = true;
+        self
+    }
+
+    /// Takes the time suspensions run out against from `clock` instead of the system
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.ledger.clock = Arc::new(clock);
+        self
+    }
+
+    /// Hands out the ids of transactions the engine creates itself with `ids`
+    pub fn id_allocator(mut self, ids: impl IdAllocator + 'static) -> Self {
+        self.ledger.ids = Some(Box::new(ids));
+        self
+    }
+
+    pub fn build(self) -> Ledger {
+        self.ledger
+    }
 }
 
 impl Ledger {
@@ -39,13 +167,119 @@ impl Ledger {
             accounts: HashMap::new(),
             history: IndexMap::new(),
             unprocessed: VecDeque::new(),
+            skipped: BTreeSet::new(),
+            breaker: None,
+            quarantine: Vec::new(),
+            max_amount: None,
+            approved: HashSet::new(),
+            rules: Vec::new(),
+            handlers: HashMap::new(),
+            columns: Vec::new(),
+            sort_report: false,
+            ids: None,
+            generated: BTreeSet::new(),
+            clock: Arc::new(SystemClock),
         }
     }
 
+    pub fn builder() -> LedgerBuilder {
+        LedgerBuilder::default()
+    }
+
+    pub fn account(&self, client: Client) -> Option<&Account> {
+        self.accounts.get(&client)
+    }
+
+    /// Every account, in no particular order
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
+
+    /// Transactions held back by the breaker, the amount cap or a rule, waiting for an
+    /// operator decision
+    pub fn quarantine(&self) -> &[TransactionState] {
+        &self.quarantine
+    }
+
     fn add_history(&mut self, tx: TransactionState) {
+        if !self.generated.contains(&tx.tx)
+            && self
+                .skipped
+                .first()
+                .is_some_and(|skipped| *skipped <= tx.tx)
+        {
+            self.skipped = self.skipped.split_off(&(tx.tx + 1));
+        }
         self.history.insert(tx.tx, tx);
     }
 
+    /// The id the next deposit, withdrawal or custom transaction must have to be applied
+    /// straight away rather than buffered, once there is any history.
+    pub(crate) fn next_id(&self) -> Option<TransactionId> {
+        Some(self.next_after(self.last_upstream()?))
+    }
+
+    /// The latest history entry upstream sent, leaving out generated transactions.
+    pub(crate) fn last_upstream(&self) -> Option<TransactionId> {
+        self.history
+            .keys()
+            .rev()
+            .find(|tx| !self.generated.contains(tx))
+            .copied()
+    }
+
+    /// The first id after `tx` that has not been skipped.
+    pub(crate) fn next_after(&self, tx: TransactionId) -> TransactionId {
+        let mut next = tx + 1;
+        while self.skipped.contains(&next) {
+            next += 1;
+        }
+        next
+    }
+
+    /// Lets the transactions after a filtered-out row go ahead without it, as if it had
+    /// been applied. Only deposits, withdrawals and custom transactions hold later ids
+    /// up, so skipping any other row changes nothing. Buffered transactions it releases
+    /// are applied, and those refused are returned.
+    pub fn skip(&mut self, tx_type: &TransactionType, tx: TransactionId) -> Vec<Refusal> {
+        let mut refused = Vec::new();
+        if let TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback =
+            tx_type
+        {
+            return refused;
+        }
+        if self.last_upstream().is_some_and(|last| last < tx) {
+            self.skipped.insert(tx);
+        }
+        self.process_unprocessed_transactions(&mut refused);
+        refused
+    }
+
+    fn record_outcome(&mut self, tx: TransactionId, applied: bool) {
+        self.history.entry(tx).and_modify(|transaction| {
+            transaction.status = if applied {
+                TransactionStatus::Applied
+            } else {
+                TransactionStatus::Rejected
+            }
+        });
+    }
+
+    /// Quarantines a deposit or withdrawal above the amount cap or held by a rule unless
+    /// it was approved. It still goes into the history so that later ids are not held up
+    /// behind it.
+    fn hold_for_review(&mut self, tx: &TransactionState) -> bool {
+        let above_cap =
+            matches!((self.max_amount, tx.amount), (Some(max), Some(amount)) if amount > max);
+        if self.approved.contains(&tx.tx) || !(above_cap || self.held_by_rules(tx)) {
+            return false;
+        }
+
+        self.add_history(tx.clone());
+        self.quarantine.push(tx.clone());
+        true
+    }
+
     fn add_unprocessed_transaction(&mut self, tx: TransactionState) {
         self.unprocessed.push_back(tx);
         self.unprocessed
@@ -53,90 +287,137 @@ impl Ledger {
             .sort_by_key(|transaction| transaction.tx);
     }
 
-    fn get_account(&mut self, tx: &TransactionState) -> Result<&mut Account, LedgerError> {
+    pub(crate) fn get_account(
+        &mut self,
+        tx: &TransactionState,
+    ) -> Result<&mut Account, LedgerError> {
         //assumption: No missing accounts
-        self.accounts
+        let account = self
+            .accounts
             .get_mut(&tx.client)
-            .ok_or_else(|| LedgerError::AccountMissing(tx.client))
+            .ok_or(LedgerError::AccountMissing(tx.client))?;
+        account.expire_suspension(self.clock.now());
+        Ok(account)
     }
 
     fn get_historical_transaction_amount(
         &self,
         tx: &TransactionState,
         check_dispute: bool,
-    ) -> Result<Decimal, LedgerError> {
+    ) -> Result<Amount, LedgerError> {
         match self.history.get(&tx.tx) {
             Some(transaction) => {
                 if check_dispute && !transaction.disputed {
                     return Err(LedgerError::TransactionIsNotDisputed(transaction.tx));
                 }
+                // only a disputed transaction can be resolved or charged back, and a
+                // quarantined, refused or charged back one has no funds that could be held
+                if transaction.status != TransactionStatus::Applied {
+                    return Err(LedgerError::TransactionNotApplied(transaction.tx));
+                }
 
                 transaction
                     .amount
-                    .ok_or_else(|| LedgerError::TransactionAmountMissing(transaction.tx))
+                    .ok_or(LedgerError::TransactionAmountMissing(transaction.tx))
             }
             None => Err(LedgerError::TransactionNotFound(tx.tx)),
         }
     }
 
     fn check_transaction(&mut self, tx: TransactionState) -> Result<()> {
+        if let TransactionType::Deposit | TransactionType::Withdrawal = tx.tx_type {
+            if self.hold_for_review(&tx) {
+                return Ok(());
+            }
+        }
+
         match tx.tx_type {
             TransactionType::Deposit => {
                 self.add_history(tx.clone());
-                let amount = tx
-                    .amount
-                    .ok_or_else(|| LedgerError::TransactionAmountMissing(tx.tx))?;
-
-                match self.get_account(&tx) {
-                    Ok(account) => {
-                        account.deposit(amount)?;
-                        return Ok(());
+                let amount = match tx.amount {
+                    Some(amount) => amount,
+                    None => {
+                        self.record_outcome(tx.tx, false);
+                        return Err(LedgerError::TransactionAmountMissing(tx.tx).into());
                     }
+                };
+
+                let result = match self.get_account(&tx) {
+                    Ok(account) => account.deposit(amount),
                     Err(_) => {
                         let account = Account::new(&mut amount.clone(), tx.client);
                         self.accounts.insert(tx.client, account);
                         Ok(())
                     }
-                }
+                };
+
+                self.record_outcome(tx.tx, result.is_ok());
+                Ok(result?)
             }
 
             TransactionType::Withdrawal => {
                 self.add_history(tx.clone());
-                let amount = tx
-                    .amount
-                    .ok_or_else(|| LedgerError::TransactionAmountMissing(tx.tx))?;
+                let amount = match tx.amount {
+                    Some(amount) => amount,
+                    None => {
+                        self.record_outcome(tx.tx, false);
+                        return Err(LedgerError::TransactionAmountMissing(tx.tx).into());
+                    }
+                };
 
-                match self.get_account(&tx) {
-                    Ok(account) => account.withdraw(amount)?,
+                let result = match self.get_account(&tx) {
+                    Ok(account) => account.withdraw(amount),
                     Err(_) => {
                         self.add_unprocessed_transaction(tx.clone());
                         return Ok(());
                     }
                 };
 
-                Ok(())
+                self.record_outcome(tx.tx, result.is_ok());
+                Ok(result?)
             }
             TransactionType::Dispute => {
-                self.history
-                    .entry(tx.tx)
-                    .and_modify(|transaction| transaction.disputed = true);
-
                 let amount = self.get_historical_transaction_amount(&tx, false)?;
 
                 let account = self.get_account(&tx)?;
 
                 account.dispute(amount)?;
 
+                // only flagged once the funds are held, so a refused dispute does not leave
+                // the history claiming funds are held that never were
+                self.history
+                    .entry(tx.tx)
+                    .and_modify(|transaction| transaction.disputed = true);
+
                 Ok(())
             }
             TransactionType::Chargeback => {
                 let amount = self.get_historical_transaction_amount(&tx, true)?;
 
                 let account = self.get_account(&tx)?;
-                account.chargeback(amount)?;
+                // a chargeback refused by an account another one already froze must not
+                // take the blame for the lock, but one refused for want of held funds
+                // freezes the account itself
+                let was_locked = account.is_locked();
+                let result = account.chargeback(amount);
+                if !was_locked {
+                    account.record_lock(LockReason::Chargeback, tx.tx);
+                }
+                result?;
+
+                self.history.entry(tx.tx).and_modify(|transaction| {
+                    transaction.disputed = false;
+                    transaction.status = TransactionStatus::ChargedBack;
+                });
 
                 Ok(())
             }
+            TransactionType::Custom(ref name) => {
+                self.add_history(tx.clone());
+                let result = self.apply_custom(name, &tx);
+                self.record_outcome(tx.tx, result.is_ok());
+                result
+            }
             TransactionType::Resolve => {
                 let amount = self.get_historical_transaction_amount(&tx, true)?;
 
@@ -152,47 +433,166 @@ impl Ledger {
         }
     }
 
-    fn process_unprocessed_transactions(&mut self) -> Result<()> {
-        while let (Some(last_tx), Some(unpro_tx)) = (self.history.last(), self.unprocessed.front())
-        {
-            if last_tx.0 + 1 != unpro_tx.tx {
+    /// Applies the buffered transactions that are next in line, carrying on past refusals.
+    fn process_unprocessed_transactions(&mut self, refused: &mut Vec<Refusal>) {
+        while let (Some(next), Some(unpro_tx)) = (self.next_id(), self.unprocessed.front()) {
+            if next != unpro_tx.tx {
                 break;
             }
             let transaction = self.unprocessed.pop_front().unwrap();
-            self.check_transaction(transaction)?;
+            self.check_or_refuse(transaction, refused);
+        }
+    }
+
+    fn check_or_refuse(&mut self, tx: TransactionState, refused: &mut Vec<Refusal>) {
+        if let Err(err) = self.check_transaction(tx.clone()) {
+            refused.push((tx, err));
+        }
+    }
+
+    /// Puts a temporary hold on a client's account. The account goes back to active on the
+    /// first transaction processed at or after `until`.
+    pub fn suspend(&mut self, client: Client, until: SystemTime) -> Result<()> {
+        let account = self
+            .accounts
+            .get_mut(&client)
+            .ok_or(LedgerError::AccountMissing(client))?;
+        account.suspend(until)?;
+        Ok(())
+    }
+
+    /// Moves a client's account to `next` through the account's transition table, e.g.
+    /// putting a frozen account under review so its disputes can be settled before
+    /// [`Ledger::unlock`].
+    pub fn transition(&mut self, client: Client, next: AccountStatus) -> Result<()> {
+        let account = self
+            .accounts
+            .get_mut(&client)
+            .ok_or(LedgerError::AccountMissing(client))?;
+        account.transition(next)?;
+        Ok(())
+    }
+
+    /// Applies a quarantined transaction through the normal ledger path.
+    pub fn approve_quarantined(&mut self, tx: TransactionId) -> Result<()> {
+        let transaction = self.take_quarantined(tx)?;
+        self.approved.insert(tx);
+        self.check_transaction(transaction)
+    }
+
+    /// Drops a quarantined transaction without applying it.
+    pub fn reject_quarantined(&mut self, tx: TransactionId) -> Result<()> {
+        let transaction = self.take_quarantined(tx)?;
+        if let TransactionType::Deposit | TransactionType::Withdrawal = transaction.tx_type {
+            self.record_outcome(tx, false);
         }
         Ok(())
     }
 
-    pub fn process_transaction(&mut self, tx: TransactionState) -> Result<()> {
-        if let Some(last_tx) = self.history.last() {
-            if let TransactionType::Withdrawal | TransactionType::Deposit = tx.tx_type {
-                if last_tx.0 + 1 != tx.tx {
-                    self.add_unprocessed_transaction(tx.clone());
-                    return Ok(());
+    fn take_quarantined(&mut self, tx: TransactionId) -> Result<TransactionState, LedgerError> {
+        let position = self
+            .quarantine
+            .iter()
+            .position(|transaction| transaction.tx == tx)
+            .ok_or(LedgerError::TransactionNotFound(tx))?;
+        Ok(self.quarantine.remove(position))
+    }
+
+    /// Allocates an id for a transaction the engine creates itself, passing over ids
+    /// already in the history.
+    pub fn allocate_id(&mut self) -> Result<TransactionId> {
+        let ids = self.ids.as_mut().ok_or(LedgerError::NoIdAllocator)?;
+        loop {
+            let tx = ids.allocate()?;
+            if !self.history.contains_key(&tx) {
+                return Ok(tx);
+            }
+        }
+    }
+
+    /// Applies a deposit, withdrawal or custom transaction the engine creates itself,
+    /// such as a fee, with an allocated id. It is applied straight away, whatever upstream
+    /// transactions are buffered, and returns the id so it can be disputed later.
+    pub fn process_generated(
+        &mut self,
+        tx_type: TransactionType,
+        client: Client,
+        amount: Option<Amount>,
+    ) -> Result<TransactionId> {
+        if let TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback =
+            tx_type
+        {
+            anyhow::bail!(
+                "a {tx_type:?} refers to an earlier transaction, so it has no id of its own"
+            );
+        }
+        if tx_type == TransactionType::Withdrawal && !self.accounts.contains_key(&client) {
+            return Err(LedgerError::AccountMissing(client).into());
+        }
+        let tx = self.allocate_id()?;
+        self.generated.insert(tx);
+        self.check_transaction(TransactionState {
+            tx_type,
+            client,
+            tx,
+            amount,
+            disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
+        })?;
+        Ok(tx)
+    }
+
+    /// Processes `tx` and the buffered transactions it releases, failing with the error of
+    /// the first one refused. Use [`Ledger::process`] to tell which one that was.
+    pub fn process_transaction(&mut self, tx: impl Into<TransactionState>) -> Result<()> {
+        self.process(tx)
+            .into_iter()
+            .next()
+            .map_or(Ok(()), |(_, err)| Err(err))
+    }
+
+    /// Processes `tx` and the buffered transactions it releases, carrying on past
+    /// refusals, and returns every transaction refused on the way with its own error: `tx`
+    /// itself or one of those released, which were read earlier.
+    pub fn process(&mut self, tx: impl Into<TransactionState>) -> Vec<Refusal> {
+        let tx = tx.into();
+        let mut refused = Vec::new();
+        if self.quarantine_if_tripped(&tx) {
+            return refused;
+        }
+
+        if let Some(next) = self.next_id() {
+            if let TransactionType::Withdrawal
+            | TransactionType::Deposit
+            | TransactionType::Custom(_) = tx.tx_type
+            {
+                if next != tx.tx {
+                    self.add_unprocessed_transaction(tx);
+                    return refused;
                 };
 
                 if let Some(unpro_tx) = self.unprocessed.front() {
-                    if last_tx.0 + 1 == unpro_tx.tx {
+                    if next == unpro_tx.tx {
                         let transaction = self.unprocessed.pop_front().unwrap();
-                        self.check_transaction(transaction)?
+                        self.check_or_refuse(transaction, &mut refused);
                     };
                 }
             }
         }
 
-        self.check_transaction(tx)?;
-
-        self.process_unprocessed_transactions()?;
-
-        Ok(())
+        self.check_or_refuse(tx, &mut refused);
+        self.process_unprocessed_transactions(&mut refused);
+        refused
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rust_decimal_macros::dec;
+    use crate::{amount::amount, clock::ManualClock};
+    use std::time::Duration;
 
     #[test]
     fn test_deposit_new_account() {
@@ -201,12 +601,15 @@ mod tests {
             tx: 1,
             client: 1,
             tx_type: TransactionType::Deposit,
-            amount: Some(dec!(100.0)),
+            amount: Some(amount!(100.0)),
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
         assert!(ledger.process_transaction(tx).is_ok());
         assert_eq!(ledger.accounts.len(), 1);
-        assert_eq!(ledger.accounts[&1].total_funds, dec!(100.0));
+        assert_eq!(ledger.accounts[&1].total_funds, amount!(100.0));
     }
 
     #[test]
@@ -216,15 +619,21 @@ mod tests {
             tx: 1,
             client: 1,
             tx_type: TransactionType::Deposit,
-            amount: Some(dec!(50.0)),
+            amount: Some(amount!(50.0)),
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
         let withdrawal = TransactionState {
             tx: 2,
             client: 1,
             tx_type: TransactionType::Withdrawal,
-            amount: Some(dec!(100.0)),
+            amount: Some(amount!(100.0)),
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
         assert!(ledger.process_transaction(deposit).is_ok());
         assert!(ledger.process_transaction(withdrawal).is_err());
@@ -239,6 +648,9 @@ mod tests {
             tx_type: TransactionType::Dispute,
             amount: None,
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
         assert!(ledger.process_transaction(tx).is_err());
     }
@@ -250,8 +662,11 @@ mod tests {
             tx: 1,
             client: 1,
             tx_type: TransactionType::Deposit,
-            amount: Some(dec!(1.0)),
+            amount: Some(amount!(1.0)),
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
 
         assert!(ledger.process_transaction(tx).is_ok());
@@ -260,13 +675,16 @@ mod tests {
             tx: 2,
             client: 1,
             tx_type: TransactionType::Withdrawal,
-            amount: Some(dec!(1.0)),
+            amount: Some(amount!(1.0)),
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
 
         assert!(ledger.process_transaction(tx_2).is_ok());
 
-        assert_eq!(ledger.accounts[&1].total_funds, dec!(0.0))
+        assert_eq!(ledger.accounts[&1].total_funds, amount!(0.0))
     }
 
     #[test]
@@ -276,8 +694,11 @@ mod tests {
             tx: 1,
             client: 1,
             tx_type: TransactionType::Deposit,
-            amount: Some(dec!(1.0)),
+            amount: Some(amount!(1.0)),
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
 
         assert!(ledger.process_transaction(tx).is_ok());
@@ -288,13 +709,16 @@ mod tests {
             tx_type: TransactionType::Dispute,
             amount: None,
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
 
         assert!(ledger.process_transaction(tx_2).is_ok());
 
-        assert_eq!(ledger.accounts[&1].total_funds, dec!(1.0));
-        assert_eq!(ledger.accounts[&1].held_funds, dec!(1.0));
-        assert_eq!(ledger.accounts[&1].available_funds, dec!(0.0));
+        assert_eq!(ledger.accounts[&1].total_funds, amount!(1.0));
+        assert_eq!(ledger.accounts[&1].held_funds, amount!(1.0));
+        assert_eq!(ledger.accounts[&1].available_funds, amount!(0.0));
 
         let tx_3 = TransactionState {
             tx: 1,
@@ -302,13 +726,16 @@ mod tests {
             tx_type: TransactionType::Resolve,
             amount: None,
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
 
         assert!(ledger.process_transaction(tx_3).is_ok());
 
-        assert_eq!(ledger.accounts[&1].total_funds, dec!(1.0));
-        assert_eq!(ledger.accounts[&1].held_funds, dec!(0.0));
-        assert_eq!(ledger.accounts[&1].available_funds, dec!(1.0));
+        assert_eq!(ledger.accounts[&1].total_funds, amount!(1.0));
+        assert_eq!(ledger.accounts[&1].held_funds, amount!(0.0));
+        assert_eq!(ledger.accounts[&1].available_funds, amount!(1.0));
     }
 
     #[test]
@@ -318,8 +745,11 @@ mod tests {
             tx: 1,
             client: 1,
             tx_type: TransactionType::Deposit,
-            amount: Some(dec!(1.0)),
+            amount: Some(amount!(1.0)),
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
 
         assert!(ledger.process_transaction(tx).is_ok());
@@ -330,13 +760,16 @@ mod tests {
             tx_type: TransactionType::Dispute,
             amount: None,
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
 
         assert!(ledger.process_transaction(tx_2).is_ok());
 
-        assert_eq!(ledger.accounts[&1].total_funds, dec!(1.0));
-        assert_eq!(ledger.accounts[&1].held_funds, dec!(1.0));
-        assert_eq!(ledger.accounts[&1].available_funds, dec!(0.0));
+        assert_eq!(ledger.accounts[&1].total_funds, amount!(1.0));
+        assert_eq!(ledger.accounts[&1].held_funds, amount!(1.0));
+        assert_eq!(ledger.accounts[&1].available_funds, amount!(0.0));
 
         let tx_3 = TransactionState {
             tx: 1,
@@ -344,13 +777,16 @@ mod tests {
             tx_type: TransactionType::Chargeback,
             amount: None,
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
 
         assert!(ledger.process_transaction(tx_3).is_ok());
 
-        assert_eq!(ledger.accounts[&1].total_funds, dec!(0.0));
-        assert_eq!(ledger.accounts[&1].held_funds, dec!(0.0));
-        assert_eq!(ledger.accounts[&1].available_funds, dec!(0.0));
+        assert_eq!(ledger.accounts[&1].total_funds, amount!(0.0));
+        assert_eq!(ledger.accounts[&1].held_funds, amount!(0.0));
+        assert_eq!(ledger.accounts[&1].available_funds, amount!(0.0));
     }
 
     #[test]
@@ -360,8 +796,11 @@ mod tests {
             tx: 1,
             client: 1,
             tx_type: TransactionType::Deposit,
-            amount: Some(dec!(1.0)),
+            amount: Some(amount!(1.0)),
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
 
         assert!(ledger.process_transaction(tx).is_ok());
@@ -370,8 +809,11 @@ mod tests {
             tx: 3,
             client: 2,
             tx_type: TransactionType::Withdrawal,
-            amount: Some(dec!(1.0)),
+            amount: Some(amount!(1.0)),
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
 
         assert!(ledger.process_transaction(tx_2).is_ok());
@@ -380,13 +822,16 @@ mod tests {
             tx: 2,
             client: 2,
             tx_type: TransactionType::Deposit,
-            amount: Some(dec!(1.0)),
+            amount: Some(amount!(1.0)),
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
 
         assert!(ledger.process_transaction(tx_3).is_ok());
-        assert_eq!(ledger.accounts[&1].total_funds, dec!(1.0));
-        assert_eq!(ledger.accounts[&2].total_funds, dec!(0.0));
+        assert_eq!(ledger.accounts[&1].total_funds, amount!(1.0));
+        assert_eq!(ledger.accounts[&2].total_funds, amount!(0.0));
     }
 
     #[test]
@@ -396,8 +841,11 @@ mod tests {
             tx: 1,
             client: 1,
             tx_type: TransactionType::Deposit,
-            amount: Some(dec!(100.0)),
+            amount: Some(amount!(100.0)),
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
         let chargeback = TransactionState {
             tx: 1,
@@ -405,6 +853,9 @@ mod tests {
             tx_type: TransactionType::Chargeback,
             amount: None,
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
         assert!(ledger.process_transaction(deposit).is_ok());
 
@@ -424,8 +875,11 @@ mod tests {
             tx: 1,
             client: 1,
             tx_type: TransactionType::Deposit,
-            amount: Some(dec!(100.0)),
+            amount: Some(amount!(100.0)),
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
         let resolve = TransactionState {
             tx: 1,
@@ -433,6 +887,9 @@ mod tests {
             tx_type: TransactionType::Resolve,
             amount: None,
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
         assert!(ledger.process_transaction(deposit).is_ok());
 
@@ -454,6 +911,9 @@ mod tests {
             tx_type: TransactionType::Deposit,
             amount: None,
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         };
 
         assert!(matches!(
@@ -464,4 +924,188 @@ mod tests {
             Ok(LedgerError::TransactionAmountMissing(1))
         ));
     }
+
+    #[test]
+    fn test_suspended_account_reactivates() {
+        let clock = ManualClock::at(SystemTime::UNIX_EPOCH);
+        let mut ledger = Ledger::builder().clock(clock.clone()).build();
+        ledger
+            .process_transaction(crate::test_support::tx::deposit(1, 1, "10.0"))
+            .unwrap();
+        ledger
+            .suspend(1, SystemTime::UNIX_EPOCH + Duration::from_secs(3600))
+            .unwrap();
+        clock.advance(Duration::from_secs(3599));
+        assert!(ledger
+            .process_transaction(crate::test_support::tx::deposit(1, 2, "1.0"))
+            .is_err());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(ledger
+            .process_transaction(crate::test_support::tx::deposit(1, 3, "1.0"))
+            .is_ok());
+        assert_eq!(ledger.accounts[&1].status, AccountStatus::Active);
+    }
+
+    #[test]
+    fn test_dispute_flags_follow_history() {
+        use crate::test_support::tx;
+
+        let mut ledger = crate::test_support::ledger_with_accounts(&[(1, "10.0"), (1, "5.0")]);
+        ledger.process_transaction(tx::dispute(1, 1)).unwrap();
+        ledger.process_transaction(tx::dispute(1, 2)).unwrap();
+        assert!(ledger.history[&1].disputed);
+
+        ledger.process_transaction(tx::resolve(1, 1)).unwrap();
+        assert!(!ledger.history[&1].disputed);
+        assert_eq!(ledger.history[&1].status, TransactionStatus::Applied);
+
+        ledger.process_transaction(tx::chargeback(1, 2)).unwrap();
+        assert!(!ledger.history[&2].disputed);
+        assert_eq!(ledger.history[&2].status, TransactionStatus::ChargedBack);
+        assert_eq!(ledger.accounts[&1].total_funds, amount!(10.0));
+    }
+
+    #[test]
+    fn test_refused_dispute_leaves_transaction_undisputed() {
+        use crate::test_support::tx;
+
+        let mut ledger = crate::test_support::ledger_with_accounts(&[(1, "10.0")]);
+        ledger
+            .process_transaction(tx::withdrawal(1, 2, "8.0"))
+            .unwrap();
+        // only 2.0 is left available to hold against the 10.0 deposit
+        assert!(ledger.process_transaction(tx::dispute(1, 1)).is_err());
+
+        assert!(!ledger.history[&1].disputed);
+        assert!(ledger.process_transaction(tx::chargeback(1, 1)).is_err());
+        assert_eq!(ledger.accounts[&1].held_funds, amount!(0.0));
+        assert!(ledger.audit().is_clean());
+    }
+
+    #[test]
+    fn test_outcomes_recorded_in_history() {
+        use crate::test_support::tx;
+
+        let mut ledger = crate::test_support::ledger_with_accounts(&[(1, "10.0")]);
+        assert!(ledger
+            .process_transaction(tx::withdrawal(1, 2, "10.5"))
+            .is_err());
+        ledger
+            .process_transaction(tx::withdrawal(1, 3, "10.0"))
+            .unwrap();
+
+        assert_eq!(ledger.history[&1].status, TransactionStatus::Applied);
+        assert_eq!(ledger.history[&2].status, TransactionStatus::Rejected);
+        assert_eq!(ledger.history[&3].status, TransactionStatus::Applied);
+        assert_eq!(ledger.accounts[&1].available_funds, amount!(0.0));
+    }
+
+    #[test]
+    fn test_buffered_transactions_apply_in_id_order() {
+        use crate::test_support::tx;
+
+        let mut ledger = Ledger::new();
+        ledger
+            .process_transaction(tx::deposit(1, 1, "1.0"))
+            .unwrap();
+        ledger
+            .process_transaction(tx::withdrawal(1, 3, "4.0"))
+            .unwrap();
+        assert_eq!(ledger.unprocessed.len(), 1);
+
+        ledger
+            .process_transaction(tx::deposit(1, 2, "3.0"))
+            .unwrap();
+        assert!(ledger.unprocessed.is_empty());
+        assert_eq!(ledger.accounts[&1].total_funds, amount!(0.0));
+    }
+
+    #[test]
+    fn test_refusal_of_buffered_transaction_names_it() {
+        use crate::test_support::tx;
+
+        let mut ledger = Ledger::new();
+        assert!(ledger.process(tx::deposit(1, 1, "1.0")).is_empty());
+        let mut withdrawal = TransactionState::from(tx::withdrawal(1, 3, "4.0"));
+        withdrawal.line = 3;
+        assert!(ledger.process(withdrawal).is_empty());
+
+        let refusals = ledger.process(tx::deposit(1, 2, "1.0"));
+        assert_eq!(refusals.len(), 1);
+        assert_eq!((refusals[0].0.tx, refusals[0].0.line), (3, 3));
+        assert!(ledger.unprocessed.is_empty());
+        assert_eq!(ledger.accounts[&1].total_funds, amount!(2.0));
+    }
+
+    #[test]
+    fn test_amount_cap_quarantines_jumbo_transactions() {
+        use crate::test_support::{amount, tx};
+
+        let mut ledger = Ledger::new();
+        ledger.max_amount = Some(amount("1000"));
+        ledger.approved.insert(4);
+        for transaction in [
+            tx::deposit(1, 1, "10.0"),
+            tx::deposit(1, 2, "1000000.0"),
+            tx::deposit(1, 3, "5.0"),
+            tx::deposit(1, 4, "2000.0"),
+        ] {
+            ledger.process_transaction(transaction).unwrap();
+        }
+
+        // the jumbo deposit does not hold up the ids after it
+        assert_eq!(ledger.accounts[&1].total_funds, amount("2015.0"));
+        assert_eq!(ledger.quarantine.len(), 1);
+        assert_eq!(ledger.history[&2].status, TransactionStatus::Pending);
+
+        ledger.reject_quarantined(2).unwrap();
+        assert_eq!(ledger.history[&2].status, TransactionStatus::Rejected);
+        assert_eq!(ledger.accounts[&1].total_funds, amount("2015.0"));
+        assert!(ledger.audit().is_clean());
+    }
+
+    #[test]
+    fn test_refused_chargeback_keeps_the_lock_of_the_first() {
+        use crate::test_support::{ledger_with_accounts, tx};
+
+        let mut ledger = ledger_with_accounts(&[(1, "10.0"), (1, "5.0")]);
+        ledger.process_transaction(tx::dispute(1, 1)).unwrap();
+        ledger.process_transaction(tx::dispute(1, 2)).unwrap();
+        ledger.process_transaction(tx::chargeback(1, 1)).unwrap();
+        assert!(ledger.process_transaction(tx::chargeback(1, 2)).is_err());
+
+        let account = &ledger.accounts[&1];
+        assert_eq!(account.locked_reason, Some(LockReason::Chargeback));
+        assert_eq!(account.locked_by_tx, Some(1));
+        assert_eq!(account.held_funds, amount!(5.0));
+    }
+
+    #[test]
+    fn test_dispute_of_quarantined_transaction_is_refused() {
+        use crate::test_support::{amount, tx};
+
+        let mut ledger = Ledger::new();
+        ledger.max_amount = Some(amount("1000"));
+        ledger
+            .process_transaction(tx::deposit(1, 1, "10.0"))
+            .unwrap();
+        ledger
+            .process_transaction(tx::deposit(1, 2, "5000.0"))
+            .unwrap();
+        assert_eq!(ledger.history[&2].status, TransactionStatus::Pending);
+
+        assert!(matches!(
+            ledger
+                .process_transaction(tx::dispute(1, 2))
+                .unwrap_err()
+                .downcast::<LedgerError>(),
+            Ok(LedgerError::TransactionNotApplied(2))
+        ));
+        assert!(!ledger.history[&2].disputed);
+        assert!(ledger.process_transaction(tx::chargeback(1, 2)).is_err());
+        assert_eq!(ledger.accounts[&1].available_funds, amount("10.0"));
+        assert_eq!(ledger.accounts[&1].held_funds, amount("0"));
+        assert!(ledger.audit().is_clean());
+    }
 }
@@ -1,6 +1,62 @@
 mod account;
+pub mod amount;
+#[cfg(feature = "amqp")]
+mod amqp;
+pub mod anomaly;
+#[cfg(feature = "arrow")]
+mod arrow;
+pub mod audit;
+#[cfg(feature = "avro")]
+mod avro;
+pub mod batching;
+pub mod breaker;
+#[cfg(any(test, feature = "chaos"))]
+pub mod chaos;
+pub mod checkpoint;
+pub mod clock;
+pub mod columns;
 pub mod command;
+pub mod dedup;
+pub mod example;
+pub mod explain;
+pub mod export;
+pub mod filter;
+pub mod gaps;
+pub mod handlers;
+pub mod ids;
+pub mod latency;
 mod ledger;
+#[cfg(feature = "msgpack")]
+mod msgpack;
+#[cfg(feature = "nats")]
+mod nats;
+pub mod prelude;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
 mod reader;
+pub mod rejected;
+#[cfg(feature = "http")]
+pub mod remote;
+pub mod report_files;
+pub mod risk;
+pub mod rollup;
+pub mod rules;
+#[cfg(feature = "aws")]
+mod s3;
+#[cfg(feature = "tower")]
+pub mod service;
+pub mod simulate;
+pub mod sink;
+pub mod source;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub mod summary;
+#[cfg(any(test, feature = "test_support"))]
+pub mod test_support;
+pub mod throttle;
+pub mod timing;
 mod transaction;
+pub mod unlock;
 mod writer;
+#[cfg(feature = "xlsx")]
+mod xlsx;
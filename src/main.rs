@@ -5,5 +5,5 @@ use mini_payments_engine::command::Command;
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Command::parse();
-    cli.run().await
+    cli.run(cli.report_sink()).await
 }
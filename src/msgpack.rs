@@ -0,0 +1,159 @@
+//! MessagePack input, behind the `msgpack` feature.
+//!
+//! The input is a plain sequence of MessagePack values, one per transaction, with no
+//! framing in between since every value carries its own length. A record is either a map
+//! with `type`, `client`, `tx` and an optional `amount` key, or an array of those in that
+//! order, which is how `rmp-serde` writes structs by default. `amount` may be a string, a
+//! number or nil.
+use crate::{
+    amount::AmountFormat,
+    reader::{on_blocking_pool, open_input},
+    source::Row,
+    transaction::{Transaction, TransactionType},
+};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{
+    io::{BufRead, BufReader},
+    path::PathBuf,
+};
+use tokio::sync::mpsc::Sender;
+
+#[derive(Debug, Deserialize)]
+struct MsgpackRecord {
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    client: u16,
+    tx: u32,
+    #[serde(default)]
+    amount: Option<MsgpackAmount>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MsgpackAmount {
+    Text(String),
+    Integer(i64),
+    Float(f64),
+}
+
+pub async fn msgpack_reader(
+    path: &PathBuf,
+    amounts: AmountFormat,
+    channel: Sender<Row>,
+) -> Result<()> {
+    let path = path.clone();
+    on_blocking_pool(move || {
+        let mut input = BufReader::new(open_input(&path)?);
+        let mut record = 0;
+        while !input.fill_buf()?.is_empty() {
+            record += 1;
+            let transaction = rmp_serde::from_read(&mut input)
+                .map_err(anyhow::Error::from)
+                .and_then(|value| parse_record(value, &amounts))
+                .with_context(|| format!("record {record}"))?;
+            if channel.blocking_send((record, transaction)).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+fn parse_record(record: MsgpackRecord, amounts: &AmountFormat) -> Result<Transaction> {
+    let amount = match record.amount {
+        None => None,
+        Some(MsgpackAmount::Text(text)) => Some(amounts.parse(text.trim())?),
+        Some(MsgpackAmount::Integer(value)) => Some(amounts.parse(&value.to_string())?),
+        Some(MsgpackAmount::Float(value)) => Some(amounts.parse(&value.to_string())?),
+    };
+    Ok(Transaction {
+        tx_type: record.tx_type,
+        client: record.client,
+        tx: record.tx,
+        amount,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::amount;
+    use serde::Serialize;
+    use tokio::sync::mpsc::channel;
+
+    #[derive(Serialize)]
+    struct Record<T> {
+        #[serde(rename = "type")]
+        tx_type: &'static str,
+        client: u16,
+        tx: u32,
+        amount: Option<T>,
+    }
+
+    #[tokio::test]
+    async fn test_msgpack_reader() {
+        let mut feed = Vec::new();
+        rmp_serde::encode::write_named(
+            &mut feed,
+            &Record {
+                tx_type: "deposit",
+                client: 3,
+                tx: 1,
+                amount: Some("1.2345"),
+            },
+        )
+        .unwrap();
+        rmp_serde::encode::write(
+            &mut feed,
+            &Record {
+                tx_type: "deposit",
+                client: 3,
+                tx: 2,
+                amount: Some(2.5),
+            },
+        )
+        .unwrap();
+        rmp_serde::encode::write_named(
+            &mut feed,
+            &Record::<i64> {
+                tx_type: "dispute",
+                client: 3,
+                tx: 1,
+                amount: None,
+            },
+        )
+        .unwrap();
+
+        let path = std::env::temp_dir().join(format!("mpe-{}-feed.msgpack", std::process::id()));
+        std::fs::write(&path, &feed).unwrap();
+        let (sender, mut receiver) = channel(8);
+        msgpack_reader(&path, AmountFormat::default(), sender)
+            .await
+            .unwrap();
+
+        // a feed cut off partway through a record is an error
+        std::fs::write(&path, &feed[..feed.len() - 1]).unwrap();
+        let (truncated, _) = channel(8);
+        assert!(msgpack_reader(&path, AmountFormat::default(), truncated)
+            .await
+            .is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let (record, deposit) = receiver.recv().await.unwrap();
+        assert_eq!(record, 1);
+        assert_eq!(deposit.tx_type, TransactionType::Deposit);
+        assert_eq!((deposit.client, deposit.tx), (3, 1));
+        assert_eq!(deposit.amount, Some(amount!(1.2345)));
+
+        let (_, array) = receiver.recv().await.unwrap();
+        assert_eq!(array.tx, 2);
+        assert_eq!(array.amount, Some(amount!(2.5)));
+
+        let (_, dispute) = receiver.recv().await.unwrap();
+        assert_eq!(dispute.tx_type, TransactionType::Dispute);
+        assert_eq!(dispute.amount, None);
+        assert!(receiver.recv().await.is_none());
+    }
+}
@@ -0,0 +1,146 @@
+//! NATS JetStream input, behind the `nats` feature.
+//!
+//! Messages are pulled from an existing consumer and read as json objects with the same
+//! fields as a JSON Lines row, numbered by their stream sequence. A message is only
+//! acked once the ledger has durably applied its row: once a checkpoint covering it has
+//! been written when the run writes checkpoints, and as soon as it is applied otherwise.
+//! Anything not acked when a run dies is redelivered to the next one, and a run resumed
+//! from the checkpoint skips the redelivered rows it already covers.
+use crate::{amount::AmountFormat, reader::parse_json_line, source::Row};
+use anyhow::{anyhow, bail, Context, Result};
+use async_nats::jetstream::{self, consumer::PullConsumer, Message};
+use futures::StreamExt;
+use std::collections::VecDeque;
+use tokio::sync::{
+    mpsc::{unbounded_channel, Sender, UnboundedReceiver},
+    watch,
+};
+
+/// Splits `nats://host:port/stream/consumer` into the server url, the stream and the
+/// consumer.
+pub fn parse_nats_url(url: &str) -> Result<(String, String, String)> {
+    let path = url
+        .strip_prefix("nats://")
+        .ok_or_else(|| anyhow!("not a nats url: {url}"))?;
+    match path.splitn(3, '/').collect::<Vec<_>>()[..] {
+        [server, stream, consumer]
+            if !server.is_empty() && !stream.is_empty() && !consumer.is_empty() =>
+        {
+            Ok((
+                format!("nats://{server}"),
+                stream.to_string(),
+                consumer.to_string(),
+            ))
+        }
+        _ => bail!("nats url needs a server, a stream and a consumer: {url}"),
+    }
+}
+
+pub async fn jetstream_reader(
+    url: String,
+    amounts: AmountFormat,
+    applied: Option<watch::Receiver<u64>>,
+    channel: Sender<Row>,
+) -> Result<()> {
+    let (server, stream, consumer) = parse_nats_url(&url)?;
+    let client = async_nats::connect(&server)
+        .await
+        .with_context(|| format!("connecting to {server}"))?;
+    let consumer: PullConsumer = jetstream::new(client)
+        .get_stream(&stream)
+        .await
+        .with_context(|| format!("looking up stream {stream}"))?
+        .get_consumer(&consumer)
+        .await
+        .with_context(|| format!("looking up consumer {consumer}"))?;
+    let mut messages = consumer.messages().await?;
+
+    // with nothing tracking what the ledger applied, messages are acked once handed over
+    let (read, unacked) = unbounded_channel();
+    let acked = applied.is_some();
+    if let Some(applied) = applied {
+        tokio::spawn(acknowledge(applied, unacked));
+    }
+
+    while let Some(message) = messages.next().await {
+        let message = message?;
+        let sequence = message
+            .info()
+            .map_err(|err| anyhow!("reading message metadata: {err}"))?
+            .stream_sequence;
+        let payload =
+            std::str::from_utf8(&message.payload).with_context(|| format!("message {sequence}"))?;
+        let transaction =
+            parse_json_line(payload, &amounts).with_context(|| format!("message {sequence}"))?;
+
+        if let Some(transaction) = transaction {
+            if channel.send((sequence, transaction)).await.is_err() {
+                break;
+            }
+        }
+        if acked {
+            let _ = read.send((sequence, message));
+        } else {
+            message
+                .ack()
+                .await
+                .map_err(|err| anyhow!("acking message {sequence}: {err}"))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Acks read messages, oldest first, as the last line durably applied moves past them.
+/// Runs until the run stops publishing that line.
+async fn acknowledge(
+    mut applied: watch::Receiver<u64>,
+    mut unacked: UnboundedReceiver<(u64, Message)>,
+) {
+    let mut pending = VecDeque::new();
+    let mut reading = true;
+    loop {
+        tokio::select! {
+            message = unacked.recv(), if reading => match message {
+                Some(message) => pending.push_back(message),
+                None => reading = false,
+            },
+            changed = applied.changed() => if changed.is_err() {
+                return;
+            },
+        }
+
+        let line = *applied.borrow_and_update();
+        while pending
+            .front()
+            .is_some_and(|(sequence, _)| *sequence <= line)
+        {
+            let Some((sequence, message)) = pending.pop_front() else {
+                break;
+            };
+            if let Err(err) = message.ack().await {
+                eprintln!("failed to ack message {sequence}: {err}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nats_url() {
+        assert_eq!(
+            parse_nats_url("nats://localhost:4222/payments/engine").unwrap(),
+            (
+                String::from("nats://localhost:4222"),
+                String::from("payments"),
+                String::from("engine")
+            )
+        );
+        assert!(parse_nats_url("nats://localhost:4222/payments").is_err());
+        assert!(parse_nats_url("nats:///payments/engine").is_err());
+        assert!(parse_nats_url("s3://payments/engine").is_err());
+    }
+}
@@ -0,0 +1,21 @@
+//! The supported public API, for `use mini_payments_engine::prelude::*`.
+//!
+//! Everything re-exported here follows semver: while the crate is at 0.x an incompatible
+//! change to any of it comes with a new minor version and is deprecated for at least one
+//! release before. The other public modules are there for the command line and the
+//! crate's own tests, and may change in any release.
+pub use crate::{
+    account::{Account, AccountError, AccountStatus, LockReason},
+    amount::{Amount, AmountError},
+    breaker::DisputeBreaker,
+    clock::Clock,
+    columns::ReportColumn,
+    handlers::TransactionHandler,
+    ids::IdAllocator,
+    ledger::{Client, History, Ledger, LedgerBuilder, LedgerError, Refusal, TransactionId},
+    rules::Rule,
+    sink::ReportSink,
+    source::{Row, TransactionSource},
+    transaction::{Transaction, TransactionState, TransactionStatus, TransactionType},
+    unlock::{UnlockCondition, UnlockError},
+};
@@ -0,0 +1,200 @@
+//! Length-delimited protobuf input, behind the `protobuf` feature.
+//!
+//! The messages mirror `proto/transaction.proto` and are declared by hand so that
+//! building does not need `protoc`; keep the two in sync.
+use crate::{
+    amount::AmountFormat,
+    reader::{on_blocking_pool, open_input},
+    source::Row,
+    transaction::{Transaction, TransactionType},
+};
+use anyhow::{bail, Context, Result};
+use prost::Message;
+use std::{
+    io::{self, BufReader, Read},
+    path::PathBuf,
+};
+use tokio::sync::mpsc::Sender;
+
+/// Messages larger than this are taken to be a corrupt length prefix
+const MAX_MESSAGE_LEN: u64 = 1024 * 1024;
+
+/// `payments.v1.TransactionType`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum ProtoTransactionType {
+    Unspecified = 0,
+    Deposit = 1,
+    Withdrawal = 2,
+    Dispute = 3,
+    Resolve = 4,
+    Chargeback = 5,
+}
+
+/// `payments.v1.Transaction`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoTransaction {
+    #[prost(enumeration = "ProtoTransactionType", tag = "1")]
+    pub r#type: i32,
+    #[prost(uint32, tag = "2")]
+    pub client: u32,
+    #[prost(uint32, tag = "3")]
+    pub tx: u32,
+    #[prost(string, optional, tag = "4")]
+    pub amount: Option<String>,
+}
+
+impl ProtoTransaction {
+    /// Converts the message, parsing its amount with `amounts`.
+    pub fn into_transaction(self, amounts: &AmountFormat) -> Result<Transaction> {
+        let tx_type = match ProtoTransactionType::try_from(self.r#type) {
+            Ok(ProtoTransactionType::Deposit) => TransactionType::Deposit,
+            Ok(ProtoTransactionType::Withdrawal) => TransactionType::Withdrawal,
+            Ok(ProtoTransactionType::Dispute) => TransactionType::Dispute,
+            Ok(ProtoTransactionType::Resolve) => TransactionType::Resolve,
+            Ok(ProtoTransactionType::Chargeback) => TransactionType::Chargeback,
+            Ok(ProtoTransactionType::Unspecified) | Err(_) => {
+                bail!("unknown transaction type {}", self.r#type)
+            }
+        };
+        let amount = message
+            .amount
+            .map(|amount| amounts.parse(amount.trim()))
+            .transpose()?;
+
+        Ok(Transaction {
+            tx_type,
+            client: u16::try_from(self.client)?,
+            tx: self.tx,
+            amount,
+        })
+    }
+}
+
+pub async fn protobuf_reader(
+    path: &PathBuf,
+    amounts: AmountFormat,
+    channel: Sender<Row>,
+) -> Result<()> {
+    let path = path.clone();
+    on_blocking_pool(move || {
+        let mut input = BufReader::new(open_input(&path)?);
+        let mut buf = Vec::new();
+        let mut message = 0;
+        while let Some(len) = read_length(&mut input)? {
+            message += 1;
+            if len > MAX_MESSAGE_LEN {
+                bail!("message {message} claims to be {len} bytes long");
+            }
+            buf.resize(len as usize, 0);
+            input
+                .read_exact(&mut buf)
+                .with_context(|| format!("message {message} is cut short"))?;
+            let transaction = ProtoTransaction::decode(buf.as_slice())
+                .map_err(anyhow::Error::from)
+                .and_then(|message| message.into_transaction(&amounts))
+                .with_context(|| format!("message {message}"))?;
+            if channel.blocking_send((message, transaction)).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Reads a varint length prefix, or `None` at a clean end of input.
+fn read_length(input: &mut impl Read) -> Result<Option<u64>> {
+    let mut len = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        if let Err(err) = input.read_exact(&mut byte) {
+            if shift == 0 && err.kind() == io::ErrorKind::UnexpectedEof {
+                return Ok(None);
+            }
+            return Err(err).context("length prefix is cut short");
+        }
+        len |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(len));
+        }
+    }
+    bail!("length prefix is longer than 10 bytes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::amount;
+    use tokio::sync::mpsc::channel;
+
+    #[tokio::test]
+    async fn test_protobuf_reader() {
+        let mut feed = Vec::new();
+        for message in [
+            ProtoTransaction {
+                r#type: ProtoTransactionType::Deposit as i32,
+                client: 3,
+                tx: 1,
+                amount: Some(String::from("1.2345")),
+            },
+            ProtoTransaction {
+                r#type: ProtoTransactionType::Dispute as i32,
+                client: 3,
+                tx: 1,
+                amount: None,
+            },
+        ] {
+            message.encode_length_delimited(&mut feed).unwrap();
+        }
+
+        let path = std::env::temp_dir().join(format!("mpe-{}-feed.pb", std::process::id()));
+        std::fs::write(&path, &feed).unwrap();
+        let (sender, mut receiver) = channel(8);
+        protobuf_reader(&path, AmountFormat::default(), sender)
+            .await
+            .unwrap();
+
+        // a feed cut off partway through a message is an error
+        std::fs::write(&path, &feed[..feed.len() - 1]).unwrap();
+        let (truncated, _) = channel(8);
+        assert!(protobuf_reader(&path, AmountFormat::default(), truncated)
+            .await
+            .is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let (message, deposit) = receiver.recv().await.unwrap();
+        assert_eq!(message, 1);
+        assert_eq!(deposit.tx_type, TransactionType::Deposit);
+        assert_eq!((deposit.client, deposit.tx), (3, 1));
+        assert_eq!(deposit.amount, Some(amount!(1.2345)));
+
+        let (_, dispute) = receiver.recv().await.unwrap();
+        assert_eq!(dispute.tx_type, TransactionType::Dispute);
+        assert_eq!(dispute.amount, None);
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[test]
+    fn test_message_conversion_errors() {
+        let unspecified = ProtoTransaction {
+            r#type: 0,
+            client: 1,
+            tx: 1,
+            amount: None,
+        };
+        assert!(unspecified
+            .into_transaction(&AmountFormat::default())
+            .is_err());
+
+        let wide_client = ProtoTransaction {
+            r#type: ProtoTransactionType::Deposit as i32,
+            client: 70_000,
+            tx: 1,
+            amount: Some(String::from("1.0")),
+        };
+        assert!(wide_client
+            .into_transaction(&AmountFormat::default())
+            .is_err());
+    }
+}
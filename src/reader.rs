@@ -1,28 +1,1214 @@
-use anyhow::Result;
-use csv::ReaderBuilder;
+use anyhow::{anyhow, bail, Context, Result};
+use csv::{ReaderBuilder, StringRecord};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, WINDOWS_1252};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use flate2::read::MultiGzDecoder;
+use memchr::{memchr, memchr_iter};
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufRead, BufReader, Read};
 use std::path::PathBuf;
-use tokio::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncBufReadExt;
+use tokio::sync::mpsc::{Receiver, Sender};
 
-use crate::transaction::Transaction;
+use crate::{
+    amount::AmountFormat,
+    rejected::RejectedRows,
+    source::{CsvLayout, CsvQuirks, Row, SourceId, TextEncoding},
+    transaction::{Transaction, TransactionType},
+};
 
-pub async fn reader(path: &PathBuf, channel: Sender<Transaction>) -> Result<()> {
-    let file = File::open(path)?;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const UTF8_BOM: [u8; 3] = [0xef, 0xbb, 0xbf];
+const UTF16LE_BOM: [u8; 2] = [0xff, 0xfe];
+const UTF16BE_BOM: [u8; 2] = [0xfe, 0xff];
+
+/// Opens an input file, transparently decompressing gzip and zstd files recognised by
+/// their magic bytes.
+pub fn open_input(path: &PathBuf) -> Result<Box<dyn Read + Send>> {
     let cap = 4 * 1024 * 1024; // 4MB buffer
-    let buf_reader = BufReader::with_capacity(cap, file);
+    decompress(BufReader::with_capacity(cap, File::open(path)?))
+}
+
+/// Wraps `input` in a gzip or zstd decoder when it starts with their magic bytes.
+pub fn decompress<R: BufRead + Send + 'static>(mut input: R) -> Result<Box<dyn Read + Send>> {
+    let (is_gzip, is_zstd) = {
+        let magic = input.fill_buf()?;
+        (
+            magic.starts_with(&GZIP_MAGIC),
+            magic.starts_with(&ZSTD_MAGIC),
+        )
+    };
+
+    Ok(if is_gzip {
+        Box::new(MultiGzDecoder::new(input))
+    } else if is_zstd {
+        Box::new(zstd::Decoder::with_buffer(input)?)
+    } else {
+        Box::new(input)
+    })
+}
+
+/// Drops the byte order mark from the start of UTF-8 text and transcodes UTF-16 text,
+/// recognised by its byte order mark, to UTF-8, as written by Windows tools. Text without
+/// a byte order mark is transcoded from `encoding`, and passed through as it is when that
+/// is UTF-8.
+pub(crate) fn decode_text(
+    input: Box<dyn Read + Send>,
+    encoding: TextEncoding,
+) -> Result<Box<dyn Read + Send>> {
+    let mut input = BufReader::new(input);
+    let (is_utf8, is_utf16, encoding) = {
+        let start = input.fill_buf()?;
+        let is_utf8 = start.starts_with(&UTF8_BOM);
+        let is_utf16 = start.starts_with(&UTF16LE_BOM) || start.starts_with(&UTF16BE_BOM);
+        let encoding = match encoding {
+            _ if is_utf8 || is_utf16 => None,
+            TextEncoding::Utf8 => None,
+            TextEncoding::Detect => detect_encoding(start),
+            TextEncoding::Other(encoding) => Some(encoding),
+        };
+        (is_utf8, is_utf16, encoding)
+    };
+
+    Ok(if is_utf16 || encoding.is_some() {
+        Box::new(
+            DecodeReaderBytesBuilder::new()
+                .encoding(encoding)
+                .strip_bom(true)
+                .build(input),
+        )
+    } else {
+        if is_utf8 {
+            input.consume(UTF8_BOM.len());
+        }
+        Box::new(input)
+    })
+}
+
+/// Guesses the encoding of text without a byte order mark from its first bytes, `None`
+/// standing for UTF-8.
+fn detect_encoding(start: &[u8]) -> Option<&'static Encoding> {
+    // csv is mostly ascii, which UTF-16 writes with a zero in every other byte
+    let pairs = start.len() / 2;
+    let zeros_at = |offset| {
+        (offset..start.len())
+            .step_by(2)
+            .filter(|&i| start[i] == 0)
+            .count()
+    };
+    if pairs >= 2 {
+        match (zeros_at(0), zeros_at(1)) {
+            (0, odd) if odd * 2 > pairs => return Some(UTF_16LE),
+            (even, 0) if even * 2 > pairs => return Some(UTF_16BE),
+            _ => {}
+        }
+    }
+    match std::str::from_utf8(start) {
+        Ok(_) => None,
+        // the buffer may end halfway through a character
+        Err(err) if err.error_len().is_none() => None,
+        Err(_) => Some(WINDOWS_1252),
+    }
+}
+
+/// Runs a blocking read loop on tokio's blocking thread pool so that reading,
+/// decompressing and parsing a large file does not hold up the runtime's worker threads.
+/// The loop hands rows over with [`Sender::blocking_send`].
+pub(crate) async fn on_blocking_pool<F>(read: F) -> Result<()>
+where
+    F: FnOnce() -> Result<()> + Send + 'static,
+{
+    tokio::task::spawn_blocking(read).await?
+}
+
+/// Reads a csv file. Rows that cannot be read go into `rejected` when it is given, and
+/// stop the read otherwise.
+pub async fn reader(
+    path: &PathBuf,
+    layout: CsvLayout,
+    rejected: Option<RejectedRows>,
+    channel: Sender<Row>,
+) -> Result<()> {
+    let path = path.clone();
+    on_blocking_pool(move || read_csv(open_input(&path)?, layout, rejected, channel)).await
+}
+
+/// Streams a csv body from an `http://` or `https://` url, sending `headers` with the
+/// request. Rows are handed over as the body arrives, and while the channel is full the
+/// body is not read any further, so a slow ledger slows the download down rather than
+/// buffering it.
+#[cfg(feature = "http")]
+pub async fn url_reader(
+    url: String,
+    headers: Vec<(String, String)>,
+    layout: CsvLayout,
+    fast: bool,
+    channel: Sender<Row>,
+) -> Result<()> {
+    on_blocking_pool(move || {
+        let request = headers
+            .iter()
+            .fold(ureq::get(&url), |request, (name, value)| {
+                request.set(name, value)
+            });
+        let response = request.call()?;
+        let body = decompress(BufReader::new(response.into_reader()))?;
+        if fast {
+            read_csv_fast(body, layout, None, channel)
+        } else {
+            read_csv(body, layout, None, channel)
+        }
+    })
+    .await
+}
+
+/// One csv row, with the amount kept as text so it is parsed by the layout's
+/// [`AmountFormat`] rather than going through a float.
+#[derive(Debug, Deserialize)]
+struct CsvRow {
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    client: u16,
+    tx: u32,
+    amount: Option<String>,
+}
+
+pub(crate) fn read_csv(
+    input: Box<dyn Read + Send>,
+    layout: CsvLayout,
+    rejected: Option<RejectedRows>,
+    channel: Sender<Row>,
+) -> Result<()> {
     let mut rdr = ReaderBuilder::new()
-        .has_headers(true)
+        .has_headers(layout.headers)
+        .delimiter(layout.delimiter)
         .trim(csv::Trim::All)
         .flexible(true)
-        .from_reader(buf_reader);
+        .from_reader(decode_text(input, layout.encoding)?);
 
-    for result in rdr.deserialize() {
-        let transaction: Transaction = result?;
-        if channel.send(transaction).await.is_err() {
+    // mapped columns are found by position, whatever the header row calls them
+    let headers = if layout.headers && layout.columns.is_none() {
+        rdr.headers()?.clone()
+    } else {
+        StringRecord::from(vec!["type", "client", "tx", "amount"])
+    };
+    let header_row = if layout.headers {
+        Some(HeaderRow::new(rdr.headers()?.as_byte_record()))
+    } else {
+        None
+    };
+    let mut warned_trailing = false;
+    // rows are read as bytes so that one with invalid UTF-8 can be rejected on its own
+    for result in rdr.byte_records() {
+        let mut record = result?;
+        let line = record.position().map_or(0, |position| position.line());
+        let raw = rejected.is_some().then(|| record.clone());
+        // held to the header's width like the fast path holds rows to four fields, so
+        // `--fast-csv` does not change which files are accepted
+        let trailing = layout.columns.is_none()
+            && record.len() > headers.len()
+            && record.iter().skip(headers.len()).all(<[u8]>::is_empty);
+        let parsed = if header_row
+            .as_ref()
+            .is_some_and(|header| header.matches(&record))
+        {
+            if layout.quirks == CsvQuirks::Warn {
+                eprintln!("line {line}: skipping a repeated header row");
+                continue;
+            }
+            Err(anyhow!(REPEATED_HEADER))
+        } else if trailing && layout.quirks == CsvQuirks::Fail {
+            Err(anyhow!(TRAILING_COLUMNS))
+        } else {
+            if trailing {
+                if !warned_trailing {
+                    eprintln!(
+                        "line {line}: ignoring empty columns after the amount, \
+                         here and further down"
+                    );
+                    warned_trailing = true;
+                }
+                record.truncate(headers.len());
+            }
+            StringRecord::from_byte_record(record)
+                .map_err(anyhow::Error::from)
+                .and_then(|record| parse_record(record, &headers, &layout))
+        };
+        let transaction = match (parsed, &rejected, raw) {
+            (Ok(transaction), _, _) => transaction,
+            (Err(err), Some(rejected), Some(raw)) => {
+                let fields: Vec<_> = raw.iter().map(String::from_utf8_lossy).collect();
+                let delimiter = char::from(layout.delimiter).to_string();
+                rejected.push(line, fields.join(&delimiter), &err);
+                continue;
+            }
+            (Err(err), _, _) => return Err(err.context(format!("line {line}"))),
+        };
+        if channel.blocking_send((line, transaction)).is_err() {
             break;
         }
     }
 
     Ok(())
 }
+
+/// Reads one row of fields named by `headers`, or picked out by the layout's column
+/// mapping, into a transaction.
+pub(crate) fn parse_record(
+    record: StringRecord,
+    headers: &StringRecord,
+    layout: &CsvLayout,
+) -> Result<Transaction> {
+    let record = match layout.columns {
+        Some(columns) => pick_columns(&record, columns)?,
+        None => record,
+    };
+    let row: CsvRow = record.deserialize(Some(headers))?;
+    Ok(Transaction {
+        tx_type: row.tx_type,
+        client: row.client,
+        tx: row.tx,
+        amount: row
+            .amount
+            .map(|amount| layout.amounts.parse(&amount))
+            .transpose()?,
+    })
+}
+
+/// Picks the type, client, tx and amount fields out of a record by position.
+fn pick_columns(record: &StringRecord, columns: [usize; 4]) -> Result<StringRecord> {
+    let mut picked = StringRecord::with_capacity(record.as_slice().len(), 4);
+    for (index, column) in columns.into_iter().enumerate() {
+        match record.get(column) {
+            Some(field) => picked.push_field(field),
+            // a missing amount column reads as an empty amount
+            None if index == 3 => picked.push_field(""),
+            None => bail!("row has no column {}", column + 1),
+        }
+    }
+    Ok(picked)
+}
+
+const REPEATED_HEADER: &str = "repeated header row, as left by concatenating csv files";
+
+const TRAILING_COLUMNS: &str = "expected at most 4 fields, found empty columns after the amount";
+
+/// The header row of an input, to recognise it when it turns up again further down.
+/// Fields are compared ignoring case, surrounding spaces, a byte order mark and trailing
+/// empty columns.
+struct HeaderRow(Vec<Vec<u8>>);
+
+impl HeaderRow {
+    fn new<'a>(fields: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        Self(header_fields(fields))
+    }
+
+    fn matches<'a>(&self, fields: impl IntoIterator<Item = &'a [u8]>) -> bool {
+        let mut fields = fields.into_iter().peekable();
+        // rows of transactions differ in their first field, and are told apart on it alone
+        let first = fields.peek().map(|first| header_field(*first));
+        let expected = self.0.first().map(Vec::as_slice);
+        match (first, expected) {
+            (Some(first), Some(expected)) if first.eq_ignore_ascii_case(expected) => {
+                header_fields(fields) == self.0
+            }
+            _ => false,
+        }
+    }
+}
+
+fn header_fields<'a>(fields: impl IntoIterator<Item = &'a [u8]>) -> Vec<Vec<u8>> {
+    let mut fields: Vec<Vec<u8>> = fields
+        .into_iter()
+        .map(|field| header_field(field).to_ascii_lowercase())
+        .collect();
+    while fields.last().is_some_and(Vec::is_empty) {
+        fields.pop();
+    }
+    fields
+}
+
+fn header_field(field: &[u8]) -> &[u8] {
+    let field = field.trim_ascii();
+    field.strip_prefix(&UTF8_BOM).unwrap_or(field).trim_ascii()
+}
+
+/// The fields of an unquoted csv line.
+fn split_line(line: &[u8], delimiter: u8) -> impl Iterator<Item = &[u8]> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    line.split(move |byte| *byte == delimiter)
+}
+
+/// A line with any empty columns at its end dropped.
+fn without_trailing_delimiters(line: &[u8], delimiter: u8) -> &[u8] {
+    let mut line = line.strip_suffix(b"\r").unwrap_or(line);
+    loop {
+        let trimmed = line.trim_ascii_end();
+        match trimmed.strip_suffix(&[delimiter]) {
+            Some(rest) => line = rest,
+            None => return trimmed,
+        }
+    }
+}
+
+/// Merges the rows of several inputs into one stream ordered by transaction id, tagging
+/// each row with the index of the input it came from and the time it was taken off that
+/// input.
+///
+/// Each input keeps its own order, and on equal ids the earlier input goes first, so the
+/// result is globally ordered as long as every input is ordered by id.
+pub async fn merge(mut inputs: Vec<Receiver<Row>>, channel: Sender<(SourceId, Row, Instant)>) {
+    let mut heads: Vec<Option<(Row, Instant)>> = Vec::with_capacity(inputs.len());
+    let mut order = BinaryHeap::new();
+    for (index, input) in inputs.iter_mut().enumerate() {
+        let head = input.recv().await.map(|row| (row, Instant::now()));
+        if let Some(((_, transaction), _)) = &head {
+            order.push(Reverse((transaction.tx, index)));
+        }
+        heads.push(head);
+    }
+
+    while let Some(Reverse((_, index))) = order.pop() {
+        let Some((row, read_at)) = heads[index].take() else {
+            continue;
+        };
+        if channel.send((index, row, read_at)).await.is_err() {
+            return;
+        }
+
+        if let Some(next) = inputs[index].recv().await {
+            order.push(Reverse((next.1.tx, index)));
+            heads[index] = Some((next, Instant::now()));
+        }
+    }
+}
+
+/// Fast path for the plain `type,client,tx,amount` layout.
+///
+/// Lines and fields are located with memchr's vectorised search and validated with
+/// simdutf8 instead of going through the generic csv state machine. Quoting is not
+/// supported, so inputs with quoted fields have to use [`reader`].
+pub async fn fast_reader(
+    path: &PathBuf,
+    layout: CsvLayout,
+    rejected: Option<RejectedRows>,
+    channel: Sender<Row>,
+) -> Result<()> {
+    let path = path.clone();
+    on_blocking_pool(move || read_csv_fast(open_input(&path)?, layout, rejected, channel)).await
+}
+
+pub(crate) fn read_csv_fast(
+    file: Box<dyn Read + Send>,
+    layout: CsvLayout,
+    rejected: Option<RejectedRows>,
+    channel: Sender<Row>,
+) -> Result<()> {
+    let mut file = decode_text(file, layout.encoding)?;
+    let cap = 4 * 1024 * 1024; // 4MB buffer
+    let mut buf = vec![0u8; cap];
+    let mut filled = 0;
+    let mut line_number = 0;
+    let mut eof = false;
+    let mut header_row = None;
+    let mut warned_trailing = false;
+
+    while !eof {
+        if filled == buf.len() {
+            // a single line is larger than the buffer
+            buf.resize(buf.len() * 2, 0);
+        }
+
+        let read = file.read(&mut buf[filled..])?;
+        eof = read == 0;
+        filled += read;
+
+        let mut start = 0;
+        let mut lines: Vec<&[u8]> = memchr_iter(b'\n', &buf[..filled])
+            .map(|end| {
+                let line = &buf[start..end];
+                start = end + 1;
+                line
+            })
+            .collect();
+
+        if eof && start < filled {
+            lines.push(&buf[start..filled]);
+            start = filled;
+        }
+
+        let mut transactions = Vec::with_capacity(lines.len());
+        for line in lines {
+            line_number += 1;
+            if layout.headers && line_number == 1 {
+                header_row = Some(HeaderRow::new(split_line(line, layout.delimiter)));
+                continue;
+            }
+
+            let parsed = match parse_line(line, &layout) {
+                Ok(parsed) => Ok(parsed),
+                Err(_)
+                    if header_row.as_ref().is_some_and(|header| {
+                        header.matches(split_line(line, layout.delimiter))
+                    }) =>
+                {
+                    if layout.quirks == CsvQuirks::Warn {
+                        eprintln!("line {line_number}: skipping a repeated header row");
+                        continue;
+                    }
+                    Err(anyhow!(REPEATED_HEADER))
+                }
+                Err(err) => match layout.quirks {
+                    CsvQuirks::Warn if layout.columns.is_none() => {
+                        // only dropping trailing delimiters can make a failed row parse
+                        let trimmed = without_trailing_delimiters(line, layout.delimiter);
+                        let parsed = parse_line(trimmed, &layout);
+                        if parsed.is_ok() && !warned_trailing {
+                            eprintln!(
+                                "line {line_number}: ignoring empty columns after the amount, \
+                                 here and further down"
+                            );
+                            warned_trailing = true;
+                        }
+                        parsed.map_err(|_| err)
+                    }
+                    _ => Err(err),
+                },
+            };
+            match (parsed, &rejected) {
+                (Ok(Some(transaction)), _) => transactions.push((line_number, transaction)),
+                (Ok(None), _) => {}
+                (Err(err), Some(rejected)) => {
+                    let raw = String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line));
+                    rejected.push(line_number, raw.into_owned(), &err);
+                }
+                (Err(err), None) => return Err(err.context(format!("line {line_number}"))),
+            }
+        }
+
+        buf.copy_within(start..filled, 0);
+        filled -= start;
+
+        for row in transactions {
+            if channel.blocking_send(row).is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Tails a plain csv file like `tail -f`, sending rows as they are appended and checking
+/// for more every `poll`. A line is only parsed once its newline has been written, so a
+/// row caught halfway through being appended is picked up whole on a later poll.
+/// Returns once the receiving side has gone away.
+pub async fn follow_reader(
+    path: &PathBuf,
+    layout: CsvLayout,
+    channel: Sender<Row>,
+    poll: Duration,
+) -> Result<()> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("opening {}", path.display()))?;
+    let mut file = tokio::io::BufReader::new(file);
+    let mut line = Vec::new();
+    let mut line_number = 0;
+
+    loop {
+        file.read_until(b'\n', &mut line).await?;
+        if line.last() != Some(&b'\n') {
+            if channel.is_closed() {
+                return Ok(());
+            }
+            tokio::time::sleep(poll).await;
+            continue;
+        }
+
+        line_number += 1;
+        if line_number == 1 {
+            if line.starts_with(&UTF16LE_BOM) || line.starts_with(&UTF16BE_BOM) {
+                bail!("UTF-16 input cannot be followed, convert it to UTF-8 first");
+            }
+            if line.starts_with(&UTF8_BOM) {
+                line.drain(..UTF8_BOM.len());
+            }
+        }
+        if !layout.headers || line_number > 1 {
+            if let Some(transaction) = parse_line(&line[..line.len() - 1], &layout)
+                .with_context(|| format!("line {line_number}"))?
+            {
+                if channel.send((line_number, transaction)).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        line.clear();
+    }
+}
+
+/// One line of JSON Lines input. The amount is kept as raw json so it can be parsed
+/// straight into an [`crate::amount::Amount`] without going through a float.
+#[derive(Debug, Deserialize)]
+struct JsonRow {
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    client: u16,
+    tx: u32,
+    #[serde(default)]
+    amount: Option<serde_json::Value>,
+}
+
+/// Reads newline-delimited json objects with the same fields as the csv columns.
+/// Amounts may be json numbers or strings.
+pub async fn jsonl_reader(
+    path: &PathBuf,
+    amounts: AmountFormat,
+    channel: Sender<Row>,
+) -> Result<()> {
+    let path = path.clone();
+    on_blocking_pool(move || read_jsonl(path, amounts, channel)).await
+}
+
+fn read_jsonl(path: PathBuf, amounts: AmountFormat, channel: Sender<Row>) -> Result<()> {
+    let buf_reader = BufReader::new(decode_text(open_input(&path)?, TextEncoding::Utf8)?);
+
+    for (index, line) in buf_reader.lines().enumerate() {
+        let line_number = index as u64 + 1;
+        let line = line?;
+        if let Some(transaction) =
+            parse_json_line(&line, &amounts).with_context(|| format!("line {line_number}"))?
+        {
+            if channel.blocking_send((line_number, transaction)).is_err() {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses one json object, returning `None` for blank lines.
+pub fn parse_json_line(line: &str, amounts: &AmountFormat) -> Result<Option<Transaction>> {
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let row: JsonRow = serde_json::from_str(line)?;
+    let amount = match row.amount {
+        None | Some(serde_json::Value::Null) => None,
+        Some(serde_json::Value::Number(number)) => Some(amounts.parse(&number.to_string())?),
+        Some(serde_json::Value::String(text)) => Some(amounts.parse(text.trim())?),
+        Some(other) => bail!("amount must be a number or a string, found {other}"),
+    };
+
+    Ok(Some(Transaction {
+        tx_type: row.tx_type,
+        client: row.client,
+        tx: row.tx,
+        amount,
+    }))
+}
+
+/// Parses one `type,client,tx[,amount]` record with fields separated by the layout's
+/// delimiter, returning `None` for blank lines. With a column mapping the fields are
+/// picked out of a row of any width instead.
+pub fn parse_line(line: &[u8], layout: &CsvLayout) -> Result<Option<Transaction>> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if line.trim_ascii().is_empty() {
+        return Ok(None);
+    }
+
+    let mut fields = [&line[..0]; 4];
+    let mut count = 0;
+    if let Some(columns) = layout.columns {
+        let row: Vec<&[u8]> = line
+            .split(|byte| *byte == layout.delimiter)
+            .map(<[u8]>::trim_ascii)
+            .collect();
+        for (field, column) in fields.iter_mut().zip(columns) {
+            match row.get(column) {
+                Some(value) => *field = value,
+                // a missing amount column reads as an empty amount
+                None if count == 3 => {}
+                None => bail!("row has no column {}", column + 1),
+            }
+            count += 1;
+        }
+    } else {
+        let mut rest = line;
+        loop {
+            if count == fields.len() {
+                if split_line(rest, layout.delimiter).all(|field| field.trim_ascii().is_empty()) {
+                    bail!(TRAILING_COLUMNS);
+                }
+                bail!("expected at most 4 fields");
+            }
+            match memchr(layout.delimiter, rest) {
+                Some(idx) => {
+                    fields[count] = rest[..idx].trim_ascii();
+                    rest = &rest[idx + 1..];
+                    count += 1;
+                }
+                None => {
+                    fields[count] = rest.trim_ascii();
+                    count += 1;
+                    break;
+                }
+            }
+        }
+    }
+
+    if count < 3 {
+        bail!("expected at least 3 fields, found {count}");
+    }
+
+    let tx_type = TransactionType::from_name(utf8(fields[0])?);
+    let client = utf8(fields[1])?.parse()?;
+    let tx = utf8(fields[2])?.parse()?;
+    let amount = match fields[3] {
+        b"" => None,
+        amount => Some(layout.amounts.parse(utf8(amount)?)?),
+    };
+
+    Ok(Some(Transaction {
+        tx_type,
+        client,
+        tx,
+        amount,
+    }))
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str> {
+    simdutf8::basic::from_utf8(bytes).map_err(|_| anyhow!("field is not valid utf-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::amount;
+
+    #[test]
+    fn test_parse_line() {
+        let tx = parse_line(b"deposit, 1, 2, 1.5\r", &CsvLayout::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(tx.tx_type, TransactionType::Deposit);
+        assert_eq!(tx.client, 1);
+        assert_eq!(tx.tx, 2);
+        assert_eq!(tx.amount, Some(amount!(1.5)));
+    }
+
+    #[test]
+    fn test_parse_line_without_amount() {
+        let with_comma = parse_line(b"dispute,1,2,", &CsvLayout::default())
+            .unwrap()
+            .unwrap();
+        let without_comma = parse_line(b"dispute,1,2", &CsvLayout::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(with_comma.amount, None);
+        assert_eq!(without_comma.amount, None);
+    }
+
+    #[test]
+    fn test_parse_blank_line() {
+        assert!(parse_line(b"  \r", &CsvLayout::default())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_parse_line_with_delimiter() {
+        let pipes = CsvLayout {
+            delimiter: b'|',
+            ..CsvLayout::default()
+        };
+        let tx = parse_line(b"withdrawal|3|4|0.25", &pipes).unwrap().unwrap();
+        assert_eq!(tx.tx_type, TransactionType::Withdrawal);
+        assert_eq!((tx.client, tx.tx), (3, 4));
+        assert_eq!(tx.amount, Some(amount!(0.25)));
+        assert!(parse_line(b"withdrawal,3,4,0.25", &pipes).is_err());
+    }
+
+    #[test]
+    fn test_parse_line_with_column_mapping() {
+        // id, memo, client, type, amount
+        let layout = CsvLayout {
+            columns: Some([3, 2, 0, 4]),
+            ..CsvLayout::default()
+        };
+        let tx = parse_line(b"7, coffee, 2, deposit, 3.5", &layout)
+            .unwrap()
+            .unwrap();
+        assert_eq!(tx.tx_type, TransactionType::Deposit);
+        assert_eq!((tx.client, tx.tx), (2, 7));
+        assert_eq!(tx.amount, Some(amount!(3.5)));
+
+        let dispute = parse_line(b"7,,2,dispute", &layout).unwrap().unwrap();
+        assert_eq!(dispute.amount, None);
+        assert!(parse_line(b"7,,2", &layout).is_err());
+    }
+
+    #[test]
+    fn test_parse_line_errors() {
+        assert!(parse_line(b"deposit,1", &CsvLayout::default()).is_err());
+        assert!(parse_line(b"deposit,1,2,1.0,extra", &CsvLayout::default()).is_err());
+        assert!(parse_line(b"deposit,-1,2,1.0", &CsvLayout::default()).is_err());
+        assert!(parse_line(b"deposit,1,2,abc", &CsvLayout::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_custom_types() {
+        let bonus = parse_line(b"bonus,1,2,1.0", &CsvLayout::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            bonus.tx_type,
+            TransactionType::Custom(String::from("bonus"))
+        );
+
+        let refund = parse_json_line(
+            r#"{"type":"refund","client":1,"tx":2}"#,
+            &AmountFormat::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            refund.tx_type,
+            TransactionType::Custom(String::from("refund"))
+        );
+    }
+
+    #[test]
+    fn test_parse_line_with_strict_amounts() {
+        let strict = CsvLayout {
+            amounts: AmountFormat::STRICT,
+            ..CsvLayout::default()
+        };
+        assert!(parse_line(b"deposit,1,2,1.5e3", &CsvLayout::default()).is_ok());
+        assert!(parse_line(b"deposit,1,2,1.5e3", &strict).is_err());
+        assert!(parse_line(b"deposit,1,2,1.23456", &strict).is_err());
+        assert!(parse_line(b"deposit,1,2,1.2345", &strict).is_ok());
+    }
+
+    #[test]
+    fn test_parse_json_line() {
+        let tx = parse_json_line(
+            r#"{"type": "deposit", "client": 1, "tx": 2, "amount": 1.2345}"#,
+            &AmountFormat::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(tx.tx_type, TransactionType::Deposit);
+        assert_eq!(tx.client, 1);
+        assert_eq!(tx.tx, 2);
+        assert_eq!(tx.amount, Some(amount!(1.2345)));
+
+        let tx = parse_json_line(
+            r#"{"type":"withdrawal","client":1,"tx":3,"amount":"0.1"}"#,
+            &AmountFormat::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(tx.amount, Some(amount!(0.1)));
+    }
+
+    #[test]
+    fn test_parse_json_line_without_amount() {
+        let missing = parse_json_line(
+            r#"{"type":"dispute","client":1,"tx":2}"#,
+            &AmountFormat::default(),
+        )
+        .unwrap()
+        .unwrap();
+        let null = parse_json_line(
+            r#"{"type":"dispute","client":1,"tx":2,"amount":null}"#,
+            &AmountFormat::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(missing.amount, None);
+        assert_eq!(null.amount, None);
+        assert!(parse_json_line("   ", &AmountFormat::default())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_parse_json_line_errors() {
+        assert!(
+            parse_json_line(r#"{"type":7,"client":1,"tx":2}"#, &AmountFormat::default()).is_err()
+        );
+        assert!(parse_json_line(
+            r#"{"type":"deposit","client":1,"tx":2,"amount":true}"#,
+            &AmountFormat::default()
+        )
+        .is_err());
+        assert!(
+            parse_json_line(r#"{"type":"deposit","client":1}"#, &AmountFormat::default()).is_err()
+        );
+        assert!(parse_json_line("deposit,1,2,1.0", &AmountFormat::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reader_without_headers() {
+        use tokio::sync::mpsc::channel;
+
+        let path = std::env::temp_dir().join(format!("mpe-{}-pipes.csv", std::process::id()));
+        std::fs::write(&path, "deposit|1|1|2.0\ndispute|1|1|\n").unwrap();
+        let layout = CsvLayout {
+            headers: false,
+            delimiter: b'|',
+            ..CsvLayout::default()
+        };
+
+        for fast in [false, true] {
+            let (sender, mut receiver) = channel(8);
+            if fast {
+                fast_reader(&path, layout, None, sender).await.unwrap();
+            } else {
+                reader(&path, layout, None, sender).await.unwrap();
+            }
+
+            let (line, deposit) = receiver.recv().await.unwrap();
+            assert_eq!((line, deposit.tx_type), (1, TransactionType::Deposit));
+            assert_eq!(deposit.amount, Some(amount!(2.0)));
+            let (line, dispute) = receiver.recv().await.unwrap();
+            assert_eq!((line, dispute.tx_type), (2, TransactionType::Dispute));
+            assert!(receiver.recv().await.is_none());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reader_collects_rejected_rows() {
+        use tokio::sync::mpsc::channel;
+
+        let path = std::env::temp_dir().join(format!("mpe-{}-bad-rows.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,x,2,1.0\ndeposit,1,3,1.0\n",
+        )
+        .unwrap();
+
+        for fast in [false, true] {
+            let (sender, mut receiver) = channel(8);
+            let rejected = RejectedRows::default();
+            let layout = CsvLayout::default();
+            if fast {
+                fast_reader(&path, layout, Some(rejected.clone()), sender)
+                    .await
+                    .unwrap();
+            } else {
+                reader(&path, layout, Some(rejected.clone()), sender)
+                    .await
+                    .unwrap();
+            }
+
+            let mut lines = vec![];
+            while let Some((line, _)) = receiver.recv().await {
+                lines.push(line);
+            }
+            assert_eq!(lines, vec![2, 4]);
+            let rows = rejected.take();
+            assert_eq!(rows.len(), 1);
+            assert_eq!((rows[0].line, rows[0].raw.as_str()), (3, "deposit,x,2,1.0"));
+            assert!(!rows[0].error.is_empty());
+        }
+
+        let (sender, _receiver) = channel(8);
+        assert!(reader(&path, CsvLayout::default(), None, sender)
+            .await
+            .is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_csv_quirks() {
+        // two exports concatenated, the second with a byte order mark, and a row with a
+        // trailing delimiter
+        let csv = [
+            &b"type,client,tx,amount\ndeposit,1,1,2.0\n"[..],
+            &UTF8_BOM,
+            b"type, client, tx, amount\ndeposit,1,2,1.0,\n",
+        ]
+        .concat();
+        let read = |fast: bool, quirks| {
+            let (sender, mut receiver) = tokio::sync::mpsc::channel(8);
+            let input: Box<dyn Read + Send> = Box::new(std::io::Cursor::new(csv.clone()));
+            let layout = CsvLayout {
+                quirks,
+                ..CsvLayout::default()
+            };
+            let read = if fast {
+                read_csv_fast(input, layout, None, sender)
+            } else {
+                read_csv(input, layout, None, sender)
+            };
+            let mut lines = vec![];
+            while let Ok((line, _)) = receiver.try_recv() {
+                lines.push(line);
+            }
+            read.map(|()| lines)
+        };
+
+        for fast in [false, true] {
+            assert_eq!(read(fast, CsvQuirks::Warn).unwrap(), vec![2, 4]);
+            let err = read(fast, CsvQuirks::Fail).unwrap_err();
+            assert_eq!(format!("{err:#}"), format!("line 3: {REPEATED_HEADER}"));
+        }
+        let err = parse_line(b"deposit,1,2,1.0,", &CsvLayout::default()).unwrap_err();
+        assert!(err.to_string().contains("empty columns after the amount"));
+    }
+
+    #[test]
+    fn test_readers_agree_on_trailing_empty_columns() {
+        let csv = b"type,client,tx,amount\ndeposit,1,1,2.0,,\ndeposit,1,2,1.0\n";
+        let read = |fast: bool, quirks| {
+            let (sender, mut receiver) = tokio::sync::mpsc::channel(8);
+            let input: Box<dyn Read + Send> = Box::new(std::io::Cursor::new(csv.to_vec()));
+            let layout = CsvLayout {
+                quirks,
+                ..CsvLayout::default()
+            };
+            let read = if fast {
+                read_csv_fast(input, layout, None, sender)
+            } else {
+                read_csv(input, layout, None, sender)
+            };
+            let mut rows = vec![];
+            while let Ok((line, transaction)) = receiver.try_recv() {
+                rows.push((line, transaction.amount));
+            }
+            read.map(|()| rows)
+        };
+
+        for fast in [false, true] {
+            assert_eq!(
+                read(fast, CsvQuirks::Warn).unwrap(),
+                vec![(2, Some(amount!(2.0))), (3, Some(amount!(1.0)))]
+            );
+            let err = read(fast, CsvQuirks::Fail).unwrap_err();
+            assert_eq!(format!("{err:#}"), format!("line 2: {TRAILING_COLUMNS}"));
+        }
+    }
+
+    /// Rows a second read out of `csv` by one of the csv readers, the channel drained as
+    /// the ledger task would.
+    fn rows_per_sec(csv: &[u8], fast: bool) -> f64 {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1024);
+        let input: Box<dyn Read + Send> = Box::new(std::io::Cursor::new(csv.to_vec()));
+        let started = Instant::now();
+        let reader = std::thread::spawn(move || {
+            if fast {
+                read_csv_fast(input, CsvLayout::default(), None, sender)
+            } else {
+                read_csv(input, CsvLayout::default(), None, sender)
+            }
+        });
+        let mut rows = 0;
+        while receiver.blocking_recv().is_some() {
+            rows += 1;
+        }
+        reader.join().unwrap().unwrap();
+        rows as f64 / started.elapsed().as_secs_f64()
+    }
+
+    #[test]
+    #[ignore = "throughput comparison, run in release mode"]
+    fn test_fast_csv_throughput() {
+        use std::fmt::Write;
+
+        let mut csv = String::from("type,client,tx,amount\n");
+        for tx in 1..=1_000_000u32 {
+            let tx_type = if tx % 4 == 0 { "withdrawal" } else { "deposit" };
+            writeln!(
+                csv,
+                "{tx_type},{},{tx},{}.{:04}",
+                tx % 5000,
+                tx % 997,
+                tx % 10000
+            )
+            .unwrap();
+        }
+
+        let generic = rows_per_sec(csv.as_bytes(), false);
+        let fast = rows_per_sec(csv.as_bytes(), true);
+        println!("csv reader: {generic:.0} rows/s, fast reader: {fast:.0} rows/s");
+        assert!(
+            fast >= 2.0 * generic,
+            "the fast reader is only {:.2}x the csv reader",
+            fast / generic
+        );
+    }
+
+    #[test]
+    fn test_byte_order_marks() {
+        let csv = "type,client,tx,amount\r\ndeposit,1,1,2.0\r\n";
+        let utf8 = [&UTF8_BOM[..], csv.as_bytes()].concat();
+        let utf16le: Vec<u8> = UTF16LE_BOM
+            .into_iter()
+            .chain(csv.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        let utf16be: Vec<u8> = UTF16BE_BOM
+            .into_iter()
+            .chain(csv.encode_utf16().flat_map(u16::to_be_bytes))
+            .collect();
+
+        for input in [utf8, utf16le, utf16be] {
+            for fast in [false, true] {
+                let (sender, mut receiver) = tokio::sync::mpsc::channel(8);
+                let input: Box<dyn Read + Send> = Box::new(std::io::Cursor::new(input.clone()));
+                if fast {
+                    read_csv_fast(input, CsvLayout::default(), None, sender).unwrap();
+                } else {
+                    read_csv(input, CsvLayout::default(), None, sender).unwrap();
+                }
+
+                let (line, deposit) = receiver.try_recv().unwrap();
+                assert_eq!((line, deposit.tx_type), (2, TransactionType::Deposit));
+                assert_eq!(deposit.amount, Some(amount!(2.0)));
+                assert!(receiver.try_recv().is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_text_transcodes_other_encodings() {
+        let decode = |bytes: &[u8], encoding| {
+            let input: Box<dyn Read + Send> = Box::new(std::io::Cursor::new(bytes.to_vec()));
+            let mut text = String::new();
+            decode_text(input, encoding)
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        };
+        let cp1252 = b"type,client,tx,amount\nd\xe9p\xf4t,1,1,2.0\n";
+        let utf16le: Vec<u8> = "type,client,tx,amount\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+
+        let windows_1252 = "windows-1252".parse().unwrap();
+        assert_eq!(
+            decode(cp1252, windows_1252),
+            "type,client,tx,amount\ndépôt,1,1,2.0\n"
+        );
+        assert_eq!(
+            decode(cp1252, TextEncoding::Detect),
+            "type,client,tx,amount\ndépôt,1,1,2.0\n"
+        );
+        assert_eq!(
+            decode(&utf16le, TextEncoding::Detect),
+            "type,client,tx,amount\n"
+        );
+        assert_eq!(decode("dépôt".as_bytes(), TextEncoding::Detect), "dépôt");
+        assert_eq!("UTF8".parse::<TextEncoding>().unwrap(), TextEncoding::Utf8);
+        assert!("klingon".parse::<TextEncoding>().is_err());
+    }
+
+    fn read_back(name: &str, contents: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("mpe-{}-{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let mut text = String::new();
+        open_input(&path)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        text
+    }
+
+    #[test]
+    fn test_open_input_decompresses() {
+        use std::io::Write;
+
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(csv.as_bytes()).unwrap();
+        assert_eq!(read_back("in.csv.gz", &gzip.finish().unwrap()), csv);
+
+        let zst = zstd::encode_all(csv.as_bytes(), 0).unwrap();
+        assert_eq!(read_back("in.csv.zst", &zst), csv);
+
+        assert_eq!(read_back("in.csv", csv.as_bytes()), csv);
+    }
+
+    #[tokio::test]
+    async fn test_merge_orders_by_transaction_id() {
+        use crate::test_support::tx;
+        use tokio::sync::mpsc::channel;
+
+        let inputs = [
+            vec![tx::deposit(1, 1, "1.0"), tx::deposit(1, 4, "1.0")],
+            vec![tx::deposit(2, 2, "1.0"), tx::deposit(2, 3, "1.0")],
+            vec![],
+        ];
+        let mut receivers = vec![];
+        for transactions in inputs {
+            let (sender, receiver) = channel(8);
+            for (line, transaction) in transactions.into_iter().enumerate() {
+                sender.send((line as u64 + 2, transaction)).await.unwrap();
+            }
+            receivers.push(receiver);
+        }
+
+        let (sender, mut merged) = channel(8);
+        merge(receivers, sender).await;
+
+        let mut ids = vec![];
+        while let Some((source, (_, transaction), _)) = merged.recv().await {
+            ids.push((source, transaction.tx));
+        }
+        assert_eq!(ids, vec![(0, 1), (1, 2), (1, 3), (0, 4)]);
+    }
+
+    #[tokio::test]
+    async fn test_follow_reader_picks_up_appended_rows() {
+        use std::io::Write;
+        use tokio::sync::mpsc::channel;
+
+        let path = std::env::temp_dir().join(format!("mpe-{}-follow.csv", std::process::id()));
+        std::fs::write(&path, "type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+
+        let (sender, mut receiver) = channel(8);
+        let following = path.clone();
+        let follower = tokio::spawn(async move {
+            follow_reader(
+                &following,
+                CsvLayout::default(),
+                sender,
+                Duration::from_millis(5),
+            )
+            .await
+        });
+        assert_eq!(receiver.recv().await.unwrap().1.tx, 1);
+
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"deposit,1,").unwrap();
+        file.flush().unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        file.write_all(b"2,2.0\n").unwrap();
+        file.flush().unwrap();
+
+        let (line, transaction) = receiver.recv().await.unwrap();
+        assert_eq!((line, transaction.tx), (3, 2));
+        assert_eq!(transaction.amount, Some(amount!(2.0)));
+
+        drop(receiver);
+        follower.await.unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+//! Malformed input rows, and transactions the ledger refused.
+//!
+//! A csv row that cannot be read as a transaction stops the run by default. A source
+//! handed a [`RejectedRows`] collector instead records the row's line number, its content
+//! and why it was refused, and reads on, so the bad rows can be sent back to whoever
+//! produced the file. A refused row never reaches the ledger: when it was a deposit or a
+//! withdrawal its id is missing, and later ones waiting on it are listed at the end of
+//! the run like those behind any other missing id.
+//!
+//! Rows that are read fine but refused by the ledger, say a withdrawal beyond the
+//! available funds or a dispute of an unknown transaction, are [`RefusedTransaction`]s,
+//! written out by `--errors` with the code and reason of their rejection.
+use crate::{source::SourceId, transaction::Transaction};
+use anyhow::Result;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// A row that could not be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedRow {
+    pub line: u64,
+    /// The row as read: its fields joined by the delimiter for the csv reader, the line
+    /// itself for the fast path
+    pub raw: String,
+    pub error: String,
+}
+
+/// Rows rejected by one source, shared between the source's reader and the run
+#[derive(Debug, Clone, Default)]
+pub struct RejectedRows(Arc<Mutex<Vec<RejectedRow>>>);
+
+impl RejectedRows {
+    pub fn push(&self, line: u64, raw: String, error: &anyhow::Error) {
+        let row = RejectedRow {
+            line,
+            raw,
+            error: format!("{error:#}"),
+        };
+        self.0.lock().expect("rejected rows poisoned").push(row);
+    }
+
+    /// The rows rejected so far, in the order they were read.
+    pub fn take(&self) -> Vec<RejectedRow> {
+        std::mem::take(&mut *self.0.lock().expect("rejected rows poisoned"))
+    }
+}
+
+/// A transaction the ledger refused
+#[derive(Debug, Clone)]
+pub struct RefusedTransaction {
+    pub source: SourceId,
+    pub line: u64,
+    pub transaction: Transaction,
+    /// Stable code of the rejection, as printed by `--verbose-rejects`
+    pub code: &'static str,
+    pub reason: String,
+}
+
+/// Writes refused transactions as csv with `source, line, type, client, tx, amount, code,
+/// reason` columns, naming each source from `names`.
+pub fn write_refused(path: &Path, names: &[&str], refused: &[RefusedTransaction]) -> Result<()> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record([
+        "source", "line", "type", "client", "tx", "amount", "code", "reason",
+    ])?;
+    for refused in refused {
+        let transaction = &refused.transaction;
+        wtr.write_record([
+            names[refused.source],
+            &refused.line.to_string(),
+            &transaction.tx_type.to_string(),
+            &transaction.client.to_string(),
+            &transaction.tx.to_string(),
+            &transaction
+                .amount
+                .map_or_else(String::new, |amount| amount.to_string()),
+            refused.code,
+            &refused.reason,
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Writes rejected rows as csv with `source, line, row, error` columns.
+pub fn write_rejected(path: &Path, rows: &[(String, RejectedRow)]) -> Result<()> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record(["source", "line", "row", "error"])?;
+    for (source, row) in rows {
+        wtr.write_record([source, &row.line.to_string(), &row.raw, &row.error])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::tx;
+
+    #[test]
+    fn test_write_refused() {
+        let path = std::env::temp_dir().join(format!("mpe-{}-errors.csv", std::process::id()));
+        let refused = [
+            RefusedTransaction {
+                source: 1,
+                line: 4,
+                transaction: tx::withdrawal(2, 3, "5.0000"),
+                code: "insufficient_available",
+                reason: String::from("not enough funds"),
+            },
+            RefusedTransaction {
+                source: 0,
+                line: 7,
+                transaction: tx::dispute(1, 9),
+                code: "unknown_transaction",
+                reason: String::from("no transaction 9"),
+            },
+        ];
+        write_refused(&path, &["a.csv", "b.csv"], &refused).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "source,line,type,client,tx,amount,code,reason\n\
+             b.csv,4,withdrawal,2,3,5.0000,insufficient_available,not enough funds\n\
+             a.csv,7,dispute,1,9,,unknown_transaction,no transaction 9\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
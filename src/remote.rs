@@ -0,0 +1,149 @@
+//! Authentication for url inputs, behind the `http` feature.
+//!
+//! `--http-headers` names a json file mapping hosts to the headers sent with every
+//! request to them, e.g. `{"exports.example.com": {"Authorization": "Bearer ..."}}`, so
+//! tokens stay out of the command line and the process list. A host may be given with
+//! its port to only match that port.
+//!
+//! With the `azure` feature an input can be an Azure blob, `az://account/container/blob`,
+//! read over https with a SAS token from `AZURE_STORAGE_SAS_TOKEN` or an OAuth bearer
+//! token from `AZURE_STORAGE_TOKEN`.
+use anyhow::{Context, Result};
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+
+/// Headers to send, by host
+#[derive(Debug, Default)]
+pub struct HttpHeaders(HashMap<String, HashMap<String, String>>);
+
+impl HttpHeaders {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("opening http headers file {}", path.display()))?;
+        let hosts = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("reading http headers file {}", path.display()))?;
+        Ok(Self(hosts))
+    }
+
+    /// The headers for a request to `url`, those given for its host and port first.
+    pub fn for_url(&self, url: &str) -> Vec<(String, String)> {
+        let authority = authority(url);
+        let host = authority
+            .rsplit_once(':')
+            .map_or(authority, |(host, _)| host);
+        [authority, host]
+            .into_iter()
+            .filter_map(|key| self.0.get(key))
+            .next()
+            .map(|headers| {
+                headers
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// The `host:port` part of an url, without any user info.
+fn authority(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let authority = &rest[..end];
+    authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host)
+}
+
+/// Version of the blob service API asked for, needed with bearer tokens
+#[cfg(feature = "azure")]
+const AZURE_API_VERSION: &str = "2021-08-06";
+
+/// Turns `az://account/container/blob` into the blob's https url and the headers to
+/// fetch it with.
+#[cfg(feature = "azure")]
+pub fn resolve_azure_url(url: &str) -> Result<(String, Vec<(String, String)>)> {
+    resolve_azure_with(url, |name| std::env::var(name).ok())
+}
+
+#[cfg(feature = "azure")]
+fn resolve_azure_with(
+    url: &str,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<(String, Vec<(String, String)>)> {
+    let path = url
+        .strip_prefix("az://")
+        .ok_or_else(|| anyhow::anyhow!("not an azure url: {url}"))?;
+    let (account, blob) = path
+        .split_once('/')
+        .filter(|(account, blob)| !account.is_empty() && blob.contains('/'))
+        .ok_or_else(|| {
+            anyhow::anyhow!("azure url needs an account, a container and a blob: {url}")
+        })?;
+    let https = format!("https://{account}.blob.core.windows.net/{blob}");
+
+    let mut headers = vec![(
+        String::from("x-ms-version"),
+        String::from(AZURE_API_VERSION),
+    )];
+    if let Some(sas) = var("AZURE_STORAGE_SAS_TOKEN") {
+        return Ok((format!("{https}?{}", sas.trim_start_matches('?')), headers));
+    }
+    if let Some(token) = var("AZURE_STORAGE_TOKEN") {
+        headers.push((String::from("Authorization"), format!("Bearer {token}")));
+        return Ok((https, headers));
+    }
+    anyhow::bail!("reading {url} needs AZURE_STORAGE_SAS_TOKEN or AZURE_STORAGE_TOKEN")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers_are_picked_by_host() {
+        let headers = HttpHeaders(HashMap::from([
+            (
+                String::from("exports.example.com"),
+                HashMap::from([(String::from("Authorization"), String::from("Bearer a"))]),
+            ),
+            (
+                String::from("exports.example.com:8443"),
+                HashMap::from([(String::from("Authorization"), String::from("Bearer b"))]),
+            ),
+        ]));
+        let auth = |url| headers.for_url(url);
+        assert_eq!(
+            auth("https://exports.example.com/day.csv"),
+            vec![(String::from("Authorization"), String::from("Bearer a"))]
+        );
+        assert_eq!(
+            auth("https://user@exports.example.com:8443/day.csv?x=1"),
+            vec![(String::from("Authorization"), String::from("Bearer b"))]
+        );
+        assert_eq!(
+            auth("https://exports.example.com:9000/day.csv"),
+            vec![(String::from("Authorization"), String::from("Bearer a"))]
+        );
+        assert!(auth("https://other.example.com/day.csv").is_empty());
+    }
+
+    #[cfg(feature = "azure")]
+    #[test]
+    fn test_resolve_azure_url() {
+        let sas =
+            |name: &str| (name == "AZURE_STORAGE_SAS_TOKEN").then(|| String::from("?sv=1&sig=x"));
+        let (url, _) = resolve_azure_with("az://ledgers/exports/2024/day.csv", sas).unwrap();
+        assert_eq!(
+            url,
+            "https://ledgers.blob.core.windows.net/exports/2024/day.csv?sv=1&sig=x"
+        );
+
+        let token = |name: &str| (name == "AZURE_STORAGE_TOKEN").then(|| String::from("t"));
+        let (url, headers) = resolve_azure_with("az://ledgers/exports/day.csv", token).unwrap();
+        assert_eq!(url, "https://ledgers.blob.core.windows.net/exports/day.csv");
+        assert!(headers.contains(&(String::from("Authorization"), String::from("Bearer t"))));
+
+        assert!(resolve_azure_with("az://ledgers/exports/day.csv", |_| None).is_err());
+        assert!(resolve_azure_with("az://ledgers/day.csv", sas).is_err());
+    }
+}
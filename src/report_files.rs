@@ -0,0 +1,220 @@
+//! Writing the report into files.
+//!
+//! `--report-dir` writes the report into a directory instead of stdout, optionally
+//! compressed with gzip or zstd, and split into chunks of at most `--report-chunk-bytes`
+//! bytes of csv each, for consumers that cap the size of a single file. Every chunk
+//! starts with the header row, rows are never split across chunks, and a
+//! `manifest.json` lists the chunks in order with their row counts and sizes. The limit
+//! applies to the csv before compression, so compressed chunks come out well below it.
+use crate::ledger::Ledger;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use csv::ReaderBuilder;
+use flate2::{write::GzEncoder, Compression};
+use serde::Serialize;
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+const CHUNK_PREFIX: &str = "report-";
+const MANIFEST: &str = "manifest.json";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportCompression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl ReportCompression {
+    fn extension(&self) -> &'static str {
+        match self {
+            ReportCompression::None => "csv",
+            ReportCompression::Gzip => "csv.gz",
+            ReportCompression::Zstd => "csv.zst",
+        }
+    }
+
+    fn compress(&self, csv: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            ReportCompression::None => csv.to_vec(),
+            ReportCompression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(csv)?;
+                encoder.finish()?
+            }
+            ReportCompression::Zstd => zstd::encode_all(csv, 0)?,
+        })
+    }
+}
+
+/// Where and how the report is written when it goes into files
+#[derive(Debug, Clone)]
+pub struct ReportFiles {
+    pub dir: PathBuf,
+    pub compression: ReportCompression,
+    /// Most bytes of csv in one chunk, header row included. A single row larger than
+    /// that gets a chunk of its own.
+    pub chunk_bytes: Option<u64>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub file: String,
+    pub rows: usize,
+    /// Size of the file as written, after compression
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct Manifest {
+    pub compression: ReportCompression,
+    pub rows: usize,
+    pub files: Vec<ManifestEntry>,
+}
+
+impl ReportFiles {
+    /// Writes the report of `ledger` into chunk files and a manifest, replacing the chunks
+    /// of an earlier report in the directory.
+    pub fn write(&self, ledger: &Ledger) -> Result<Manifest> {
+        let mut report = Vec::new();
+        ledger.write_report(&mut report)?;
+
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("creating {}", self.dir.display()))?;
+        remove_chunks(&self.dir)?;
+
+        let mut files = Vec::new();
+        for (index, (csv, rows)) in self.chunks(&report)?.into_iter().enumerate() {
+            let file = format!("{CHUNK_PREFIX}{index:03}.{}", self.compression.extension());
+            let contents = self.compression.compress(&csv)?;
+            let path = self.dir.join(&file);
+            fs::write(&path, &contents).with_context(|| format!("writing {}", path.display()))?;
+            files.push(ManifestEntry {
+                file,
+                rows,
+                bytes: contents.len() as u64,
+            });
+        }
+
+        let manifest = Manifest {
+            compression: self.compression,
+            rows: files.iter().map(|entry| entry.rows).sum(),
+            files,
+        };
+        let path = self.dir.join(MANIFEST);
+        fs::write(&path, serde_json::to_vec_pretty(&manifest)?)
+            .with_context(|| format!("writing {}", path.display()))?;
+        Ok(manifest)
+    }
+
+    /// Splits a csv report into chunks that each start with its header row, with the
+    /// number of rows in each. There is always at least one chunk.
+    fn chunks(&self, report: &[u8]) -> Result<Vec<(Vec<u8>, usize)>> {
+        // record boundaries come from the csv reader, so quoted fields are never cut
+        let mut starts = Vec::new();
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(report);
+        for record in rdr.byte_records() {
+            starts.push(record?.position().map_or(0, |position| position.byte()) as usize);
+        }
+        starts.push(report.len());
+
+        let header = &report[..starts.get(1).copied().unwrap_or(report.len())];
+        let limit = self.chunk_bytes.unwrap_or(u64::MAX) as usize;
+        let mut chunks = vec![(header.to_vec(), 0)];
+        for row in starts.windows(2).skip(1) {
+            let row = &report[row[0]..row[1]];
+            let (chunk, rows) = chunks.last_mut().expect("there is always a chunk");
+            if *rows > 0 && chunk.len() + row.len() > limit {
+                chunks.push((header.to_vec(), 0));
+            }
+            let (chunk, rows) = chunks.last_mut().expect("there is always a chunk");
+            chunk.extend_from_slice(row);
+            *rows += 1;
+        }
+        Ok(chunks)
+    }
+}
+
+/// Removes the chunks and manifest an earlier report left in `dir`.
+fn remove_chunks(dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_chunk = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| {
+                name == MANIFEST || (name.starts_with(CHUNK_PREFIX) && name.contains(".csv"))
+            });
+        if is_chunk {
+            fs::remove_file(&path).with_context(|| format!("removing {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::ledger_with_accounts;
+    use std::io::Read;
+
+    #[test]
+    fn test_report_is_split_into_compressed_chunks() {
+        let ledger = ledger_with_accounts(&[(1, "1.0"), (2, "2.0"), (3, "3.0")]);
+        let mut report = Vec::new();
+        ledger.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        let mut lines = report.lines();
+        let header = lines.next().unwrap();
+        let row = lines.next().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("mpe-{}-report-chunks", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("report-007.csv"), "stale").unwrap();
+
+        // room for the header and two rows
+        let files = ReportFiles {
+            dir: dir.clone(),
+            compression: ReportCompression::Gzip,
+            chunk_bytes: Some((header.len() + 2 * row.len() + 3) as u64),
+        };
+        let manifest = files.write(&ledger).unwrap();
+        assert_eq!(manifest.rows, 3);
+        let chunks: Vec<(&str, usize)> = manifest
+            .files
+            .iter()
+            .map(|entry| (entry.file.as_str(), entry.rows))
+            .collect();
+        assert_eq!(
+            chunks,
+            vec![("report-000.csv.gz", 2), ("report-001.csv.gz", 1)]
+        );
+        assert!(!dir.join("report-007.csv").exists());
+        assert!(dir.join(MANIFEST).exists());
+
+        let mut joined = String::new();
+        for entry in &manifest.files {
+            let mut csv = String::new();
+            flate2::read::GzDecoder::new(fs::File::open(dir.join(&entry.file)).unwrap())
+                .read_to_string(&mut csv)
+                .unwrap();
+            let mut lines = csv.lines();
+            assert_eq!(lines.next(), Some(header));
+            for line in lines {
+                joined.push_str(line);
+                joined.push('\n');
+            }
+        }
+        assert_eq!(format!("{header}\n{joined}"), report);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
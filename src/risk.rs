@@ -0,0 +1,119 @@
+//! Exposure and liquidity figures for treasury.
+use crate::{
+    amount::{self, Amount},
+    ledger::{Client, Ledger},
+};
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ClientExposure {
+    pub client: Client,
+    /// The client's total funds
+    pub total: Amount,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct RiskReport {
+    pub total_available: Amount,
+    pub total_held: Amount,
+    /// Client holding the largest total funds, `None` without any accounts
+    pub largest_exposure: Option<ClientExposure>,
+    /// Sum of the amounts under open dispute, which would leave if every one of them
+    /// ended in a chargeback
+    pub chargeback_exposure: Amount,
+    pub open_disputes: usize,
+}
+
+impl fmt::Display for RiskReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "total available {}, total held {}",
+            self.total_available, self.total_held
+        )?;
+        if let Some(largest) = &self.largest_exposure {
+            writeln!(
+                f,
+                "largest exposure: client {} with {}",
+                largest.client, largest.total
+            )?;
+        }
+        writeln!(
+            f,
+            "chargeback exposure {} over {} open disputes",
+            self.chargeback_exposure, self.open_disputes
+        )
+    }
+}
+
+impl Ledger {
+    pub fn risk_report(&self) -> RiskReport {
+        let mut report = RiskReport {
+            total_available: amount::zero(),
+            total_held: amount::zero(),
+            largest_exposure: None,
+            chargeback_exposure: amount::zero(),
+            open_disputes: 0,
+        };
+
+        for account in self.accounts.values() {
+            report.total_available += account.available_funds;
+            report.total_held += account.held_funds;
+            // ties go to the lower client id so the report does not depend on map order
+            let larger = report.largest_exposure.as_ref().is_none_or(|largest| {
+                (account.total_funds, std::cmp::Reverse(account.client_id))
+                    > (largest.total, std::cmp::Reverse(largest.client))
+            });
+            if larger {
+                report.largest_exposure = Some(ClientExposure {
+                    client: account.client_id,
+                    total: account.total_funds,
+                });
+            }
+        }
+
+        for transaction in self.history.values().filter(|tx| tx.disputed) {
+            report.open_disputes += 1;
+            if let Some(amount) = transaction.amount {
+                report.chargeback_exposure += amount;
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{amount, ledger_with_accounts, tx};
+
+    #[test]
+    fn test_risk_report() {
+        let mut ledger = ledger_with_accounts(&[(1, "10.0"), (2, "25.0"), (3, "25.0")]);
+        ledger.process_transaction(tx::dispute(1, 1)).unwrap();
+        ledger.process_transaction(tx::dispute(2, 2)).unwrap();
+        ledger.process_transaction(tx::resolve(2, 2)).unwrap();
+
+        let report = ledger.risk_report();
+        assert_eq!(report.total_available, amount("50.0"));
+        assert_eq!(report.total_held, amount("10.0"));
+        assert_eq!(
+            report.largest_exposure,
+            Some(ClientExposure {
+                client: 2,
+                total: amount("25.0"),
+            })
+        );
+        assert_eq!(report.chargeback_exposure, amount("10.0"));
+        assert_eq!(report.open_disputes, 1);
+    }
+
+    #[test]
+    fn test_risk_report_without_accounts() {
+        let report = Ledger::new().risk_report();
+        assert_eq!(report.largest_exposure, None);
+        assert_eq!(report.open_disputes, 0);
+    }
+}
@@ -0,0 +1,193 @@
+//! Balances rolled up over groups of clients, such as desks, regions or products.
+use crate::{
+    amount::{self, Amount},
+    ledger::{Client, Ledger},
+};
+use anyhow::{bail, Context, Result};
+use csv::{ReaderBuilder, Trim};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::File,
+    io::Read,
+    path::Path,
+};
+
+/// Group of the clients a grouping file does not mention
+pub const UNGROUPED: &str = "ungrouped";
+
+/// The group each client belongs to along one or more dimensions
+#[derive(Debug, Default, Clone)]
+pub struct Groupings {
+    /// Dimension names such as `desk` or `region`, in file order
+    pub dimensions: Vec<String>,
+    /// Each client's group in every dimension, in the same order
+    pub clients: HashMap<Client, Vec<String>>,
+}
+
+impl Groupings {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("opening grouping file {}", path.display()))?;
+        Self::from_reader(file).with_context(|| format!("reading grouping file {}", path.display()))
+    }
+
+    /// Reads csv with a `client` column followed by one column per dimension, e.g.
+    /// `client,desk,region`.
+    pub fn from_reader(reader: impl Read) -> Result<Self> {
+        let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+        let headers = rdr.headers()?.clone();
+        if headers.get(0) != Some("client") || headers.len() < 2 {
+            bail!("expected a client column followed by at least one group column");
+        }
+
+        let mut groupings = Groupings {
+            dimensions: headers.iter().skip(1).map(String::from).collect(),
+            clients: HashMap::new(),
+        };
+        for result in rdr.records() {
+            let record = result?;
+            let client = record[0]
+                .parse()
+                .with_context(|| format!("invalid client id: {}", &record[0]))?;
+            let groups = record.iter().skip(1).map(String::from).collect();
+            groupings.clients.insert(client, groups);
+        }
+        Ok(groupings)
+    }
+
+    fn group_of(&self, client: Client, dimension: usize) -> &str {
+        self.clients
+            .get(&client)
+            .and_then(|groups| groups.get(dimension))
+            .map_or(UNGROUPED, String::as_str)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct GroupTotals {
+    pub dimension: String,
+    pub group: String,
+    pub clients: usize,
+    pub locked: usize,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    /// Disputed transactions not yet resolved or charged back, the source of `held`
+    pub open_disputes: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RollupReport {
+    /// Ordered by dimension as in the grouping file, then by group name
+    pub groups: Vec<GroupTotals>,
+}
+
+impl fmt::Display for RollupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for totals in &self.groups {
+            writeln!(
+                f,
+                "{}={}: {} clients ({} locked), available {}, held {} over {} open disputes, total {}",
+                totals.dimension,
+                totals.group,
+                totals.clients,
+                totals.locked,
+                totals.available,
+                totals.held,
+                totals.open_disputes,
+                totals.total
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Ledger {
+    /// Adds up balances, locked accounts and open disputes per group in every dimension of
+    /// `groupings`.
+    pub fn rollup(&self, groupings: &Groupings) -> RollupReport {
+        let mut open_disputes: HashMap<Client, usize> = HashMap::new();
+        for transaction in self.history.values().filter(|tx| tx.disputed) {
+            *open_disputes.entry(transaction.client).or_default() += 1;
+        }
+
+        let mut report = RollupReport::default();
+        for (index, dimension) in groupings.dimensions.iter().enumerate() {
+            let mut groups: BTreeMap<&str, GroupTotals> = BTreeMap::new();
+            for account in self.accounts.values() {
+                let group = groupings.group_of(account.client_id, index);
+                let totals = groups.entry(group).or_insert_with(|| GroupTotals {
+                    dimension: dimension.clone(),
+                    group: group.to_string(),
+                    clients: 0,
+                    locked: 0,
+                    available: amount::zero(),
+                    held: amount::zero(),
+                    total: amount::zero(),
+                    open_disputes: 0,
+                });
+                totals.clients += 1;
+                totals.locked += account.is_locked() as usize;
+                totals.available += account.available_funds;
+                totals.held += account.held_funds;
+                totals.total += account.total_funds;
+                totals.open_disputes += open_disputes.get(&account.client_id).unwrap_or(&0);
+            }
+            report.groups.extend(groups.into_values());
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{amount, ledger_with_accounts, tx};
+
+    #[test]
+    fn test_rollup_per_dimension() {
+        let groupings =
+            Groupings::from_reader("client,desk,region\n1,fx,emea\n2,fx,apac\n".as_bytes())
+                .unwrap();
+        let mut ledger = ledger_with_accounts(&[(1, "10.0"), (2, "5.0"), (3, "1.0")]);
+        ledger.process_transaction(tx::dispute(2, 2)).unwrap();
+
+        let report = ledger.rollup(&groupings);
+        let groups: Vec<(&str, &str, usize)> = report
+            .groups
+            .iter()
+            .map(|totals| {
+                (
+                    totals.dimension.as_str(),
+                    totals.group.as_str(),
+                    totals.clients,
+                )
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                ("desk", "fx", 2),
+                ("desk", UNGROUPED, 1),
+                ("region", "apac", 1),
+                ("region", "emea", 1),
+                ("region", UNGROUPED, 1),
+            ]
+        );
+
+        let fx = &report.groups[0];
+        assert_eq!(fx.available, amount("10.0"));
+        assert_eq!(fx.held, amount("5.0"));
+        assert_eq!(fx.total, amount("15.0"));
+        assert_eq!(fx.open_disputes, 1);
+    }
+
+    #[test]
+    fn test_grouping_file_needs_client_column() {
+        assert!(Groupings::from_reader("desk,client\nfx,1\n".as_bytes()).is_err());
+        assert!(Groupings::from_reader("client\n1\n".as_bytes()).is_err());
+        assert!(Groupings::from_reader("client,desk\nabc,fx\n".as_bytes()).is_err());
+    }
+}
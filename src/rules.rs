@@ -0,0 +1,63 @@
+//! House rules layered over the ledger's own checks.
+//!
+//! A [`Rule`] looks at each deposit and withdrawal before it is applied and can hold it
+//! for review. Held transactions are parked in [`Ledger::quarantine`] just like those
+//! above the amount cap, and an operator settles them with
+//! [`Ledger::approve_quarantined`] or [`Ledger::reject_quarantined`].
+use crate::{ledger::Ledger, transaction::TransactionState};
+use std::fmt;
+
+pub trait Rule: fmt::Debug + Send + Sync {
+    /// Whether `tx` is held for review, judged against the ledger as it is before `tx`.
+    /// Rules should only look at the account of the transaction's own client, which is
+    /// all a [`Ledger::fork`] carries over.
+    fn holds(&self, ledger: &Ledger, tx: &TransactionState) -> bool;
+}
+
+impl Ledger {
+    /// Whether any installed rule holds `tx`.
+    pub(crate) fn held_by_rules(&self, tx: &TransactionState) -> bool {
+        self.rules.iter().any(|rule| rule.holds(self, tx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ledger::Client,
+        test_support::{amount, ledger_with_accounts, tx},
+    };
+    use std::sync::Arc;
+
+    /// Holds every transaction of one client
+    #[derive(Debug)]
+    struct HoldClient(Client);
+
+    impl Rule for HoldClient {
+        fn holds(&self, _ledger: &Ledger, tx: &TransactionState) -> bool {
+            tx.client == self.0
+        }
+    }
+
+    #[test]
+    fn test_rule_quarantines_until_approved() {
+        let mut ledger = ledger_with_accounts(&[(1, "10.0"), (2, "10.0")]);
+        ledger.rules.push(Arc::new(HoldClient(2)));
+
+        ledger
+            .process_transaction(tx::deposit(2, 3, "5.0"))
+            .unwrap();
+        // later ids are not held up behind the quarantined one
+        ledger
+            .process_transaction(tx::deposit(1, 4, "5.0"))
+            .unwrap();
+        assert_eq!(ledger.quarantine.len(), 1);
+        assert_eq!(ledger.accounts[&1].total_funds, amount("15.0"));
+        assert_eq!(ledger.accounts[&2].total_funds, amount("10.0"));
+
+        ledger.approve_quarantined(3).unwrap();
+        assert!(ledger.quarantine.is_empty());
+        assert_eq!(ledger.accounts[&2].total_funds, amount("15.0"));
+    }
+}
@@ -0,0 +1,387 @@
+//! Object storage, behind the `aws` feature, and Google Cloud Storage behind `gcs`.
+//!
+//! Csv inputs are streamed out of objects in ranged reads of [`PART_SIZE`] bytes, so a
+//! very large object is never requested in one go, and a dropped connection only costs
+//! the part in flight: each part is retried from its first byte up to [`RETRIES`] times
+//! before the read fails. Reports are streamed into objects with a multipart upload of
+//! [`UPLOAD_PART_SIZE`] byte parts, and checkpoints are put whole, so a checkpoint object
+//! is always either the previous one or the new one.
+//!
+//! `s3://` objects are reached with credentials and region from the usual AWS environment
+//! variables and profiles. `gs://` objects go through Cloud Storage's S3 compatible API
+//! with an HMAC key given as `GCS_ACCESS_KEY_ID` and `GCS_SECRET_ACCESS_KEY`.
+use crate::{
+    reader::{decompress, on_blocking_pool, read_csv, read_csv_fast},
+    source::{CsvLayout, Row},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use aws_sdk_s3::{
+    config::{Credentials, Region, RequestChecksumCalculation},
+    primitives::ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart},
+    Client,
+};
+use std::{
+    io::{self, BufReader, Cursor, Read, Write},
+    thread,
+    time::Duration,
+};
+use tokio::{runtime::Handle, sync::mpsc::Sender};
+
+const PART_SIZE: u64 = 64 * 1024 * 1024;
+const RETRIES: u32 = 3;
+/// Above the 5 MiB both stores require of every part but the last
+const UPLOAD_PART_SIZE: usize = 8 * 1024 * 1024;
+const GCS_ENDPOINT: &str = "https://storage.googleapis.com";
+
+/// The object store an url points into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Store {
+    S3,
+    Gcs,
+}
+
+/// Splits `s3://bucket/key` or `gs://bucket/key` into its store, bucket and key.
+pub fn parse_object_url(url: &str) -> Result<(Store, String, String)> {
+    let (store, path) = if let Some(path) = url.strip_prefix("s3://") {
+        (Store::S3, path)
+    } else if let Some(path) = url.strip_prefix("gs://") {
+        (Store::Gcs, path)
+    } else {
+        bail!("not an s3 or gs url: {url}");
+    };
+    match path.split_once('/') {
+        Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => {
+            Ok((store, bucket.to_string(), key.to_string()))
+        }
+        _ => bail!("object url needs a bucket and a key: {url}"),
+    }
+}
+
+async fn client(store: Store) -> Result<Client> {
+    Ok(match store {
+        Store::S3 => Client::new(&aws_config::load_from_env().await),
+        Store::Gcs => {
+            let variable = |name| std::env::var(name).with_context(|| format!("{name} is not set"));
+            let credentials = Credentials::new(
+                variable("GCS_ACCESS_KEY_ID")?,
+                variable("GCS_SECRET_ACCESS_KEY")?,
+                None,
+                None,
+                "gcs-hmac",
+            );
+            let config = aws_sdk_s3::Config::builder()
+                .behavior_version_latest()
+                .endpoint_url(GCS_ENDPOINT)
+                .region(Region::new("auto"))
+                .credentials_provider(credentials)
+                // the compatible API does not take the checksums S3 now asks for by default
+                .request_checksum_calculation(RequestChecksumCalculation::WhenRequired)
+                .build();
+            Client::from_conf(config)
+        }
+    })
+}
+
+/// Fetches a whole object, such as a checkpoint.
+pub async fn get_object(url: &str) -> Result<Vec<u8>> {
+    let (store, bucket, key) = parse_object_url(url)?;
+    let output = client(store)
+        .await?
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .with_context(|| format!("fetching {url}"))?;
+    Ok(output.body.collect().await?.into_bytes().to_vec())
+}
+
+/// Replaces an object with `bytes` in one request.
+pub async fn put_object(url: &str, bytes: Vec<u8>) -> Result<()> {
+    let (store, bucket, key) = parse_object_url(url)?;
+    client(store)
+        .await?
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .body(ByteStream::from(bytes))
+        .send()
+        .await
+        .with_context(|| format!("writing {url}"))?;
+    Ok(())
+}
+
+pub async fn delete_object(url: &str) -> Result<()> {
+    let (store, bucket, key) = parse_object_url(url)?;
+    client(store)
+        .await?
+        .delete_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .with_context(|| format!("removing {url}"))?;
+    Ok(())
+}
+
+pub async fn s3_reader(
+    url: String,
+    layout: CsvLayout,
+    fast: bool,
+    channel: Sender<Row>,
+) -> Result<()> {
+    let (store, bucket, key) = parse_object_url(&url)?;
+    let client = client(store).await?;
+    let head = client
+        .head_object()
+        .bucket(&bucket)
+        .key(&key)
+        .send()
+        .await
+        .with_context(|| format!("looking up {url}"))?;
+
+    let object = RangedObject {
+        client,
+        bucket,
+        key,
+        size: head.content_length().unwrap_or(0).max(0) as u64,
+        offset: 0,
+        part: Cursor::new(Vec::new()),
+        runtime: Handle::current(),
+    };
+    on_blocking_pool(move || {
+        let body = decompress(BufReader::new(object))?;
+        if fast {
+            read_csv_fast(body, layout, None, channel)
+        } else {
+            read_csv(body, layout, None, channel)
+        }
+    })
+    .await
+}
+
+/// Blocking reader over an S3 object that fetches one part at a time.
+struct RangedObject {
+    client: Client,
+    bucket: String,
+    key: String,
+    size: u64,
+    /// First byte of the next part to fetch
+    offset: u64,
+    part: Cursor<Vec<u8>>,
+    runtime: Handle,
+}
+
+impl RangedObject {
+    async fn fetch(&self, first: u64, last: u64) -> Result<Vec<u8>> {
+        let output = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .range(format!("bytes={first}-{last}"))
+            .send()
+            .await?;
+        Ok(output.body.collect().await?.into_bytes().to_vec())
+    }
+
+    fn fetch_with_retries(&self, first: u64, last: u64) -> Result<Vec<u8>> {
+        let mut attempt = 0;
+        loop {
+            match self.runtime.block_on(self.fetch(first, last)) {
+                Ok(bytes) => return Ok(bytes),
+                Err(_) if attempt < RETRIES => {
+                    attempt += 1;
+                    thread::sleep(Duration::from_millis(200 << attempt));
+                }
+                Err(err) => {
+                    return Err(err.context(format!(
+                        "reading bytes {first}-{last} of {}/{}",
+                        self.bucket, self.key
+                    )))
+                }
+            }
+        }
+    }
+}
+
+impl Read for RangedObject {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.part.position() as usize == self.part.get_ref().len() {
+            if self.offset >= self.size {
+                return Ok(0);
+            }
+
+            let last = (self.offset + PART_SIZE).min(self.size) - 1;
+            let bytes = self
+                .fetch_with_retries(self.offset, last)
+                .map_err(io::Error::other)?;
+            if bytes.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "s3 object ended before its reported size",
+                ));
+            }
+            self.offset += bytes.len() as u64;
+            self.part = Cursor::new(bytes);
+        }
+
+        self.part.read(buf)
+    }
+}
+
+/// Blocking writer streaming into an object with a multipart upload. The object only
+/// appears once [`MultipartUpload::finish`] completes the upload.
+pub struct MultipartUpload {
+    client: Client,
+    url: String,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    part: Vec<u8>,
+    parts: Vec<CompletedPart>,
+    runtime: Handle,
+}
+
+impl MultipartUpload {
+    pub async fn start(url: &str) -> Result<Self> {
+        let (store, bucket, key) = parse_object_url(url)?;
+        let client = client(store).await?;
+        let output = client
+            .create_multipart_upload()
+            .bucket(&bucket)
+            .key(&key)
+            .send()
+            .await
+            .with_context(|| format!("starting an upload to {url}"))?;
+        let upload_id = output
+            .upload_id()
+            .ok_or_else(|| anyhow!("no upload id for {url}"))?
+            .to_string();
+        Ok(Self {
+            client,
+            url: url.to_string(),
+            bucket,
+            key,
+            upload_id,
+            part: Vec::with_capacity(UPLOAD_PART_SIZE),
+            parts: Vec::new(),
+            runtime: Handle::current(),
+        })
+    }
+
+    fn upload_part(&mut self) -> Result<()> {
+        let number = self.parts.len() as i32 + 1;
+        let body = std::mem::replace(&mut self.part, Vec::with_capacity(UPLOAD_PART_SIZE));
+        let output = self
+            .runtime
+            .block_on(
+                self.client
+                    .upload_part()
+                    .bucket(&self.bucket)
+                    .key(&self.key)
+                    .upload_id(&self.upload_id)
+                    .part_number(number)
+                    .body(ByteStream::from(body))
+                    .send(),
+            )
+            .with_context(|| format!("uploading part {number} of {}", self.url))?;
+        self.parts.push(
+            CompletedPart::builder()
+                .part_number(number)
+                .set_e_tag(output.e_tag().map(String::from))
+                .build(),
+        );
+        Ok(())
+    }
+
+    /// Uploads what is left and completes the object, or gives the upload up on failure.
+    pub fn finish(mut self) -> Result<()> {
+        let completed = self.complete();
+        if completed.is_err() {
+            self.abort();
+        }
+        completed
+    }
+
+    fn complete(&mut self) -> Result<()> {
+        // an empty object still needs its one part
+        if !self.part.is_empty() || self.parts.is_empty() {
+            self.upload_part()?;
+        }
+        let parts = CompletedMultipartUpload::builder()
+            .set_parts(Some(std::mem::take(&mut self.parts)))
+            .build();
+        self.runtime
+            .block_on(
+                self.client
+                    .complete_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(&self.key)
+                    .upload_id(&self.upload_id)
+                    .multipart_upload(parts)
+                    .send(),
+            )
+            .with_context(|| format!("completing the upload to {}", self.url))?;
+        Ok(())
+    }
+
+    /// Gives the upload up, so the store drops the parts sent so far.
+    pub fn abort(&self) {
+        let aborted = self.runtime.block_on(
+            self.client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .upload_id(&self.upload_id)
+                .send(),
+        );
+        if let Err(err) = aborted {
+            eprintln!("failed to abort the upload to {}: {err}", self.url);
+        }
+    }
+}
+
+impl Write for MultipartUpload {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.part.extend_from_slice(buf);
+        if self.part.len() >= UPLOAD_PART_SIZE {
+            self.upload_part().map_err(io::Error::other)?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Parts are only sent once full, as the stores refuse small ones.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_object_url() {
+        assert_eq!(
+            parse_object_url("s3://exports/2024/06/01.csv").unwrap(),
+            (
+                Store::S3,
+                String::from("exports"),
+                String::from("2024/06/01.csv")
+            )
+        );
+        assert_eq!(
+            parse_object_url("gs://reports/daily.csv.gz").unwrap(),
+            (
+                Store::Gcs,
+                String::from("reports"),
+                String::from("daily.csv.gz")
+            )
+        );
+        assert!(parse_object_url("s3://exports").is_err());
+        assert!(parse_object_url("s3:///key.csv").is_err());
+        assert!(parse_object_url("https://exports/key.csv").is_err());
+    }
+}
@@ -0,0 +1,262 @@
+//! Embedding the ledger as a `tower::Service`.
+//!
+//! The ledger is owned by a worker task and fed through a bounded channel. The service
+//! only reports ready once it holds a slot in that channel, so a ledger that falls behind
+//! pushes back on callers through the normal tower readiness contract and composes with
+//! timeout, load-shed and similar middleware.
+//!
+//! A service serving http can shed load itself instead: with
+//! [`LedgerService::shed_above`] it stays ready while the queue is deep and fails calls
+//! with [`Overloaded`], which carries the 503 status, `Retry-After` and queue depth to
+//! answer with, so clients and load balancers back off rather than requests piling up.
+use crate::{
+    ledger::Ledger,
+    transaction::{Transaction, TransactionState},
+};
+use anyhow::{anyhow, Result};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::{
+    spawn,
+    sync::{mpsc::channel, oneshot},
+    task::JoinHandle,
+};
+use tokio_util::sync::PollSender;
+use tower::Service;
+
+type Request = (TransactionState, oneshot::Sender<Result<()>>);
+
+#[derive(Clone)]
+pub struct LedgerService {
+    sender: PollSender<Request>,
+    capacity: usize,
+    shedding: Option<Shedding>,
+    /// Set by `poll_ready` when the next call is to be refused
+    overloaded: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Shedding {
+    threshold: usize,
+    retry_after: Duration,
+}
+
+/// A call refused because the ledger's queue was too deep
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("ledger queue holds {queue_depth} transactions, retry after {retry_after:?}")]
+pub struct Overloaded {
+    pub queue_depth: usize,
+    pub retry_after: Duration,
+}
+
+impl Overloaded {
+    /// Status to answer an http request with
+    pub const STATUS: u16 = 503;
+
+    /// `Retry-After` in whole seconds, and the queue depth as `X-Queue-Depth`.
+    pub fn headers(&self) -> [(&'static str, String); 2] {
+        [
+            (
+                "Retry-After",
+                retry_after_secs(self.retry_after).to_string(),
+            ),
+            ("X-Queue-Depth", self.queue_depth.to_string()),
+        ]
+    }
+}
+
+/// `Retry-After` only takes whole seconds, so a shorter wait still asks for one.
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs_f64().ceil().max(1.0) as u64
+}
+
+impl LedgerService {
+    /// Moves the ledger onto a worker task accepting up to `capacity` queued transactions.
+    /// The handle resolves to the ledger once every clone of the service has been dropped.
+    pub fn new(ledger: Ledger, capacity: usize) -> (Self, JoinHandle<Ledger>) {
+        let (tx, mut rx) = channel::<Request>(capacity);
+
+        let worker = spawn(async move {
+            let mut ledger = ledger;
+            while let Some((transaction, response)) = rx.recv().await {
+                let _ = response.send(ledger.process_transaction(transaction));
+            }
+            ledger
+        });
+
+        (
+            Self {
+                sender: PollSender::new(tx),
+                capacity,
+                shedding: None,
+                overloaded: None,
+            },
+            worker,
+        )
+    }
+
+    /// Refuses calls with [`Overloaded`], asking to retry after `retry_after`, once
+    /// `threshold` or more transactions are queued, instead of waiting for room.
+    pub fn shed_above(mut self, threshold: usize, retry_after: Duration) -> Self {
+        self.shedding = Some(Shedding {
+            threshold: threshold.clamp(1, self.capacity),
+            retry_after,
+        });
+        self
+    }
+
+    /// Transactions queued for the worker and not yet taken up.
+    pub fn queue_depth(&self) -> usize {
+        self.sender
+            .get_ref()
+            .map_or(0, |sender| sender.max_capacity() - sender.capacity())
+    }
+}
+
+impl Service<Transaction> for LedgerService {
+    type Response = ();
+    type Error = anyhow::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if let Some(shedding) = self.shedding {
+            if self.queue_depth() >= shedding.threshold {
+                // a slot reserved by an earlier poll counts towards the depth, and would
+                // never be given back once the call it was reserved for is shed
+                self.sender.abort_send();
+                let queue_depth = self.queue_depth();
+                if queue_depth >= shedding.threshold {
+                    self.overloaded = Some(queue_depth);
+                    return Poll::Ready(Ok(()));
+                }
+            }
+            self.overloaded = None;
+        }
+        self.sender
+            .poll_reserve(cx)
+            .map_err(|_| anyhow!("ledger worker has stopped"))
+    }
+
+    fn call(&mut self, transaction: Transaction) -> Self::Future {
+        if let (Some(queue_depth), Some(shedding)) = (self.overloaded.take(), self.shedding) {
+            let overloaded = Overloaded {
+                queue_depth,
+                retry_after: shedding.retry_after,
+            };
+            return Box::pin(async move { Err(overloaded.into()) });
+        }
+        let (response_tx, response_rx) = oneshot::channel();
+        let sent = self
+            .sender
+            .send_item((transaction.into(), response_tx))
+            .map_err(|_| anyhow!("ledger service called before it was ready"));
+
+        Box::pin(async move {
+            sent?;
+            response_rx
+                .await
+                .map_err(|_| anyhow!("ledger worker dropped the transaction"))?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{amount, tx};
+    use std::future::poll_fn;
+
+    /// Polls the service for readiness once, without waiting for it.
+    async fn poll_ready_now(service: &mut LedgerService) -> Poll<Result<()>> {
+        poll_fn(|cx| Poll::Ready(service.poll_ready(cx))).await
+    }
+
+    #[tokio::test]
+    async fn test_service_applies_transactions() {
+        let (mut service, worker) = LedgerService::new(Ledger::new(), 8);
+
+        poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+        service.call(tx::deposit(1, 1, "2.0")).await.unwrap();
+
+        drop(service);
+        let ledger = worker.await.unwrap();
+        assert_eq!(ledger.accounts[&1].total_funds, amount("2.0"));
+    }
+
+    #[tokio::test]
+    async fn test_service_reports_rejections() {
+        let (mut service, _worker) = LedgerService::new(Ledger::new(), 8);
+        poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+        assert!(service.call(tx::withdrawal(1, 1, "5.0")).await.is_ok());
+
+        poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+        assert!(service.call(tx::dispute(1, 7)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_service_not_ready_when_queue_full() {
+        let (mut service, _worker) = LedgerService::new(Ledger::new(), 1);
+
+        // the worker has not been polled yet on this single threaded runtime, so the
+        // first transaction stays queued and fills the channel
+        assert!(poll_ready_now(&mut service).await.is_ready());
+        let _pending = service.call(tx::deposit(1, 1, "1.0"));
+        assert!(poll_ready_now(&mut service).await.is_pending());
+    }
+
+    #[tokio::test]
+    async fn test_service_sheds_load_above_threshold() {
+        let (service, _worker) = LedgerService::new(Ledger::new(), 4);
+        let mut service = service.shed_above(2, Duration::from_millis(1500));
+
+        // as above, nothing is taken off the queue while the test runs
+        let mut pending = vec![];
+        for id in 1..=2 {
+            assert!(poll_ready_now(&mut service).await.is_ready());
+            pending.push(service.call(tx::deposit(1, id, "1.0")));
+        }
+        assert_eq!(service.queue_depth(), 2);
+
+        assert!(poll_ready_now(&mut service).await.is_ready());
+        let err = service.call(tx::deposit(1, 3, "1.0")).await.unwrap_err();
+        let overloaded = err.downcast_ref::<Overloaded>().unwrap();
+        assert_eq!(
+            overloaded.headers(),
+            [
+                ("Retry-After", String::from("2")),
+                ("X-Queue-Depth", String::from("2"))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shedding_gives_back_a_reserved_slot() {
+        let (service, _worker) = LedgerService::new(Ledger::new(), 4);
+        let mut service = service.shed_above(2, Duration::from_secs(1));
+        let mut other = service.clone();
+
+        // a slot is reserved, then the queue fills up behind it from another clone
+        assert!(poll_ready_now(&mut service).await.is_ready());
+        let mut pending = vec![];
+        for id in 1..=2 {
+            assert!(poll_ready_now(&mut other).await.is_ready());
+            pending.push(other.call(tx::deposit(1, id, "1.0")));
+        }
+        assert_eq!(service.queue_depth(), 3);
+
+        assert!(poll_ready_now(&mut service).await.is_ready());
+        assert!(service.call(tx::deposit(1, 3, "1.0")).await.is_err());
+        assert_eq!(service.queue_depth(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_call_without_ready_fails() {
+        let (mut service, _worker) = LedgerService::new(Ledger::new(), 1);
+        assert!(service.call(tx::deposit(1, 1, "1.0")).await.is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! What-if runs of hypothetical transactions.
+//!
+//! [`Ledger::fork`] copies only the state a given set of transactions can reach (the
+//! accounts of their clients and of buffered transactions they may release, and the
+//! history entries they reference) into a new ledger, so simulations, shadow rules and
+//! speculative validation of a batch cost as much as the batch touches rather than a deep
+//! clone of every account. A fork is only faithful for the transactions it was made for.
+use crate::{
+    account::Account,
+    explain::Explanation,
+    ledger::{Client, Ledger},
+    transaction::Transaction,
+};
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Debug, Clone)]
+pub struct SimulationResult {
+    /// Every account the simulated transactions could have touched, as it would end up
+    pub accounts: BTreeMap<Client, Account>,
+    /// Transactions the ledger would refuse, in order, with the reason
+    pub rejections: Vec<Explanation>,
+}
+
+impl Ledger {
+    /// A ledger holding copies of just the state `transactions` can reach. Applying
+    /// those transactions to the fork gives the same results as applying them here.
+    pub fn fork(&self, transactions: &[Transaction]) -> Ledger {
+        let mut fork = Ledger::new();
+        fork.breaker = self.breaker.clone();
+        fork.max_amount = self.max_amount;
+        fork.approved = self.approved.clone();
+        fork.rules = self.rules.clone();
+        fork.handlers = self.handlers.clone();
+        fork.columns = self.columns.clone();
+        fork.unprocessed = self.unprocessed.clone();
+        fork.skipped = self.skipped.clone();
+        fork.generated = self.generated.clone();
+        fork.clock = self.clock.clone();
+
+        let clients: HashSet<Client> = transactions
+            .iter()
+            .map(|transaction| transaction.client)
+            .chain(
+                self.unprocessed
+                    .iter()
+                    .map(|transaction| transaction.client),
+            )
+            .collect();
+        for client in clients {
+            if let Some(account) = self.accounts.get(&client) {
+                fork.accounts.insert(client, account.clone());
+            }
+        }
+
+        for transaction in transactions {
+            if let Some(entry) = self.history.get(&transaction.tx) {
+                fork.history.insert(transaction.tx, entry.clone());
+            }
+        }
+        // the latest upstream entry decides whether a new deposit or withdrawal is next in
+        // line, so it has to stay last
+        if let Some(tx) = self.last_upstream() {
+            fork.history.shift_remove(&tx);
+            fork.history.insert(tx, self.history[&tx].clone());
+        }
+
+        fork
+    }
+
+    /// Applies `transactions` to a fork and reports the resulting balances and
+    /// rejections, leaving the ledger itself unchanged.
+    pub fn simulate(
+        &self,
+        transactions: impl IntoIterator<Item = Transaction>,
+    ) -> SimulationResult {
+        let transactions: Vec<Transaction> = transactions.into_iter().collect();
+        let mut fork = self.fork(&transactions);
+
+        let mut rejections = Vec::new();
+        for transaction in transactions {
+            for (refused, err) in fork.process(transaction) {
+                rejections.push(fork.explain_rejection(&Transaction::from(&refused), &err));
+            }
+        }
+
+        SimulationResult {
+            accounts: fork.accounts.into_iter().collect(),
+            rejections,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{amount, ledger_with_accounts, tx};
+
+    #[test]
+    fn test_simulate_chargeback_leaves_ledger_untouched() {
+        let mut ledger = ledger_with_accounts(&[(1, "10.0"), (2, "5.0")]);
+        ledger.process_transaction(tx::dispute(1, 1)).unwrap();
+
+        let result = ledger.simulate([tx::chargeback(1, 1), tx::withdrawal(1, 3, "1.0")]);
+        assert!(result.accounts[&1].is_locked());
+        assert_eq!(result.accounts[&1].total_funds, amount("0"));
+        assert!(!result.accounts.contains_key(&2));
+        assert_eq!(result.rejections.len(), 1);
+        assert_eq!(result.rejections[0].tx, 3);
+
+        assert!(!ledger.accounts[&1].is_locked());
+        assert_eq!(ledger.accounts[&1].held_funds, amount("10.0"));
+        assert!(ledger.history[&1].disputed);
+        assert_eq!(ledger.history.len(), 2);
+    }
+
+    #[test]
+    fn test_simulate_deposit_follows_history() {
+        let ledger = ledger_with_accounts(&[(1, "10.0"), (2, "5.0")]);
+
+        // tx 3 is next in line, tx 5 would wait on tx 4
+        let result = ledger.simulate([tx::deposit(2, 3, "1.0"), tx::deposit(2, 5, "1.0")]);
+        assert_eq!(result.accounts[&2].available_funds, amount("6.0"));
+        assert!(result.rejections.is_empty());
+    }
+
+    #[test]
+    fn test_fork_copies_only_reachable_state() {
+        let mut ledger = ledger_with_accounts(&[(1, "10.0"), (2, "5.0"), (3, "1.0")]);
+        ledger
+            .process_transaction(tx::withdrawal(3, 6, "1.0"))
+            .unwrap();
+
+        let fork = ledger.fork(&[tx::dispute(1, 1)]);
+        // client 3 has a buffered withdrawal the fork may release
+        let mut clients: Vec<Client> = fork.accounts.keys().copied().collect();
+        clients.sort();
+        assert_eq!(clients, vec![1, 3]);
+        let history: Vec<_> = fork.history.keys().copied().collect();
+        assert_eq!(history, vec![1, 3]);
+        assert_eq!(fork.unprocessed.len(), 1);
+    }
+}
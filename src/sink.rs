@@ -0,0 +1,147 @@
+//! Pluggable report destinations.
+//!
+//! A [`ReportSink`] takes the finished ledger and writes its accounts somewhere, so
+//! embedders can send the report to a database, an http endpoint or memory instead of a
+//! file, and hand it to [`crate::command::Command::run`]. The command line picks one of
+//! the sinks here from its flags, csv on stdout by default.
+use crate::{account::Account, ledger::Ledger, report_files::ReportFiles};
+use anyhow::{Context, Result};
+use std::{
+    fs::{self, File},
+    io::{stdout, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+pub trait ReportSink: Send {
+    /// Writes out the accounts of `ledger`. It is called once at the end of a run, from a
+    /// thread that may block, and with `--follow` also every `--report-interval` from the
+    /// task applying transactions, so it should not block for long.
+    fn write(&mut self, ledger: &Ledger) -> Result<()>;
+}
+
+/// The report as csv on stdout
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CsvStdout;
+
+impl ReportSink for CsvStdout {
+    fn write(&mut self, ledger: &Ledger) -> Result<()> {
+        ledger.write_report(stdout())
+    }
+}
+
+/// The report as a csv file, replaced whole once the report is written
+#[derive(Debug, Clone)]
+pub struct CsvFile(pub PathBuf);
+
+impl ReportSink for CsvFile {
+    fn write(&mut self, ledger: &Ledger) -> Result<()> {
+        write_report_file(ledger, &self.0)
+    }
+}
+
+/// Writes the report next to `path` and renames it into place, so a reader of `path`
+/// never sees half a report and a failed run leaves the previous one alone.
+fn write_report_file(ledger: &Ledger, path: &Path) -> Result<()> {
+    let partial = path.with_extension("partial");
+    let written = (|| {
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        ledger.write_report(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        anyhow::Ok(())
+    })();
+    if let Err(err) = written {
+        let _ = fs::remove_file(&partial);
+        return Err(err.context(format!("writing report {}", partial.display())));
+    }
+    fs::rename(&partial, path).with_context(|| format!("replacing report {}", path.display()))
+}
+
+impl ReportSink for ReportFiles {
+    fn write(&mut self, ledger: &Ledger) -> Result<()> {
+        ReportFiles::write(self, ledger).map(drop)
+    }
+}
+
+/// Keeps the accounts of the latest report in memory. Clones share the accounts, so keep
+/// one to read them once the run is done.
+#[derive(Debug, Clone, Default)]
+pub struct InMemory(Arc<Mutex<Vec<Account>>>);
+
+impl InMemory {
+    /// The accounts of the latest report, in client id order.
+    pub fn accounts(&self) -> Vec<Account> {
+        self.0.lock().expect("report sink poisoned").clone()
+    }
+}
+
+impl ReportSink for InMemory {
+    fn write(&mut self, ledger: &Ledger) -> Result<()> {
+        let mut accounts: Vec<Account> = ledger.accounts().cloned().collect();
+        accounts.sort_unstable_by_key(|account| account.client_id);
+        *self.0.lock().expect("report sink poisoned") = accounts;
+        Ok(())
+    }
+}
+
+/// Streams the report into an `s3://` or `gs://` object with a multipart upload, so it is
+/// never staged on local disk. The object only appears once the whole report is written.
+#[cfg(feature = "aws")]
+#[derive(Debug, Clone)]
+pub struct ObjectUpload(pub String);
+
+#[cfg(feature = "aws")]
+impl ReportSink for ObjectUpload {
+    fn write(&mut self, ledger: &Ledger) -> Result<()> {
+        let runtime = tokio::runtime::Handle::current();
+        let mut upload = runtime.block_on(crate::s3::MultipartUpload::start(&self.0))?;
+        match ledger.write_report(&mut upload) {
+            Ok(()) => upload.finish(),
+            Err(err) => {
+                upload.abort();
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::ledger_with_accounts;
+
+    #[test]
+    fn test_report_file_is_replaced_whole() {
+        let dir = std::env::temp_dir().join(format!("mpe-{}-report-file", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("accounts.csv");
+        fs::write(&path, "previous report").unwrap();
+
+        let ledger = ledger_with_accounts(&[(1, "1.0"), (2, "2.0")]);
+        CsvFile(path.clone()).write(&ledger).unwrap();
+
+        let mut report = Vec::new();
+        ledger.write_report(&mut report).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), report);
+        assert!(!path.with_extension("partial").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_in_memory_sink_keeps_the_latest_report() {
+        let sink = InMemory::default();
+        let mut writer: Box<dyn ReportSink> = Box::new(sink.clone());
+        writer
+            .write(&ledger_with_accounts(&[(2, "2.0"), (1, "1.0")]))
+            .unwrap();
+        writer.write(&ledger_with_accounts(&[(3, "3.0")])).unwrap();
+
+        let clients: Vec<u16> = sink
+            .accounts()
+            .iter()
+            .map(|account| account.client_id)
+            .collect();
+        assert_eq!(clients, vec![3]);
+    }
+}
@@ -0,0 +1,148 @@
+//! SQLite input, behind the `sqlite` feature.
+//!
+//! An input given as `sqlite://path?table=name` reads the `type`, `client`, `tx` and
+//! `amount` columns of a table in ascending tx order, rows with the same tx in the order
+//! they were inserted, so transactions staged in a database need no csv export first.
+//! The table defaults to `transactions`, and rows are numbered from 1 in that order.
+//! Amounts may be stored as text, integers, reals or null; text is the only storage that
+//! keeps every decimal exactly.
+use crate::{
+    amount::AmountFormat,
+    reader::on_blocking_pool,
+    source::Row,
+    transaction::{Transaction, TransactionType},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use rusqlite::{types::ValueRef, Connection, OpenFlags};
+use tokio::sync::mpsc::Sender;
+
+const DEFAULT_TABLE: &str = "transactions";
+
+/// Splits `sqlite://path?table=name` into the database path and the table name.
+pub fn parse_sqlite_url(url: &str) -> Result<(String, String)> {
+    let rest = url
+        .strip_prefix("sqlite://")
+        .ok_or_else(|| anyhow!("not a sqlite url: {url}"))?;
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    if path.is_empty() {
+        bail!("sqlite url needs a database path: {url}");
+    }
+
+    let mut table = DEFAULT_TABLE.to_string();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        match pair.split_once('=') {
+            Some(("table", name)) => table = name.to_string(),
+            _ => bail!("unknown sqlite url parameter {pair}: {url}"),
+        }
+    }
+    // the name goes into the query as is, so it has to be a plain identifier
+    if table.is_empty() || !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        bail!("invalid sqlite table name {table}");
+    }
+    Ok((path.to_string(), table))
+}
+
+pub async fn sqlite_reader(url: String, amounts: AmountFormat, channel: Sender<Row>) -> Result<()> {
+    on_blocking_pool(move || {
+        let (path, table) = parse_sqlite_url(&url)?;
+        let connection = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("opening {path}"))?;
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT type, client, tx, amount FROM \"{table}\" ORDER BY tx, rowid"
+            ))
+            .with_context(|| format!("querying table {table}"))?;
+        let mut rows = statement.query([])?;
+
+        let mut number = 0;
+        while let Some(row) = rows.next()? {
+            number += 1;
+            let transaction = parse_row(row, &amounts).with_context(|| format!("row {number}"))?;
+            if channel.blocking_send((number, transaction)).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+fn parse_row(row: &rusqlite::Row, amounts: &AmountFormat) -> Result<Transaction> {
+    let tx_type: String = row.get(0)?;
+    let client: i64 = row.get(1)?;
+    let tx: i64 = row.get(2)?;
+    let amount = match row.get_ref(3)? {
+        ValueRef::Null => None,
+        ValueRef::Text(text) => Some(amounts.parse(std::str::from_utf8(text)?.trim())?),
+        ValueRef::Integer(value) => Some(amounts.parse(&value.to_string())?),
+        ValueRef::Real(value) => Some(amounts.parse(&value.to_string())?),
+        ValueRef::Blob(_) => bail!("amount must be text or a number, found a blob"),
+    };
+
+    Ok(Transaction {
+        tx_type: TransactionType::from_name(tx_type.trim()),
+        client: u16::try_from(client).with_context(|| format!("invalid client {client}"))?,
+        tx: u32::try_from(tx).with_context(|| format!("invalid tx {tx}"))?,
+        amount,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::channel;
+
+    #[test]
+    fn test_parse_sqlite_url() {
+        assert_eq!(
+            parse_sqlite_url("sqlite://staging.db?table=pending").unwrap(),
+            (String::from("staging.db"), String::from("pending"))
+        );
+        assert_eq!(
+            parse_sqlite_url("sqlite:///var/lib/staging.db").unwrap(),
+            (
+                String::from("/var/lib/staging.db"),
+                String::from("transactions")
+            )
+        );
+        assert!(parse_sqlite_url("sqlite://?table=pending").is_err());
+        assert!(parse_sqlite_url("sqlite://staging.db?table=a;b").is_err());
+        assert!(parse_sqlite_url("sqlite://staging.db?limit=5").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reads_table_in_tx_order() {
+        let path = std::env::temp_dir().join(format!("mpe-{}-staging.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let connection = Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE transactions (type TEXT, client INTEGER, tx INTEGER, amount);
+                 INSERT INTO transactions VALUES ('withdrawal', 1, 2, '0.5');
+                 INSERT INTO transactions VALUES ('deposit', 1, 1, 1.5);
+                 INSERT INTO transactions VALUES ('dispute', 1, 1, NULL);",
+            )
+            .unwrap();
+        drop(connection);
+
+        let (sender, mut receiver) = channel(8);
+        let url = format!("sqlite://{}", path.display());
+        sqlite_reader(url, AmountFormat::default(), sender)
+            .await
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut rows = vec![];
+        while let Some((number, transaction)) = receiver.recv().await {
+            rows.push((number, transaction.tx_type, transaction.tx));
+        }
+        assert_eq!(
+            rows,
+            vec![
+                (1, TransactionType::Deposit, 1),
+                (2, TransactionType::Dispute, 1),
+                (3, TransactionType::Withdrawal, 2),
+            ]
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use crate::{
+    amount::{self, Amount},
+    ledger::Ledger,
+    transaction::{TransactionStatus, TransactionType},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+/// What one input contributed to a run, so reconciliation breaks can be traced back to
+/// the upstream feed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SourceTotals {
+    pub name: String,
+    pub rows: u64,
+    pub rejected: u64,
+    /// Rows dropped as repeats by `--dedup-window`, not counted in `rows`
+    #[serde(default)]
+    pub duplicates: u64,
+    /// Rows of a type nothing handles, by type name, skipped by `--skip-unknown-types` and
+    /// not counted in `rows`
+    #[serde(default)]
+    pub unknown_types: BTreeMap<String, u64>,
+    /// Sum of the deposits applied and not charged back
+    pub deposited: Amount,
+    /// Sum of the withdrawals applied
+    pub withdrawn: Amount,
+}
+
+impl SourceTotals {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            rows: 0,
+            rejected: 0,
+            duplicates: 0,
+            unknown_types: BTreeMap::new(),
+            deposited: amount::zero(),
+            withdrawn: amount::zero(),
+        }
+    }
+}
+
+/// What a batch run did, for posting to ops once it finishes
+#[derive(Debug, Default, Clone, Serialize, PartialEq, Eq)]
+pub struct RunSummary {
+    pub rows: u64,
+    pub rejected: u64,
+    pub duplicates: u64,
+    pub unknown_types: u64,
+    pub chargebacks: usize,
+    pub locked_accounts: usize,
+    pub duration: Duration,
+    /// One entry per input, in the order the inputs were given
+    pub sources: Vec<SourceTotals>,
+}
+
+impl RunSummary {
+    /// Adds up the row counts of `sources` and fills in their amounts, the chargeback
+    /// count and the locked account count from the final ledger.
+    pub fn new(ledger: &Ledger, mut sources: Vec<SourceTotals>, duration: Duration) -> Self {
+        for transaction in ledger.history.values() {
+            let (Some(totals), Some(amount), TransactionStatus::Applied) = (
+                sources.get_mut(transaction.source),
+                transaction.amount,
+                transaction.status,
+            ) else {
+                continue;
+            };
+            match transaction.tx_type {
+                TransactionType::Deposit => totals.deposited += amount,
+                TransactionType::Withdrawal => totals.withdrawn += amount,
+                _ => {}
+            }
+        }
+
+        Self {
+            rows: sources.iter().map(|totals| totals.rows).sum(),
+            rejected: sources.iter().map(|totals| totals.rejected).sum(),
+            duplicates: sources.iter().map(|totals| totals.duplicates).sum(),
+            unknown_types: sources
+                .iter()
+                .flat_map(|totals| totals.unknown_types.values())
+                .sum(),
+            chargebacks: ledger
+                .history
+                .values()
+                .filter(|transaction| transaction.status == TransactionStatus::ChargedBack)
+                .count(),
+            locked_accounts: ledger
+                .accounts
+                .values()
+                .filter(|account| account.is_locked())
+                .count(),
+            duration,
+            sources,
+        }
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "batch finished in {:.1}s: {} rows processed, {} rejected, {} chargebacks, {} locked accounts",
+            self.duration.as_secs_f64(),
+            self.rows,
+            self.rejected,
+            self.chargebacks,
+            self.locked_accounts
+        )?;
+        if self.duplicates > 0 {
+            write!(f, ", {} duplicates dropped", self.duplicates)?;
+        }
+        if self.unknown_types > 0 {
+            write!(f, ", {} rows of unknown types skipped", self.unknown_types)?;
+        }
+
+        // a single input's totals are the run's totals
+        if self.sources.len() > 1 {
+            for totals in &self.sources {
+                write!(
+                    f,
+                    "\n{}: {} rows, {} rejected, {} deposited, {} withdrawn",
+                    totals.name, totals.rows, totals.rejected, totals.deposited, totals.withdrawn
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Posts the summary to a Slack or Teams incoming webhook, both of which accept a json
+/// body with a `text` field.
+#[cfg(feature = "webhook")]
+pub fn post_webhook(url: &str, summary: &RunSummary) -> anyhow::Result<()> {
+    ureq::post(url).send_json(serde_json::json!({ "text": summary.to_string() }))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_support::{amount, ledger_with_accounts, tx},
+        transaction::TransactionState,
+    };
+
+    #[test]
+    fn test_summary_counts() {
+        let mut ledger = ledger_with_accounts(&[(1, "1.0"), (2, "1.0")]);
+        ledger.process_transaction(tx::dispute(1, 1)).unwrap();
+        ledger.process_transaction(tx::chargeback(1, 1)).unwrap();
+
+        let mut totals = SourceTotals::new(String::from("in.csv"));
+        totals.rows = 5;
+        totals.rejected = 1;
+        totals.duplicates = 2;
+        totals.unknown_types.insert(String::from("depost"), 3);
+        let summary = RunSummary::new(&ledger, vec![totals], Duration::from_millis(1500));
+        assert_eq!(summary.chargebacks, 1);
+        assert_eq!(summary.locked_accounts, 1);
+        assert_eq!(
+            summary.to_string(),
+            "batch finished in 1.5s: 5 rows processed, 1 rejected, 1 chargebacks, 1 locked accounts, 2 duplicates dropped, 3 rows of unknown types skipped"
+        );
+    }
+
+    #[test]
+    fn test_summary_totals_per_source() {
+        let mut ledger = Ledger::new();
+        let rows = [
+            (0, tx::deposit(1, 1, "10.0")),
+            (1, tx::deposit(2, 2, "5.0")),
+            (1, tx::withdrawal(2, 3, "2.0")),
+            (0, tx::withdrawal(1, 4, "20.0")),
+        ];
+        let mut sources = vec![
+            SourceTotals::new(String::from("a.csv")),
+            SourceTotals::new(String::from("b.csv")),
+        ];
+        for (source, transaction) in rows {
+            let mut state = TransactionState::from(transaction);
+            state.source = source;
+            sources[source].rows += 1;
+            if ledger.process_transaction(state).is_err() {
+                sources[source].rejected += 1;
+            }
+        }
+
+        let summary = RunSummary::new(&ledger, sources, Duration::from_secs(1));
+        assert_eq!(summary.rows, 4);
+        assert_eq!(summary.rejected, 1);
+        assert_eq!(summary.sources[0].deposited, amount("10.0"));
+        assert_eq!(summary.sources[0].withdrawn, amount("0"));
+        assert_eq!(summary.sources[1].deposited, amount("5.0"));
+        assert_eq!(summary.sources[1].withdrawn, amount("2.0"));
+        assert!(summary
+            .to_string()
+            .ends_with("\nb.csv: 2 rows, 0 rejected, 5.0000 deposited, 2.0000 withdrawn"));
+    }
+}
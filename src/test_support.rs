@@ -0,0 +1,156 @@
+//! Builders and assertion helpers for writing tests against the engine.
+//!
+//! Enabled with the `test_support` feature so downstream crates can pull it in as a
+//! dev-dependency without shipping it in release builds.
+use crate::{
+    amount::Amount,
+    clock::ManualClock,
+    ledger::{Client, Ledger, TransactionId},
+    transaction::Transaction,
+};
+use std::{str::FromStr, time::UNIX_EPOCH};
+
+/// Parses a decimal literal such as `"10.00"`, panicking on malformed input.
+pub fn amount(value: &str) -> Amount {
+    Amount::from_str(value).unwrap_or_else(|_| panic!("invalid amount in test: {value}"))
+}
+
+/// Shorthand constructors for each transaction type, in the same argument order as the
+/// csv columns: client, tx, amount.
+pub mod tx {
+    use super::amount;
+    use crate::{
+        ledger::{Client, TransactionId},
+        transaction::{Transaction, TransactionType},
+    };
+
+    fn build(
+        tx_type: TransactionType,
+        client: Client,
+        tx: TransactionId,
+        value: Option<&str>,
+    ) -> Transaction {
+        Transaction {
+            tx_type,
+            client,
+            tx,
+            amount: value.map(amount),
+        }
+    }
+
+    pub fn deposit(client: Client, tx: TransactionId, value: &str) -> Transaction {
+        build(TransactionType::Deposit, client, tx, Some(value))
+    }
+
+    pub fn withdrawal(client: Client, tx: TransactionId, value: &str) -> Transaction {
+        build(TransactionType::Withdrawal, client, tx, Some(value))
+    }
+
+    pub fn dispute(client: Client, tx: TransactionId) -> Transaction {
+        build(TransactionType::Dispute, client, tx, None)
+    }
+
+    pub fn resolve(client: Client, tx: TransactionId) -> Transaction {
+        build(TransactionType::Resolve, client, tx, None)
+    }
+
+    pub fn chargeback(client: Client, tx: TransactionId) -> Transaction {
+        build(TransactionType::Chargeback, client, tx, None)
+    }
+}
+
+/// Builds a ledger holding one account per `(client, amount)` pair, funded by deposits
+/// with transaction ids `1..=accounts.len()`. Its clock is stopped at the Unix epoch, so
+/// nothing in a test depends on when it runs.
+pub fn ledger_with_accounts(accounts: &[(Client, &str)]) -> Ledger {
+    let mut ledger = Ledger::builder().clock(ManualClock::at(UNIX_EPOCH)).build();
+    for (id, (client, value)) in accounts.iter().enumerate() {
+        ledger
+            .process_transaction(tx::deposit(*client, id as TransactionId + 1, value))
+            .expect("failed to seed ledger");
+    }
+    ledger
+}
+
+/// Feeds every transaction into the ledger, panicking on the first rejection.
+pub fn apply_all(ledger: &mut Ledger, transactions: impl IntoIterator<Item = Transaction>) {
+    for transaction in transactions {
+        let tx = transaction.tx;
+        if let Err(err) = ledger.process_transaction(transaction) {
+            panic!("transaction {tx} was rejected: {err}");
+        }
+    }
+}
+
+/// Asserts the balances of a client's account.
+///
+/// ```ignore
+/// assert_balances!(ledger, 1, available: "5.00", held: "5.00", total: "10.00");
+/// assert_balances!(ledger, 1, available: "0", held: "0", total: "0", locked: true);
+/// ```
+#[macro_export]
+macro_rules! assert_balances {
+    ($ledger:expr, $client:expr, available: $available:expr, held: $held:expr, total: $total:expr $(,)?) => {{
+        let account = $ledger
+            .account($client)
+            .unwrap_or_else(|| panic!("no account for client {}", $client));
+        assert_eq!(
+            account.available_funds,
+            $crate::test_support::amount($available),
+            "available funds of client {}",
+            $client
+        );
+        assert_eq!(
+            account.held_funds,
+            $crate::test_support::amount($held),
+            "held funds of client {}",
+            $client
+        );
+        assert_eq!(
+            account.total_funds,
+            $crate::test_support::amount($total),
+            "total funds of client {}",
+            $client
+        );
+    }};
+    ($ledger:expr, $client:expr, available: $available:expr, held: $held:expr, total: $total:expr, locked: $locked:expr $(,)?) => {{
+        $crate::assert_balances!($ledger, $client, available: $available, held: $held, total: $total);
+        assert_eq!(
+            $ledger
+                .account($client)
+                .is_some_and(|account| account.is_locked()),
+            $locked,
+            "locked flag of client {}",
+            $client
+        );
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ledger_with_accounts() {
+        let ledger = ledger_with_accounts(&[(1, "10.00"), (2, "2.5")]);
+        assert_balances!(ledger, 1, available: "10", held: "0", total: "10");
+        assert_balances!(ledger, 2, available: "2.5", held: "0", total: "2.5");
+    }
+
+    #[test]
+    fn test_dispute_chargeback_with_builders() {
+        let mut ledger = ledger_with_accounts(&[(1, "10.00")]);
+        apply_all(&mut ledger, [tx::deposit(1, 2, "5.00"), tx::dispute(1, 2)]);
+        assert_balances!(ledger, 1, available: "10", held: "5", total: "15");
+
+        apply_all(&mut ledger, [tx::chargeback(1, 2)]);
+        assert_balances!(ledger, 1, available: "10", held: "0", total: "10", locked: true);
+    }
+
+    #[test]
+    #[should_panic(expected = "transaction 2 was rejected")]
+    fn test_apply_all_panics_on_rejection() {
+        let mut ledger = ledger_with_accounts(&[(1, "1.00")]);
+        apply_all(&mut ledger, [tx::withdrawal(1, 2, "2.00")]);
+    }
+}
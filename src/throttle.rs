@@ -0,0 +1,54 @@
+//! Throttling how fast rows reach the ledger.
+//!
+//! `--max-tps` paces the merged rows of every input to at most that many per second
+//! before they are batched, so a replay does not hammer whatever the run feeds
+//! downstream, such as a webhook or a metrics sink. Rows are released on a fixed schedule
+//! from the first one, so a stall in the inputs is not made up for with a burst, and the
+//! inputs are only read as fast as the schedule lets rows through.
+use std::time::Duration;
+use tokio::{
+    sync::mpsc::{Receiver, Sender},
+    time::{sleep_until, Instant},
+};
+
+/// Passes the rows of `input` on to `output`, no more than `max_tps` a second.
+pub async fn throttle<T>(mut input: Receiver<T>, output: Sender<T>, max_tps: u32) {
+    let interval = Duration::from_secs(1) / max_tps.max(1);
+    let mut next = Instant::now();
+    while let Some(row) = input.recv().await {
+        // a row that arrives late goes straight on, and the schedule starts over from it
+        next = next.max(Instant::now());
+        sleep_until(next).await;
+        next += interval;
+        if output.send(row).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::channel;
+
+    #[tokio::test]
+    async fn test_throttle_paces_rows() {
+        let (sender, receiver) = channel(32);
+        for row in 0..20 {
+            sender.send(row).await.unwrap();
+        }
+        drop(sender);
+
+        let (output, mut rows) = channel(32);
+        let started = Instant::now();
+        tokio::spawn(throttle(receiver, output, 200));
+        let mut received = vec![];
+        while let Some(row) = rows.recv().await {
+            received.push(row);
+        }
+
+        assert_eq!(received, (0..20).collect::<Vec<_>>());
+        // the first row goes at once and each of the other 19 waits 5ms
+        assert!(started.elapsed() >= Duration::from_millis(95));
+    }
+}
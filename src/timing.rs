@@ -0,0 +1,85 @@
+//! Stage timings for `--timing`.
+//!
+//! Validation and application happen in one pass inside [`crate::ledger::Ledger`], so they
+//! are reported together as `apply`. The queue-wait histogram records how long the ledger
+//! sat waiting for the next batch of rows: mostly short waits mean the ledger is the bottleneck,
+//! mostly long ones mean reading and parsing the input is. Percentiles of how long rows
+//! took from being read to being processed come from [`crate::latency`].
+use crate::latency::LatencySummary;
+use serde::Serialize;
+use std::{future::Future, time::Duration, time::Instant};
+
+/// Number of power-of-two buckets, the last one catching everything from ~0.5s up
+const BUCKETS: usize = 20;
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Histogram {
+    /// Exclusive upper bound of each bucket in microseconds, `None` for the last one
+    pub upper_bounds_us: Vec<Option<u64>>,
+    pub counts: Vec<u64>,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            upper_bounds_us: (0..BUCKETS)
+                .map(|i| (i + 1 < BUCKETS).then(|| 1 << i))
+                .collect(),
+            counts: vec![0; BUCKETS],
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, wait: Duration) {
+        let micros = wait.as_micros();
+        let bucket = self
+            .upper_bounds_us
+            .iter()
+            .position(|bound| bound.is_none_or(|bound| micros < bound as u128))
+            .unwrap_or(BUCKETS - 1);
+        self.counts[bucket] += 1;
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Timing {
+    /// Wall time of the slowest input reader, including time blocked on a full queue
+    pub read_parse_secs: f64,
+    /// Time the ledger spent validating and applying transactions
+    pub apply_secs: f64,
+    /// Time spent writing the report
+    pub write_secs: f64,
+    pub total_secs: f64,
+    pub queue_wait: Histogram,
+    /// Latency percentiles in microseconds, absent when no row was processed
+    pub latency_us: Option<LatencySummary>,
+}
+
+/// Runs `future`, also returning how long it took.
+pub async fn timed<F: Future>(future: F) -> (F::Output, Duration) {
+    let started = Instant::now();
+    let output = future.await;
+    (output, started.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut histogram = Histogram::default();
+        histogram.record(Duration::from_nanos(500));
+        histogram.record(Duration::from_micros(3));
+        histogram.record(Duration::from_micros(4));
+        histogram.record(Duration::from_secs(60));
+
+        assert_eq!(histogram.counts[0], 1);
+        assert_eq!(histogram.counts[2], 1);
+        assert_eq!(histogram.counts[3], 1);
+        assert_eq!(histogram.counts[BUCKETS - 1], 1);
+        assert_eq!(histogram.upper_bounds_us[3], Some(8));
+        assert_eq!(histogram.upper_bounds_us[BUCKETS - 1], None);
+    }
+}
@@ -1,8 +1,8 @@
-use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
+use crate::{amount::Amount, source::SourceId};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "snake_case")]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum TransactionType {
     ///A deposit is a credit to the client's asset account, meaning it should increase the available and
     ///total funds of the client account
@@ -29,6 +29,53 @@ pub enum TransactionType {
     ///decrease by the amount no longer disputed, their available funds should increase by the amount
     ///no longer disputed, and their total funds should remain the same.
     Resolve,
+
+    ///A product-specific type such as `bonus` or `adjustment`, applied by the
+    ///[`crate::handlers::TransactionHandler`] registered for its name on the ledger. Types
+    ///without a handler are rejected.
+    Custom(String),
+}
+
+impl TransactionType {
+    /// The type called `name` in the input, a [`TransactionType::Custom`] one for names
+    /// the engine does not know itself.
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "deposit" => TransactionType::Deposit,
+            "withdrawal" => TransactionType::Withdrawal,
+            "dispute" => TransactionType::Dispute,
+            "resolve" => TransactionType::Resolve,
+            "chargeback" => TransactionType::Chargeback,
+            other => TransactionType::Custom(other.to_string()),
+        }
+    }
+}
+
+impl Serialize for TransactionType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(Self::from_name(&name))
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tx_type = match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Custom(name) => name,
+        };
+        f.write_str(tx_type)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,8 +86,8 @@ pub struct Transaction {
     pub tx: u32,
     // precision of up to 4 decimal places, e.g 0.1234
     //#[serde(with = "rust_decimal::serde::arbitrary_precision")]
-    #[serde(with = "rust_decimal::serde::float_option")]
-    pub amount: Option<Decimal>,
+    #[serde(with = "crate::amount::serde_option")]
+    pub amount: Option<Amount>,
 }
 
 impl From<Transaction> for TransactionState {
@@ -51,15 +98,52 @@ impl From<Transaction> for TransactionState {
             tx: value.tx,
             amount: value.amount,
             disputed: false,
+            status: TransactionStatus::Pending,
+            source: 0,
+            line: 0,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+impl From<&TransactionState> for Transaction {
+    fn from(value: &TransactionState) -> Self {
+        Self {
+            tx_type: value.tx_type.clone(),
+            client: value.client,
+            tx: value.tx,
+            amount: value.amount,
+        }
+    }
+}
+
+/// Outcome of a deposit or withdrawal as recorded in the ledger history
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    ///Received but not yet applied to an account, e.g. still waiting on an earlier tx id
+    Pending,
+
+    ///Applied to the client's account
+    Applied,
+
+    ///Could not be applied, the account balances were left untouched
+    Rejected,
+
+    ///Applied and later reversed by a chargeback
+    ChargedBack,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionState {
     pub tx_type: TransactionType,
     pub client: u16,
     pub tx: u32,
-    pub amount: Option<Decimal>,
+    pub amount: Option<Amount>,
     pub disputed: bool,
+    pub status: TransactionStatus,
+    /// Index of the input the transaction was read from, in the order the inputs were given
+    pub source: SourceId,
+    /// Line of its input the transaction was read from, 0 when it did not come from a run
+    #[serde(default)]
+    pub line: u64,
 }
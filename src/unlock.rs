@@ -0,0 +1,153 @@
+//! Unlocking frozen accounts.
+//!
+//! An account frozen by a chargeback or a custom transaction only goes back to active
+//! through [`Ledger::unlock`], which refuses while any of its deposits are still under
+//! dispute, while any of its balances are negative, or without an approval, and lists
+//! every condition that is not met. Disputes cannot run on a frozen account, so settling
+//! them first means putting the account under review with [`Ledger::transition`], where
+//! resolves and chargebacks are applied as usual.
+use crate::{
+    account::AccountStatus,
+    amount,
+    ledger::{Client, Ledger, TransactionId},
+};
+use std::fmt;
+use thiserror::Error;
+
+/// A precondition of [`Ledger::unlock`] that does not hold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnlockCondition {
+    /// These transactions of the client are still under dispute
+    OpenDisputes(Vec<TransactionId>),
+    NegativeBalance,
+    NoApproval,
+}
+
+impl fmt::Display for UnlockCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnlockCondition::OpenDisputes(txs) => {
+                let txs: Vec<String> = txs.iter().map(ToString::to_string).collect();
+                write!(f, "transactions {} are still disputed", txs.join(", "))
+            }
+            UnlockCondition::NegativeBalance => f.write_str("a balance is negative"),
+            UnlockCondition::NoApproval => f.write_str("no approval was given"),
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum UnlockError {
+    #[error("Client Account is missing: {0}")]
+    AccountMissing(Client),
+
+    #[error("Account {0} is not locked")]
+    NotLocked(Client),
+
+    #[error("Account {0} cannot be unlocked: {}", list(.1))]
+    Refused(Client, Vec<UnlockCondition>),
+}
+
+fn list(conditions: &[UnlockCondition]) -> String {
+    let conditions: Vec<String> = conditions.iter().map(ToString::to_string).collect();
+    conditions.join("; ")
+}
+
+impl Ledger {
+    /// Brings a frozen account, or one put under review after it froze, back to active
+    /// once its disputes are settled, its balances are not negative and `approval`
+    /// names who signed off on it.
+    pub fn unlock(&mut self, client: Client, approval: Option<&str>) -> Result<(), UnlockError> {
+        let account = self
+            .accounts
+            .get(&client)
+            .ok_or(UnlockError::AccountMissing(client))?;
+        if account.locked_reason.is_none() && !account.is_locked() {
+            return Err(UnlockError::NotLocked(client));
+        }
+
+        let mut unmet = Vec::new();
+        let disputed: Vec<TransactionId> = self
+            .history
+            .values()
+            .filter(|transaction| transaction.client == client && transaction.disputed)
+            .map(|transaction| transaction.tx)
+            .collect();
+        if !disputed.is_empty() {
+            unmet.push(UnlockCondition::OpenDisputes(disputed));
+        }
+        let zero = amount::zero();
+        if account.available_funds < zero || account.held_funds < zero || account.total_funds < zero
+        {
+            unmet.push(UnlockCondition::NegativeBalance);
+        }
+        if approval.map_or(true, |approval| approval.trim().is_empty()) {
+            unmet.push(UnlockCondition::NoApproval);
+        }
+        if !unmet.is_empty() {
+            return Err(UnlockError::Refused(client, unmet));
+        }
+
+        if let Some(account) = self.accounts.get_mut(&client) {
+            account.status = AccountStatus::Active;
+            account.clear_lock();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::LockReason,
+        test_support::{ledger_with_accounts, tx},
+    };
+
+    #[test]
+    fn test_unlock_lists_unmet_conditions() {
+        let mut ledger = ledger_with_accounts(&[(1, "10.0"), (1, "5.0"), (2, "1.0")]);
+        for transaction in [tx::dispute(1, 1), tx::dispute(1, 2), tx::chargeback(1, 1)] {
+            ledger.process_transaction(transaction).unwrap();
+        }
+
+        assert_eq!(
+            ledger.unlock(2, Some("ops")),
+            Err(UnlockError::NotLocked(2))
+        );
+        let refused = ledger.unlock(1, None).unwrap_err();
+        assert_eq!(
+            refused,
+            UnlockError::Refused(
+                1,
+                vec![
+                    UnlockCondition::OpenDisputes(vec![2]),
+                    UnlockCondition::NoApproval
+                ]
+            )
+        );
+        assert_eq!(
+            refused.to_string(),
+            "Account 1 cannot be unlocked: transactions 2 are still disputed; no approval was given"
+        );
+
+        // going around the unlock is refused
+        ledger.transition(1, AccountStatus::UnderReview).unwrap();
+        assert!(ledger.transition(1, AccountStatus::Active).is_err());
+        assert!(ledger.transition(9, AccountStatus::UnderReview).is_err());
+
+        ledger.process_transaction(tx::resolve(1, 2)).unwrap();
+        assert_eq!(
+            ledger.account(1).unwrap().locked_reason,
+            Some(LockReason::Chargeback)
+        );
+        ledger.unlock(1, Some("ops")).unwrap();
+
+        let account = ledger.account(1).unwrap();
+        assert_eq!(account.status, AccountStatus::Active);
+        assert_eq!(account.locked_by_tx, None);
+        ledger
+            .process_transaction(tx::deposit(1, 4, "1.0"))
+            .unwrap();
+    }
+}
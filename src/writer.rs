@@ -1,18 +1,83 @@
-use crate::{account::Account, ledger::Ledger};
+use crate::{account::Account, columns, ledger::Ledger};
 use anyhow::Result;
 use csv::Writer;
-use std::io::stdout;
+use std::io::Write;
 
-pub fn output_report(ledger: &Ledger) -> Result<()> {
-    let mut wtr = Writer::from_writer(stdout());
+impl Ledger {
+    /// Writes the account report as csv to `out`, in the same format the command line
+    /// prints it, followed by any columns added with
+    /// [`crate::ledger::LedgerBuilder::column`].
+    pub fn write_report<W: Write>(&self, out: W) -> Result<()> {
+        let mut wtr = Writer::from_writer(out);
 
-    let accounts: Vec<&Account> = ledger.accounts.values().collect();
+        let mut accounts: Vec<&Account> = self.accounts.values().collect();
+        if self.sort_report {
+            accounts.sort_unstable_by_key(|account| account.client_id);
+        }
 
-    for account in accounts {
-        wtr.serialize(account)?;
+        if self.columns.is_empty() {
+            for account in accounts {
+                wtr.serialize(account)?;
+            }
+        } else {
+            // csv can't serialize a struct followed by more fields, so the rows are
+            // written out field by field
+            let mut header = vec![
+                "client_id",
+                "available_funds",
+                "held_funds",
+                "total_funds",
+                "locked",
+                "status",
+                "sequence",
+            ];
+            header.extend(self.columns.iter().map(|(name, _)| name.as_str()));
+            wtr.write_record(header)?;
+
+            for account in accounts {
+                let mut record = vec![
+                    account.client_id.to_string(),
+                    account.available_funds.to_string(),
+                    account.held_funds.to_string(),
+                    account.total_funds.to_string(),
+                    account.is_locked().to_string(),
+                    account.status.to_string(),
+                    account.sequence.to_string(),
+                ];
+                record.extend(
+                    self.columns
+                        .iter()
+                        .map(|(_, column)| columns::field(column(account, &self.history))),
+                );
+                wtr.write_record(record)?;
+            }
+        }
+
+        wtr.flush()?;
+
+        Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::ledger_with_accounts;
 
-    wtr.flush()?;
+    #[test]
+    fn test_sorted_report_is_in_client_order() {
+        let accounts: Vec<(u16, &str)> = (1..=50).rev().map(|client| (client, "1.0")).collect();
+        let mut ledger = ledger_with_accounts(&accounts);
+        ledger.sort_report = true;
 
-    Ok(())
+        let mut report = Vec::new();
+        ledger.write_report(&mut report).unwrap();
+        let clients: Vec<u16> = String::from_utf8(report)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|row| row.split(',').next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(clients, (1..=50).collect::<Vec<_>>());
+    }
 }
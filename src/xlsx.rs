@@ -0,0 +1,94 @@
+//! Excel workbook input, behind the `xlsx` feature.
+//!
+//! One sheet of the workbook is read as the transaction table, the first one unless a
+//! sheet is named. Rows are taken like csv rows with the same layout: a header row naming
+//! the `type`, `client`, `tx` and `amount` columns unless there is none, or a column
+//! mapping by position. Cells are read as the text the sheet shows, so a number typed as
+//! `1.1` comes out as `1.1` rather than its nearest float, and rows are numbered as in
+//! the spreadsheet. Blank rows are skipped.
+use crate::{
+    reader::{on_blocking_pool, parse_record},
+    source::{CsvLayout, Row},
+};
+use anyhow::{anyhow, Context, Result};
+use calamine::{open_workbook, Data, Reader, Xlsx};
+use csv::StringRecord;
+use std::path::PathBuf;
+use tokio::sync::mpsc::Sender;
+
+pub async fn xlsx_reader(
+    path: &PathBuf,
+    sheet: Option<String>,
+    layout: CsvLayout,
+    channel: Sender<Row>,
+) -> Result<()> {
+    let path = path.clone();
+    on_blocking_pool(move || {
+        let mut workbook: Xlsx<_> =
+            open_workbook(&path).with_context(|| format!("opening workbook {}", path.display()))?;
+        let range = match &sheet {
+            Some(name) => workbook
+                .worksheet_range(name)
+                .with_context(|| format!("reading sheet {name}"))?,
+            None => workbook
+                .worksheet_range_at(0)
+                .ok_or_else(|| anyhow!("workbook {} has no sheets", path.display()))?
+                .context("reading the first sheet")?,
+        };
+
+        // cells are counted from the top left of the used range, not from A1
+        let first_row = range.start().map_or(0, |(row, _)| row as u64);
+        let mut rows = range
+            .rows()
+            .enumerate()
+            .map(|(index, cells)| (first_row + index as u64 + 1, record(cells)));
+
+        // mapped columns are found by position, whatever the header row calls them
+        let header_row = if layout.headers { rows.next() } else { None };
+        let headers = match header_row {
+            Some((_, headers)) if layout.columns.is_none() => headers,
+            _ => StringRecord::from(vec!["type", "client", "tx", "amount"]),
+        };
+
+        for (line, record) in rows {
+            if record.iter().all(str::is_empty) {
+                continue;
+            }
+            let transaction =
+                parse_record(record, &headers, &layout).with_context(|| format!("row {line}"))?;
+            if channel.blocking_send((line, transaction)).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// The trimmed text of each cell in a row, with empty cells as empty fields.
+fn record(cells: &[Data]) -> StringRecord {
+    cells
+        .iter()
+        .map(|cell| cell.to_string().trim().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_reads_cells_as_shown() {
+        let cells = [
+            Data::String(String::from(" deposit ")),
+            Data::Float(1.0),
+            Data::Int(7),
+            Data::Float(1.1),
+            Data::Empty,
+        ];
+        assert_eq!(
+            record(&cells),
+            StringRecord::from(vec!["deposit", "1", "7", "1.1", ""])
+        );
+    }
+}
@@ -0,0 +1,89 @@
+//! End-to-end conformance suite.
+//!
+//! Each directory under `tests/conformance/<version>` is one case: the engine reads
+//! `input.csv`, and must produce the report in `expected.csv` (rows sorted by client) and
+//! refuse exactly the rows listed in `rejected.csv`. The fixtures only depend on the csv
+//! formats, so alternative implementations can run them as well. Run with
+//! `cargo test --features conformance`.
+#![cfg(feature = "conformance")]
+
+use csv::{ReaderBuilder, Trim, Writer};
+use mini_payments_engine::prelude::{Ledger, Transaction};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const VERSION: &str = "v1";
+
+struct Outcome {
+    report: String,
+    rejected: String,
+}
+
+fn run_case(dir: &Path) -> Outcome {
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(true)
+        .trim(Trim::All)
+        .flexible(true)
+        .from_path(dir.join("input.csv"))
+        .unwrap();
+
+    let mut ledger = Ledger::new();
+    let mut rejected = Writer::from_writer(vec![]);
+    rejected.write_record(["type", "client", "tx"]).unwrap();
+
+    for result in rdr.deserialize() {
+        let transaction: Transaction = result.unwrap();
+        // a refusal can be of a buffered row this one released, so the refused row is
+        // the one written
+        for (refused, _) in ledger.process(transaction) {
+            rejected
+                .serialize((refused.tx_type, refused.client, refused.tx))
+                .unwrap();
+        }
+    }
+
+    let mut accounts: Vec<_> = ledger.accounts().collect();
+    accounts.sort_by_key(|account| account.client_id);
+
+    let mut report = Writer::from_writer(vec![]);
+    for account in accounts {
+        report.serialize(account).unwrap();
+    }
+
+    Outcome {
+        report: String::from_utf8(report.into_inner().unwrap()).unwrap(),
+        rejected: String::from_utf8(rejected.into_inner().unwrap()).unwrap(),
+    }
+}
+
+fn cases() -> Vec<PathBuf> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/conformance")
+        .join(VERSION);
+    let mut cases: Vec<PathBuf> = fs::read_dir(root)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    cases.sort();
+    cases
+}
+
+#[test]
+fn test_conformance() {
+    let cases = cases();
+    assert!(!cases.is_empty(), "no conformance cases found");
+
+    for case in cases {
+        let name = case.file_name().unwrap().to_string_lossy().into_owned();
+        let outcome = run_case(&case);
+
+        let expected = fs::read_to_string(case.join("expected.csv")).unwrap();
+        assert_eq!(outcome.report, expected, "report of case {name}");
+
+        let rejected = fs::read_to_string(case.join("rejected.csv")).unwrap();
+        assert_eq!(outcome.rejected, rejected, "rejected rows of case {name}");
+    }
+}